@@ -2,6 +2,7 @@ use psst_core::{
     audio_normalize::NormalizationLevel,
     audio_output::AudioOutput,
     audio_player::{PlaybackConfig, PlaybackItem, Player, PlayerCommand, PlayerEvent},
+    audio_sink::{AudioSink, NullSink, PcmSink, WavSink},
     cache::{Cache, CacheHandle},
     cdn::{Cdn, CdnHandle},
     connection::Credentials,
@@ -18,6 +19,7 @@ fn main() {
     let track_id = args
         .get(1)
         .expect("Expected <track_id> in the first parameter");
+    let sink = open_sink(args.get(2).map(String::as_str)).unwrap();
     let login_creds = Credentials::from_username_and_password(
         env::var("SPOTIFY_USERNAME").unwrap(),
         env::var("SPOTIFY_PASSWORD").unwrap(),
@@ -27,17 +29,65 @@ fn main() {
         proxy_url: None,
     });
 
-    start(track_id, session).unwrap();
+    start(track_id, session, sink).unwrap();
 }
 
-fn start(track_id: &str, session: SessionService) -> Result<(), Error> {
+const SINK_SPECS: &str = "device, device:<id>, pipe, pipe:<path>, wav:<path>, null, null:unlimited";
+
+/// Open the audio sink described by the optional second parameter:
+///  - `device` or nothing, for the default audio device,
+///  - `device:<id>`, for an audio device from `AudioOutput::devices`,
+///  - `pipe`, for raw PCM written to stdout,
+///  - `pipe:<path>`, for raw PCM written to a file or a named pipe,
+///  - `wav:<path>`, for a WAV file,
+///  - `null` or `null:unlimited`, to discard the audio in real time or as fast as
+///    possible.
+fn open_sink(spec: Option<&str>) -> Result<Box<dyn AudioSink>, Error> {
+    let (kind, param) = match spec {
+        Some(spec) => match spec.split_once(':') {
+            Some((kind, param)) => (kind, Some(param)),
+            None => (spec, None),
+        },
+        None => ("device", None),
+    };
+    let sink: Box<dyn AudioSink> = match (kind, param) {
+        ("device", device_id) => Box::new(AudioOutput::open_with_device(
+            device_id.map(str::to_string),
+        )?),
+        ("pipe", None) => Box::new(PcmSink::stdout()),
+        ("pipe", Some(path)) => Box::new(PcmSink::pipe(PathBuf::from(path))),
+        ("wav", Some(path)) => Box::new(WavSink::new(PathBuf::from(path))),
+        ("null", None) => Box::new(NullSink::real_time()),
+        ("null", Some("unlimited")) => Box::new(NullSink::unlimited()),
+        _ => {
+            let message = format!(
+                "Unknown audio sink {:?}, expected one of: {}",
+                spec.unwrap_or_default(),
+                SINK_SPECS
+            );
+            return Err(Error::AudioOutputError(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                message,
+            ))));
+        }
+    };
+    Ok(sink)
+}
+
+fn start(track_id: &str, session: SessionService, sink: Box<dyn AudioSink>) -> Result<(), Error> {
     let cdn = Cdn::new(session.clone(), None)?;
     let cache = Cache::new(PathBuf::from("cache"))?;
-    let item_id = ItemId::from_base62(track_id, ItemIdType::Track).unwrap();
+    // Accept either a base62 track ID, or a `spotify:track:` or `spotify:episode:` URI.
+    let item_id = if track_id.contains(':') {
+        ItemId::from_uri(track_id).unwrap()
+    } else {
+        ItemId::from_base62(track_id, ItemIdType::Track).unwrap()
+    };
     play_item(
         session,
         cdn,
         cache,
+        sink,
         PlaybackItem {
             item_id,
             norm_level: NormalizationLevel::Track,
@@ -49,9 +99,9 @@ fn play_item(
     session: SessionService,
     cdn: CdnHandle,
     cache: CacheHandle,
+    output: Box<dyn AudioSink>,
     item: PlaybackItem,
 ) -> Result<(), Error> {
-    let output = AudioOutput::open()?;
     let output_remote = output.remote();
     let config = PlaybackConfig::default();
 
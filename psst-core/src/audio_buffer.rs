@@ -0,0 +1,256 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::audio_output::AudioSample;
+
+/// Create a lock-free ring buffer of audio samples, with a single producer and a
+/// single consumer.  Capacity is rounded up to the next power of two.
+pub fn audio_buffer(capacity: usize) -> (AudioBufferProducer, AudioBufferConsumer) {
+    let capacity = capacity.next_power_of_two();
+    let shared = Arc::new(Shared {
+        samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+        written: AtomicU64::new(0),
+        read: AtomicU64::new(0),
+        discard_to: AtomicU64::new(0),
+        has_discard: AtomicBool::new(false),
+        is_producing: AtomicBool::new(false),
+    });
+    let producer = AudioBufferProducer {
+        shared: shared.clone(),
+        written: 0,
+    };
+    let consumer = AudioBufferConsumer {
+        shared,
+        read: 0,
+        discarded_to: 0,
+    };
+    (producer, consumer)
+}
+
+struct Shared {
+    // Samples are stored as bits of `f32`, so we can access them atomically.
+    samples: Box<[AtomicU32]>,
+    // Total count of samples published by the producer, and consumed by the
+    // consumer.  Positions in the buffer are these counts modulo the capacity.
+    written: AtomicU64,
+    read: AtomicU64,
+    // Position the consumer should skip to, valid if `has_discard` is set.
+    discard_to: AtomicU64,
+    has_discard: AtomicBool,
+    // Set while the producer is expected to produce more samples.
+    is_producing: AtomicBool,
+}
+
+impl Shared {
+    fn capacity(&self) -> u64 {
+        self.samples.len() as u64
+    }
+
+    fn slot(&self, position: u64) -> &AtomicU32 {
+        &self.samples[(position % self.capacity()) as usize]
+    }
+}
+
+pub struct AudioBufferProducer {
+    shared: Arc<Shared>,
+    // Count of written samples, including the ones not published yet.
+    written: u64,
+}
+
+impl AudioBufferProducer {
+    /// Count of samples that can be pushed before the buffer is full.
+    pub fn free(&self) -> usize {
+        let read = self.shared.read.load(Ordering::Acquire);
+        (self.shared.capacity() - (self.written - read)) as usize
+    }
+
+    /// Total count of samples pushed so far.
+    pub fn position(&self) -> u64 {
+        self.written
+    }
+
+    /// Write a sample into the buffer.  It does not become visible to the
+    /// consumer until `publish` is called.  Returns false if the buffer is full.
+    pub fn push(&mut self, sample: AudioSample) -> bool {
+        if self.free() == 0 {
+            return false;
+        }
+        self.shared
+            .slot(self.written)
+            .store(sample.to_bits(), Ordering::Relaxed);
+        self.written += 1;
+        true
+    }
+
+    pub fn publish(&self) {
+        self.shared.written.store(self.written, Ordering::Release);
+    }
+
+    /// Make the consumer skip all samples pushed so far.
+    pub fn discard(&mut self) {
+        self.publish();
+        self.shared
+            .discard_to
+            .store(self.written, Ordering::Relaxed);
+        self.shared.has_discard.store(true, Ordering::Release);
+    }
+
+    pub fn set_producing(&self, is_producing: bool) {
+        self.shared
+            .is_producing
+            .store(is_producing, Ordering::Release);
+    }
+}
+
+pub struct AudioBufferConsumer {
+    shared: Arc<Shared>,
+    read: u64,
+    discarded_to: u64,
+}
+
+impl AudioBufferConsumer {
+    pub fn pop(&mut self) -> Option<AudioSample> {
+        self.apply_discard();
+        if self.read == self.shared.written.load(Ordering::Acquire) {
+            return None;
+        }
+        let sample = self.shared.slot(self.read).load(Ordering::Relaxed);
+        self.read += 1;
+        self.shared.read.store(self.read, Ordering::Release);
+        Some(AudioSample::from_bits(sample))
+    }
+
+    /// Total count of samples consumed, or skipped, so far.
+    pub fn position(&self) -> u64 {
+        self.read
+    }
+
+    /// Position of the last discard made by the producer.  Samples before it
+    /// have been skipped.
+    pub fn discarded_to(&self) -> u64 {
+        self.discarded_to
+    }
+
+    /// Returns true if the producer is expected to push more samples, meaning
+    /// an empty buffer is an underrun, rather than the end of the stream.
+    pub fn is_producing(&self) -> bool {
+        self.shared.is_producing.load(Ordering::Acquire)
+    }
+
+    fn apply_discard(&mut self) {
+        if self.shared.has_discard.swap(false, Ordering::Acquire) {
+            self.discarded_to = self.shared.discard_to.load(Ordering::Relaxed);
+            self.read = self.discarded_to;
+            self.shared.read.store(self.read, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn capacity_rounds_up_to_power_of_two() {
+        let (producer, _consumer) = audio_buffer(3);
+        assert_eq!(producer.free(), 4);
+    }
+
+    #[test]
+    fn full_and_empty_buffer() {
+        let (mut producer, mut consumer) = audio_buffer(4);
+        assert_eq!(consumer.pop(), None);
+        for n in 0..4 {
+            assert!(producer.push(n as f32));
+        }
+        assert_eq!(producer.free(), 0);
+        assert!(!producer.push(4.0));
+        // Samples are not visible before they are published.
+        assert_eq!(consumer.pop(), None);
+        producer.publish();
+        for n in 0..4 {
+            assert_eq!(consumer.pop(), Some(n as f32));
+        }
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.free(), 4);
+        assert_eq!(consumer.position(), 4);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let (mut producer, mut consumer) = audio_buffer(4);
+        let mut next = 0;
+        for round in 0..10 {
+            // Push and pop a count that does not divide the capacity, so the
+            // positions land on every slot.
+            for _ in 0..3 {
+                assert!(producer.push(next as f32));
+                next += 1;
+            }
+            producer.publish();
+            for n in next - 3..next {
+                assert_eq!(consumer.pop(), Some(n as f32), "round {}", round);
+            }
+        }
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.position(), 30);
+        assert_eq!(consumer.position(), 30);
+    }
+
+    #[test]
+    fn discard_skips_pushed_samples() {
+        let (mut producer, mut consumer) = audio_buffer(8);
+        for n in 0..5 {
+            producer.push(n as f32);
+        }
+        producer.publish();
+        assert_eq!(consumer.pop(), Some(0.0));
+        producer.discard();
+        // Space is reclaimed once the consumer has seen the discard.
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.free(), 8);
+        producer.push(10.0);
+        producer.publish();
+        assert_eq!(consumer.pop(), Some(10.0));
+        assert_eq!(consumer.discarded_to(), 5);
+        assert_eq!(consumer.position(), 6);
+    }
+
+    #[test]
+    fn producer_and_consumer_threads_keep_order() {
+        const COUNT: u32 = 1_000_000;
+        let (mut producer, mut consumer) = audio_buffer(64);
+        let producing = thread::spawn(move || {
+            let mut n = 0;
+            while n < COUNT {
+                // Publish in chunks of varying length, as the decoder does.
+                let chunk = n % 50 + 1;
+                let mut pushed = 0;
+                while pushed < chunk && n < COUNT && producer.push(n as f32) {
+                    pushed += 1;
+                    n += 1;
+                }
+                producer.publish();
+                if pushed == 0 {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < COUNT {
+            match consumer.pop() {
+                Some(sample) => {
+                    assert_eq!(sample, expected as f32);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producing.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+    }
+}
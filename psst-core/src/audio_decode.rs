@@ -1,6 +1,110 @@
-use std::{io, slice};
+use std::{
+    io,
+    io::{Read, Seek, SeekFrom},
+    slice,
+    time::Duration,
+};
 
-use crate::error::Error;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{self, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::{MediaSource, MediaSourceStream},
+    meta::{MetadataOptions, MetadataRevision, StandardTagKey},
+    probe::{Hint, ProbeResult},
+    units::Time,
+};
+
+use crate::{audio_output::AudioSample, error::Error};
+
+/// Decoder of a single audio stream, producing interleaved samples.
+pub trait AudioDecoder: Iterator<Item = AudioSample> + Send {
+    /// Continue decoding from the PCM frame at `pcm_frame`.  In case of an
+    /// error, the position of the decoder is undefined.
+    fn seek(&mut self, pcm_frame: u64) -> Result<(), Error>;
+
+    fn channels(&self) -> u8;
+
+    fn sample_rate(&self) -> u32;
+
+    /// Error that has ended the stream before its end.  Decoders try to recover
+    /// from errors by skipping the broken part of the stream, this is only set
+    /// if that fails.
+    fn take_error(&mut self) -> Option<Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    Vorbis,
+    Mp3,
+    Flac,
+    Wav,
+}
+
+impl AudioCodec {
+    /// Detect the codec from the first bytes of `input`, and rewind it back to
+    /// the start.
+    pub fn detect(input: &mut (impl Read + Seek)) -> Result<Self, Error> {
+        let mut header = [0; 12];
+        let mut len = 0;
+        while len < header.len() {
+            match input.read(&mut header[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        input.seek(SeekFrom::Start(0))?;
+        match &header[..len] {
+            [b'O', b'g', b'g', b'S', ..] => Ok(Self::Vorbis),
+            [b'f', b'L', b'a', b'C', ..] => Ok(Self::Flac),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E'] => Ok(Self::Wav),
+            [b'I', b'D', b'3', ..] => Ok(Self::Mp3),
+            [0xFF, sync, ..] if sync & 0xE0 == 0xE0 => Ok(Self::Mp3),
+            _ => Err(Error::AudioDecodingError(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported audio format",
+            )))),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Vorbis => "ogg",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Wav => "wav",
+        }
+    }
+}
+
+/// Open a decoder of `input`, picking the codec by the stream contents.
+pub fn open_decoder<R>(mut input: R) -> Result<Box<dyn AudioDecoder>, Error>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    let codec = AudioCodec::detect(&mut input)?;
+    log::debug!("opening {:?} decoder", codec);
+    let decoder: Box<dyn AudioDecoder> = match codec {
+        AudioCodec::Vorbis => Box::new(VorbisDecoder::new(input)?),
+        _ => Box::new(SymphoniaDecoder::new(input, codec)?),
+    };
+    // Streams without any channels would never produce a frame, and the rate is
+    // needed for resampling.
+    if decoder.channels() == 0 || decoder.sample_rate() == 0 {
+        return Err(Error::AudioDecodingError(Box::new(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "audio stream has no channels or no sample rate",
+        ))));
+    }
+    Ok(decoder)
+}
+
+/// Tags and total duration of an audio file.
+pub struct AudioInfo {
+    pub tags: Vec<(String, String)>,
+    pub duration: Duration,
+}
 
 pub struct VorbisDecoder<R>
 where
@@ -11,6 +115,7 @@ where
     packet: Vec<f32>,
     // Offset into `packet`, currently pending sample.
     pos: usize,
+    resync: Resync,
 }
 
 impl<R> VorbisDecoder<R>
@@ -24,16 +129,16 @@ where
             vorbis,
             packet: Vec::with_capacity(minivorbis::TYPICAL_PACKET_CAP),
             pos: 0, // Buffer is initially empty.
+            resync: Resync::default(),
         })
     }
+}
 
-    pub fn seek(&mut self, pcm_frame: u64) {
-        self.vorbis
-            .seek_to_pcm(pcm_frame)
-            .expect("Failed to set current OGG stream position");
-    }
-
-    fn read_next_packet(&mut self) -> Result<usize, minivorbis::Error> {
+impl<R> PacketDecoder for VorbisDecoder<R>
+where
+    R: io::Read + io::Seek + Send,
+{
+    fn read_next_packet(&mut self) -> Result<usize, Error> {
         loop {
             let packet = unsafe {
                 slice::from_raw_parts_mut(self.packet.as_mut_ptr(), self.packet.capacity())
@@ -47,15 +152,38 @@ where
                     unsafe {
                         self.packet.set_len(len);
                     }
+                    self.pos = 0;
                     return Ok(len);
                 }
-                other_result => {
-                    return other_result;
+                Err(err) => {
+                    return Err(err.into());
                 }
             }
         }
     }
 
+    fn seek_to_frame(&mut self, pcm_frame: u64) -> Result<(), Error> {
+        self.vorbis.seek_to_pcm(pcm_frame)?;
+        self.packet.clear();
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn resync_mut(&mut self) -> &mut Resync {
+        &mut self.resync
+    }
+}
+
+impl<R> AudioDecoder for VorbisDecoder<R>
+where
+    R: io::Read + io::Seek + Send,
+{
+    fn seek(&mut self, pcm_frame: u64) -> Result<(), Error> {
+        self.seek_to_frame(pcm_frame)?;
+        self.resync.reset(pcm_frame);
+        Ok(())
+    }
+
     fn channels(&self) -> u8 {
         self.vorbis.channels
     }
@@ -63,32 +191,206 @@ where
     fn sample_rate(&self) -> u32 {
         self.vorbis.sample_rate
     }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.resync.error.take()
+    }
 }
 
 impl<R> Iterator for VorbisDecoder<R>
 where
-    R: io::Read + io::Seek,
+    R: io::Read + io::Seek + Send,
 {
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
         if self.pos >= self.packet.len() {
             // We have reached the end of the packet, try to read the next one.
-            match self.read_next_packet() {
+            if !read_packet_or_resync(self) {
+                return None; // End of stream.
+            }
+        }
+        // Sample is available in this packet, return it.
+        let sample = self.packet[self.pos];
+        self.pos += 1;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.packet.len() - self.pos, None)
+    }
+}
+
+impl From<minivorbis::Error> for Error {
+    fn from(err: minivorbis::Error) -> Error {
+        Error::AudioDecodingError(Box::new(err))
+    }
+}
+
+/// Decoder of the formats supported by Symphonia, that is MP3, FLAC and WAV.
+pub struct SymphoniaDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn codecs::Decoder>,
+    track_id: u32,
+    channels: u8,
+    sample_rate: u32,
+    // Interleaved samples of the last decoded packet.
+    packet: Vec<f32>,
+    // Offset into `packet`, currently pending sample.
+    pos: usize,
+    resync: Resync,
+}
+
+impl SymphoniaDecoder {
+    pub fn new<R>(input: R, codec: AudioCodec) -> Result<Self, Error>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let format = probe(input, codec)?.format;
+        let track = format
+            .default_track()
+            .ok_or(SymphoniaError::Unsupported("no audio track"))?;
+        let track_id = track.id;
+        let channels = track.codec_params.channels.map_or(2, |c| c.count() as u8);
+        let sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+        let mut symphonia = Self {
+            format,
+            decoder,
+            track_id,
+            channels,
+            sample_rate,
+            packet: Vec::new(),
+            pos: 0,
+            resync: Resync::default(),
+        };
+        // Some formats, i.e. MP3, only reveal the stream format in the first packet.
+        let len = symphonia.read_next_packet()?;
+        symphonia.resync.advance(len, symphonia.channels);
+        Ok(symphonia)
+    }
+
+    /// Read the tags and the duration of `input`, without decoding any audio.
+    pub fn read_info<R>(input: R, codec: AudioCodec) -> Result<AudioInfo, Error>
+    where
+        R: Read + Seek + Send + Sync + 'static,
+    {
+        let mut probed = probe(input, codec)?;
+        let mut tags = Vec::new();
+        // Tags can be in the container skipped by the probe, i.e. ID3v2, or in the
+        // format itself.
+        if let Some(metadata) = probed.metadata.get() {
+            if let Some(revision) = metadata.current() {
+                collect_tags(revision, &mut tags);
+            }
+        }
+        if let Some(revision) = probed.format.metadata().current() {
+            collect_tags(revision, &mut tags);
+        }
+        let duration = probed
+            .format
+            .default_track()
+            .and_then(|track| {
+                let frames = track.codec_params.n_frames?;
+                let sample_rate = track.codec_params.sample_rate?;
+                Some(Duration::from_secs_f64(frames as f64 / sample_rate as f64))
+            })
+            .unwrap_or_default();
+        Ok(AudioInfo { tags, duration })
+    }
+}
+
+impl PacketDecoder for SymphoniaDecoder {
+    fn read_next_packet(&mut self) -> Result<usize, Error> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(0);
+                }
                 Err(err) => {
-                    log::error!("error while decoding: {:?}", err);
-                    return None; // Signal an end of stream.
+                    return Err(err.into());
                 }
-                Ok(0) => {
-                    return None; // End of stream.
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            match self.decoder.decode(&packet) {
+                Err(SymphoniaError::DecodeError(err)) => {
+                    // Skip malformed packets.
+                    log::warn!("skipping packet: {}", err);
+                    continue;
                 }
-                Ok(_) => {
-                    // We have read next packet, reset the cursor and continue.
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let mut samples = SampleBuffer::new(decoded.capacity() as u64, spec);
+                    samples.copy_interleaved_ref(decoded);
+                    self.channels = spec.channels.count() as u8;
+                    self.sample_rate = spec.rate;
+                    self.packet.clear();
+                    self.packet.extend_from_slice(samples.samples());
                     self.pos = 0;
+                    if !self.packet.is_empty() {
+                        return Ok(self.packet.len());
+                    }
+                }
+                Err(err) => {
+                    return Err(err.into());
                 }
             }
         }
-        // Sample is available in this packet, return it.
+    }
+
+    fn seek_to_frame(&mut self, pcm_frame: u64) -> Result<(), Error> {
+        let sample_rate = u64::from(self.sample_rate);
+        let time = Time::new(
+            pcm_frame / sample_rate,
+            (pcm_frame % sample_rate) as f64 / sample_rate as f64,
+        );
+        let seek_to = SeekTo::Time {
+            time,
+            track_id: Some(self.track_id),
+        };
+        self.format.seek(SeekMode::Accurate, seek_to)?;
+        self.decoder.reset();
+        self.packet.clear();
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn resync_mut(&mut self) -> &mut Resync {
+        &mut self.resync
+    }
+}
+
+impl AudioDecoder for SymphoniaDecoder {
+    fn seek(&mut self, pcm_frame: u64) -> Result<(), Error> {
+        self.seek_to_frame(pcm_frame)?;
+        self.resync.reset(pcm_frame);
+        Ok(())
+    }
+
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        self.resync.error.take()
+    }
+}
+
+impl Iterator for SymphoniaDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos >= self.packet.len() && !read_packet_or_resync(self) {
+            return None; // End of stream.
+        }
         let sample = self.packet[self.pos];
         self.pos += 1;
         Some(sample)
@@ -99,8 +401,157 @@ where
     }
 }
 
-impl From<minivorbis::Error> for Error {
-    fn from(err: minivorbis::Error) -> Error {
+// How many times in a row we try to skip past a broken part of the stream,
+// before giving up.
+const MAX_RESYNC_ATTEMPTS: usize = 5;
+// How far ahead of the broken part we continue decoding.
+const RESYNC_SKIP: Duration = Duration::from_millis(500);
+
+/// Decoder working in packets, able to continue from an arbitrary frame.
+trait PacketDecoder: AudioDecoder {
+    /// Decode the next packet.  Returns the count of decoded samples, zero at
+    /// the end of stream.
+    fn read_next_packet(&mut self) -> Result<usize, Error>;
+
+    fn seek_to_frame(&mut self, pcm_frame: u64) -> Result<(), Error>;
+
+    fn resync_mut(&mut self) -> &mut Resync;
+}
+
+/// Position of a decoder in its stream, and the state of resynchronisation
+/// after decoding errors.
+#[derive(Default)]
+struct Resync {
+    // Frame following the last decoded packet.
+    position: u64,
+    // Count of consecutive failed attempts to decode.
+    failures: usize,
+    // Error that has ended the stream prematurely.
+    error: Option<Error>,
+}
+
+impl Resync {
+    fn advance(&mut self, samples: usize, channels: u8) {
+        self.position += (samples / usize::from(channels.max(1))) as u64;
+        self.failures = 0;
+    }
+
+    fn reset(&mut self, position: u64) {
+        self.position = position;
+        self.failures = 0;
+        self.error = None;
+    }
+}
+
+/// Read the next packet of `decoder`.  In case of an error, try to skip past
+/// the broken part of the stream and continue from there.  Returns `false` at
+/// the end of stream, or if the decoding cannot continue, in which case the
+/// error is kept in `Resync::error`.
+fn read_packet_or_resync(decoder: &mut impl PacketDecoder) -> bool {
+    loop {
+        let err = match decoder.read_next_packet() {
+            Ok(0) => return false,
+            Ok(len) => {
+                let channels = decoder.channels();
+                decoder.resync_mut().advance(len, channels);
+                return true;
+            }
+            Err(err) => err,
+        };
+        let skip = (RESYNC_SKIP.as_secs_f64() * f64::from(decoder.sample_rate())) as u64;
+        let resync = decoder.resync_mut();
+        resync.failures += 1;
+        if resync.failures > MAX_RESYNC_ATTEMPTS {
+            log::error!("giving up decoding: {}", err);
+            resync.error.replace(err);
+            return false;
+        }
+        let target = resync.position + skip;
+        log::warn!(
+            "error while decoding, skipping to frame {}: {}",
+            target,
+            err
+        );
+        match decoder.seek_to_frame(target) {
+            Ok(_) => {
+                decoder.resync_mut().position = target;
+            }
+            Err(seek_err) => {
+                log::error!("failed to skip past decoding error: {}", seek_err);
+                decoder.resync_mut().error.replace(err);
+                return false;
+            }
+        }
+    }
+}
+
+fn probe<R>(input: R, codec: AudioCodec) -> Result<ProbeResult, Error>
+where
+    R: Read + Seek + Send + Sync + 'static,
+{
+    let source = MediaSourceStream::new(Box::new(SeekableSource::new(input)?), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(codec.extension());
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    Ok(probed)
+}
+
+fn collect_tags(revision: &MetadataRevision, tags: &mut Vec<(String, String)>) {
+    for tag in revision.tags() {
+        // Use the Vorbis comment names for the tags we are interested in.
+        let key = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => "TITLE",
+            Some(StandardTagKey::Artist) => "ARTIST",
+            Some(StandardTagKey::Album) => "ALBUM",
+            _ => tag.key.as_str(),
+        };
+        tags.push((key.to_string(), tag.value.to_string()));
+    }
+}
+
+/// Seekable input of known length, as expected by Symphonia.
+struct SeekableSource<R> {
+    input: R,
+    length: u64,
+}
+
+impl<R: Read + Seek> SeekableSource<R> {
+    fn new(mut input: R) -> io::Result<Self> {
+        let length = input.seek(SeekFrom::End(0))?;
+        input.seek(SeekFrom::Start(0))?;
+        Ok(Self { input, length })
+    }
+}
+
+impl<R: Read> Read for SeekableSource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for SeekableSource<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.input.seek(pos)
+    }
+}
+
+impl<R: Read + Seek + Send + Sync> MediaSource for SeekableSource<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.length)
+    }
+}
+
+impl From<SymphoniaError> for Error {
+    fn from(err: SymphoniaError) -> Error {
         Error::AudioDecodingError(Box::new(err))
     }
 }
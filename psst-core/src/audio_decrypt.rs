@@ -12,16 +12,20 @@ const AUDIO_AESIV: [u8; 16] = [
 ];
 
 pub struct AudioDecrypt<T> {
-    cipher: Aes128Ctr,
+    // Files that are not hosted by Spotify are not encrypted, and are passed
+    // through as they are.
+    cipher: Option<Aes128Ctr>,
     reader: T,
 }
 
 impl<T: io::Read> AudioDecrypt<T> {
-    pub fn new(key: AudioKey, reader: T) -> AudioDecrypt<T> {
-        let cipher = Aes128Ctr::new(
-            GenericArray::from_slice(&key.0),
-            GenericArray::from_slice(&AUDIO_AESIV),
-        );
+    pub fn new(key: Option<AudioKey>, reader: T) -> AudioDecrypt<T> {
+        let cipher = key.map(|key| {
+            Aes128Ctr::new(
+                GenericArray::from_slice(&key.0),
+                GenericArray::from_slice(&AUDIO_AESIV),
+            )
+        });
         AudioDecrypt { cipher, reader }
     }
 }
@@ -30,7 +34,9 @@ impl<T: io::Read> io::Read for AudioDecrypt<T> {
     fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
         let len = self.reader.read(output)?;
 
-        self.cipher.apply_keystream(&mut output[..len]);
+        if let Some(cipher) = &mut self.cipher {
+            cipher.apply_keystream(&mut output[..len]);
+        }
 
         Ok(len)
     }
@@ -40,7 +46,9 @@ impl<T: io::Read + io::Seek> io::Seek for AudioDecrypt<T> {
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
         let newpos = self.reader.seek(pos)?;
 
-        self.cipher.seek(newpos);
+        if let Some(cipher) = &mut self.cipher {
+            cipher.seek(newpos);
+        }
 
         Ok(newpos)
     }
@@ -0,0 +1,379 @@
+use std::f32::consts::PI;
+
+use crate::{audio_output::AudioSample, audio_resample::AudioFormat};
+
+/// Center frequencies of the bands of the default equalizer, in Hz.
+pub const BAND_FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+// Quality factor of the default bands, roughly one octave wide.
+const BAND_Q: f32 = 1.41;
+// How long it takes to fade the equalizer in or out, and to change the preamp
+// or the bands.
+const RAMP_SECS: f32 = 0.02;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EqualizerConfig {
+    pub enabled: bool,
+    /// Gain applied before the filters, in dB.  Use a negative preamp to make
+    /// room for boosted bands.
+    pub preamp: f32,
+    pub bands: Vec<EqualizerBand>,
+}
+
+impl EqualizerConfig {
+    pub const MAX_GAIN: f32 = 12.0;
+
+    /// Equalizer with the default bands, see `BAND_FREQUENCIES`, set to
+    /// `gains`.  The lowest and the highest band are shelves.
+    pub fn with_gains(enabled: bool, preamp: f32, gains: &[f32]) -> Self {
+        let last = BAND_FREQUENCIES.len() - 1;
+        let bands = BAND_FREQUENCIES
+            .iter()
+            .zip(gains)
+            .enumerate()
+            .map(|(i, (&frequency, &gain))| EqualizerBand {
+                kind: match i {
+                    0 => FilterKind::LowShelf,
+                    i if i == last => FilterKind::HighShelf,
+                    _ => FilterKind::Peaking,
+                },
+                frequency,
+                gain: gain.clamp(-Self::MAX_GAIN, Self::MAX_GAIN),
+                q: BAND_Q,
+            })
+            .collect();
+        Self {
+            enabled,
+            preamp: preamp.clamp(-Self::MAX_GAIN, Self::MAX_GAIN),
+            bands,
+        }
+    }
+}
+
+impl Default for EqualizerConfig {
+    fn default() -> Self {
+        Self::with_gains(false, 0.0, &EqualizerPreset::Flat.gains())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqualizerBand {
+    pub kind: FilterKind,
+    /// Center frequency of a peaking filter, or the corner frequency of a
+    /// shelf, in Hz.
+    pub frequency: f32,
+    /// Gain, in dB.
+    pub gain: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EqualizerPreset {
+    Flat,
+    BassBoost,
+    TrebleBoost,
+    Vocal,
+    Loudness,
+}
+
+impl EqualizerPreset {
+    pub const ALL: [Self; 5] = [
+        Self::Flat,
+        Self::BassBoost,
+        Self::TrebleBoost,
+        Self::Vocal,
+        Self::Loudness,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Flat => "Flat",
+            Self::BassBoost => "Bass boost",
+            Self::TrebleBoost => "Treble boost",
+            Self::Vocal => "Vocal",
+            Self::Loudness => "Loudness",
+        }
+    }
+
+    /// Gains of the default bands, in dB.
+    pub fn gains(self) -> [f32; 10] {
+        match self {
+            Self::Flat => [0.0; 10],
+            Self::BassBoost => [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0],
+            Self::TrebleBoost => [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 2.0, 4.0, 5.0, 6.0],
+            Self::Vocal => [-2.0, -2.0, -1.0, 1.0, 3.0, 3.0, 2.0, 1.0, 0.0, -1.0],
+            Self::Loudness => [5.0, 4.0, 2.0, 0.0, -1.0, -1.0, 0.0, 2.0, 4.0, 5.0],
+        }
+    }
+
+    /// Preamp that keeps the boosted bands from clipping, in dB.
+    pub fn preamp(self) -> f32 {
+        let max_gain = self.gains().iter().cloned().fold(0.0, f32::max);
+        -max_gain
+    }
+}
+
+/// Multi-band equalizer, filtering an interleaved stream of samples.
+/// Configuration can change at any time, filter state is kept and the changes
+/// of the preamp, of the enabled state and of the filter coefficients are
+/// ramped, so there are no clicks.
+pub struct Equalizer {
+    config: EqualizerConfig,
+    format: AudioFormat,
+    // Current coefficients of every band, moving towards `target_filters` over
+    // the next `filter_ramp` frames.  While the band count changes, both are
+    // padded with pass-through filters to the larger of the counts.
+    filters: Vec<Biquad>,
+    target_filters: Vec<Biquad>,
+    filter_ramp: usize,
+    // Filter state of every band and channel, indexed by `band * channels + channel`.
+    states: Vec<BiquadState>,
+    // Channel of the next sample.
+    channel: usize,
+    // Current and target linear preamp gain.
+    preamp: f32,
+    target_preamp: f32,
+    // Current and target mix of the filtered signal, 0 for bypass, 1 for full effect.
+    wet: f32,
+    target_wet: f32,
+    // Change of the ramped values per frame.
+    ramp_step: f32,
+}
+
+impl Equalizer {
+    pub fn new(config: EqualizerConfig, format: AudioFormat) -> Self {
+        let mut equalizer = Self {
+            config: EqualizerConfig::default(),
+            format,
+            filters: Vec::new(),
+            target_filters: Vec::new(),
+            filter_ramp: 0,
+            states: Vec::new(),
+            channel: 0,
+            preamp: 1.0,
+            target_preamp: 1.0,
+            wet: 0.0,
+            target_wet: 0.0,
+            ramp_step: 0.0,
+        };
+        equalizer.set_format(format);
+        equalizer.set_config(config);
+        // Start in the configured state right away.
+        equalizer.preamp = equalizer.target_preamp;
+        equalizer.wet = equalizer.target_wet;
+        equalizer.finish_filter_ramp();
+        equalizer
+    }
+
+    pub fn config(&self) -> &EqualizerConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: EqualizerConfig) {
+        let is_bypassed = self.wet == 0.0;
+        self.config = config;
+        self.target_preamp = db_to_gain(self.config.preamp);
+        self.target_wet = if self.config.enabled { 1.0 } else { 0.0 };
+        self.update_filters();
+        if is_bypassed && self.config.enabled {
+            // Filters have not been running, start them from silence, with the new
+            // coefficients right away.
+            self.finish_filter_ramp();
+            self.states = vec![BiquadState::default(); self.state_len(self.filters.len())];
+        }
+    }
+
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.format = format;
+        self.ramp_step = 1.0 / (RAMP_SECS * format.sample_rate as f32);
+        self.channel = 0;
+        // Coefficients of the previous sample rate are of no use, and the state is
+        // starting over.
+        self.update_filters();
+        self.finish_filter_ramp();
+        self.states = vec![BiquadState::default(); self.state_len(self.filters.len())];
+    }
+
+    pub fn process(&mut self, sample: AudioSample) -> AudioSample {
+        let channels = usize::from(self.format.channels.max(1));
+        let channel = self.channel;
+        self.channel = (self.channel + 1) % channels;
+        if channel == 0 {
+            // Advance the ramps once per frame, so all channels are treated equally.
+            self.wet = approach(self.wet, self.target_wet, self.ramp_step);
+            self.preamp = approach(self.preamp, self.target_preamp, self.ramp_step);
+            self.advance_filter_ramp();
+        }
+        if self.wet == 0.0 {
+            return sample;
+        }
+        let mut filtered = sample * self.preamp;
+        for (band, filter) in self.filters.iter().enumerate() {
+            let state = &mut self.states[band * channels + channel];
+            filtered = filter.process(state, filtered);
+        }
+        sample + (filtered - sample) * self.wet
+    }
+
+    fn state_len(&self, bands: usize) -> usize {
+        bands * usize::from(self.format.channels.max(1))
+    }
+
+    /// Start ramping the filters towards the configured bands.
+    fn update_filters(&mut self) {
+        let sample_rate = self.format.sample_rate as f32;
+        self.target_filters = self
+            .config
+            .bands
+            .iter()
+            .map(|band| Biquad::new(band, sample_rate))
+            .collect();
+        // Bands that are added start as pass-through, with an empty state, bands
+        // that are removed fade into pass-through before they are dropped.
+        let len = self.filters.len().max(self.target_filters.len());
+        self.filters.resize(len, Biquad::PASS_THROUGH);
+        self.target_filters.resize(len, Biquad::PASS_THROUGH);
+        let state_len = self.state_len(len);
+        self.states.resize(state_len, BiquadState::default());
+        self.filter_ramp = (RAMP_SECS * sample_rate) as usize;
+    }
+
+    /// Move the coefficients one frame closer to the target.  Every step is a
+    /// linear interpolation between two stable filters, which is stable as well.
+    fn advance_filter_ramp(&mut self) {
+        if self.filter_ramp == 0 {
+            return;
+        }
+        let fraction = 1.0 / self.filter_ramp as f32;
+        for (filter, target) in self.filters.iter_mut().zip(&self.target_filters) {
+            filter.approach(target, fraction);
+        }
+        self.filter_ramp -= 1;
+        if self.filter_ramp == 0 {
+            self.finish_filter_ramp();
+        }
+    }
+
+    /// Jump to the target coefficients, and drop the bands that are gone.
+    fn finish_filter_ramp(&mut self) {
+        let len = self.config.bands.len();
+        self.target_filters.truncate(len);
+        self.filters = self.target_filters.clone();
+        let state_len = self.state_len(len);
+        self.states.truncate(state_len);
+        self.filter_ramp = 0;
+    }
+}
+
+fn approach(value: f32, target: f32, step: f32) -> f32 {
+    if value < target {
+        (value + step).min(target)
+    } else {
+        (value - step).max(target)
+    }
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+/// Normalized coefficients of a biquad filter, see the Audio EQ Cookbook by
+/// Robert Bristow-Johnson.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BiquadState {
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    const PASS_THROUGH: Self = Self {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    fn new(band: &EqualizerBand, sample_rate: f32) -> Self {
+        // Keep the frequency safely below Nyquist.
+        let frequency = band.frequency.clamp(1.0, sample_rate * 0.45);
+        let a = 10.0_f32.powf(band.gain / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q.max(0.01));
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            FilterKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => {
+                let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                    (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+                )
+            }
+            FilterKind::HighShelf => {
+                let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+                (
+                    a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                    (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+                )
+            }
+        };
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Move the coefficients by `fraction` of the way to `target`.
+    fn approach(&mut self, target: &Self, fraction: f32) {
+        self.b0 += (target.b0 - self.b0) * fraction;
+        self.b1 += (target.b1 - self.b1) * fraction;
+        self.b2 += (target.b2 - self.b2) * fraction;
+        self.a1 += (target.a1 - self.a1) * fraction;
+        self.a2 += (target.a2 - self.a2) * fraction;
+    }
+
+    /// Filter a single sample, in the transposed direct form II.
+    fn process(&self, state: &mut BiquadState, x: f32) -> f32 {
+        let y = self.b0 * x + state.z1;
+        state.z1 = self.b1 * x - self.a1 * y + state.z2;
+        state.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
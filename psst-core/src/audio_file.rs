@@ -1,37 +1,52 @@
 use std::{
+    cmp::Reverse,
     io,
-    io::{BufReader, Seek, SeekFrom},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::PathBuf,
-    sync::Arc,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
     thread,
     thread::JoinHandle,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use crossbeam_channel::Receiver;
+use sha1::{Digest, Sha1};
+
 use crate::{
-    audio_decode::VorbisDecoder,
+    audio_decode::{self, AudioDecoder},
     audio_decrypt::AudioDecrypt,
     audio_key::AudioKey,
     audio_normalize::NormalizationData,
     cache::CacheHandle,
-    cdn::{CdnHandle, CdnUrl},
+    cdn::{self, CdnHandle, CdnUrl},
     error::Error,
     item_id::{FileId, ItemId},
     protocol::metadata::mod_AudioFile::Format,
-    stream_storage::{StreamReader, StreamRequest, StreamStorage, StreamWriter},
+    stream_storage::{StreamRequest, StreamStorage, StreamWriter},
     util::OffsetFile,
 };
 
-pub type FileAudioSource = VorbisDecoder<OffsetFile<AudioDecrypt<BufReader<StreamReader>>>>;
+pub type FileAudioSource = Box<dyn AudioDecoder>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct AudioPath {
+    /// Item that has been requested to play.
     pub item_id: ItemId,
+    /// Item the file belongs to.  Differs from `item_id` in case a regional
+    /// alternative is played in place of the requested track.
+    pub played_item_id: ItemId,
     pub file_id: FileId,
     pub file_format: Format,
     pub duration: Duration,
 }
 
+impl AudioPath {
+    pub fn is_substitute(&self) -> bool {
+        self.item_id != self.played_item_id
+    }
+}
+
 pub enum AudioFile {
     Streamed {
         streamed_file: Arc<StreamedFile>,
@@ -40,6 +55,11 @@ pub enum AudioFile {
     Cached {
         cached_file: CachedFile,
     },
+    /// File of the local music folder.  These are read the same way as the
+    /// cached files, but are never encrypted and have no Spotify header.
+    Local {
+        local_file: CachedFile,
+    },
 }
 
 impl AudioFile {
@@ -64,20 +84,55 @@ impl AudioFile {
         }
     }
 
-    pub fn open(path: AudioPath, cdn: CdnHandle, cache: CacheHandle) -> Result<Self, Error> {
-        let cached_path = cache.audio_file_path(path.file_id);
-        if cached_path.exists() {
+    /// Format we assume for files hosted outside of Spotify.  These are
+    /// practically always MP3s, but we cannot know the bitrate in advance.
+    pub const EXTERNAL_FORMAT: Format = Format::MP3_256;
+
+    /// Format we report for local files, as they are not described by any of
+    /// the Spotify formats.
+    pub const LOCAL_FORMAT: Format = Format::OTHER5;
+
+    /// Bitrates of the Spotify formats we play, from the lowest.
+    pub const BITRATES: [usize; 3] = [96, 160, 320];
+
+    /// Derive a stable file ID from the URL of an external file, so it can be
+    /// cached the same way as the files from the CDN.
+    pub fn external_file_id(url: &str) -> FileId {
+        let mut id = [0; 20];
+        id.copy_from_slice(&Sha1::digest(url.as_bytes()));
+        FileId(id)
+    }
+
+    /// Open the file from cache, or start streaming it, either from the CDN, or
+    /// from `external_url` in case the file is not hosted by Spotify.  While
+    /// streaming, `blocking_callback` is called with `true` every time reading
+    /// has to wait for the data to download, and with `false` once it arrives.
+    /// Ranges of the file are downloaded through `downloads`.
+    pub fn open(
+        path: AudioPath,
+        external_url: Option<String>,
+        cdn: CdnHandle,
+        cache: CacheHandle,
+        downloads: DownloadScheduler,
+        blocking_callback: impl Fn(bool) + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        if let Some(cached_path) = cache.get_audio_file(path.file_id) {
             let cached_file = CachedFile::open(path, cached_path)?;
             Ok(Self::Cached { cached_file })
         } else {
-            let streamed_file = Arc::new(StreamedFile::open(path, cdn, cache)?);
+            let streamed_file = Arc::new(StreamedFile::open(
+                path,
+                external_url,
+                cdn,
+                cache,
+                Box::new(blocking_callback),
+            )?);
             let servicing_handle = thread::spawn({
-                let streamed_file = Arc::clone(&streamed_file);
-                move || {
-                    streamed_file
-                        .service_streaming()
-                        .expect("Streaming thread failed");
-                }
+                // Keep only a weak reference, so the pending downloads get cancelled
+                // once the file is dropped.
+                let file = Arc::downgrade(&streamed_file);
+                let requests = streamed_file.storage.receiver().clone();
+                move || StreamedFile::service_streaming(file, requests, downloads)
             });
             Ok(Self::Streamed {
                 streamed_file,
@@ -86,30 +141,75 @@ impl AudioFile {
         }
     }
 
+    /// Open a file of the local music folder at `file_path`.
+    pub fn open_local(path: AudioPath, file_path: PathBuf) -> Result<Self, Error> {
+        let local_file = CachedFile::open(path, file_path)?;
+        Ok(Self::Local { local_file })
+    }
+
     pub fn path(&self) -> AudioPath {
         match self {
             Self::Streamed { streamed_file, .. } => streamed_file.path,
             Self::Cached { cached_file, .. } => cached_file.path,
+            Self::Local { local_file } => local_file.path,
+        }
+    }
+
+    /// Returns false for files read from the cache or from the local music
+    /// folder, true for files downloaded over the network.
+    pub fn is_streamed(&self) -> bool {
+        matches!(self, Self::Streamed { .. })
+    }
+
+    /// Parts of the file that are available, as time ranges from the beginning.
+    /// Byte offsets are mapped to time proportionally, so the ranges are only
+    /// approximate for files of variable bitrate.
+    pub fn buffered_ranges(&self) -> Vec<Range<Duration>> {
+        let duration = self.path().duration;
+        match self {
+            Self::Streamed { streamed_file, .. } => {
+                let total_size = streamed_file.storage.total_size().max(1) as f64;
+                let to_time = |offset: u64| duration.mul_f64(offset as f64 / total_size);
+                streamed_file
+                    .storage
+                    .downloaded_ranges()
+                    .into_iter()
+                    .map(|range| to_time(range.start)..to_time(range.end))
+                    .collect()
+            }
+            Self::Cached { .. } | Self::Local { .. } => vec![Duration::default()..duration],
         }
     }
 
+    /// Open a decoder of the file contents.  `key` is `None` for files that are
+    /// not encrypted.
     pub fn audio_source(
         &self,
-        key: AudioKey,
+        key: Option<AudioKey>,
     ) -> Result<(FileAudioSource, NormalizationData), Error> {
         let reader = match self {
             Self::Streamed { streamed_file, .. } => streamed_file.storage.reader()?,
             Self::Cached { cached_file, .. } => cached_file.storage.reader()?,
+            Self::Local { local_file } => local_file.storage.reader()?,
         };
         let buffered = BufReader::new(reader);
         let mut decrypted = AudioDecrypt::new(key, buffered);
-        let normalization = NormalizationData::parse(&mut decrypted)?;
-        let encoded = OffsetFile::new(decrypted, self.header_length())?;
-        let decoded = VorbisDecoder::new(encoded)?;
+        let header_length = self.header_length();
+        // Normalization data is only present in the Spotify header of the file.
+        let normalization = if header_length > 0 {
+            NormalizationData::parse(&mut decrypted)?
+        } else {
+            NormalizationData::default()
+        };
+        let encoded = OffsetFile::new(decrypted, header_length)?;
+        let decoded = audio_decode::open_decoder(encoded)?;
         Ok((decoded, normalization))
     }
 
     fn header_length(&self) -> u64 {
+        if let Self::Local { .. } = self {
+            return 0;
+        }
         match self.path().file_format {
             Format::OGG_VORBIS_96 | Format::OGG_VORBIS_160 | Format::OGG_VORBIS_320 => 167,
             _ => 0,
@@ -120,16 +220,27 @@ impl AudioFile {
 pub struct StreamedFile {
     path: AudioPath,
     storage: StreamStorage,
-    url: CdnUrl,
+    url: Mutex<CdnUrl>,
     cdn: CdnHandle,
     cache: CacheHandle,
+    blocking_callback: Box<dyn Fn(bool) + Send + Sync>,
 }
 
 impl StreamedFile {
-    fn open(path: AudioPath, cdn: CdnHandle, cache: CacheHandle) -> Result<StreamedFile, Error> {
+    fn open(
+        path: AudioPath,
+        external_url: Option<String>,
+        cdn: CdnHandle,
+        cache: CacheHandle,
+        blocking_callback: Box<dyn Fn(bool) + Send + Sync>,
+    ) -> Result<StreamedFile, Error> {
         // First, we need to resolve URL of the file contents.
-        let url = cdn.resolve_audio_file_url(path.file_id)?;
-        log::debug!("resolved file URL: {:?}", url.url);
+        let is_external = external_url.is_some();
+        let mut url = match external_url {
+            Some(url) => CdnUrl::external(url),
+            None => cdn.resolve_audio_file_url(path.file_id)?,
+        };
+        log::debug!("resolved file URL: {:?}", url.url());
 
         // How many bytes we request in the first chunk.
         const INITIAL_REQUEST_LENGTH: u64 = 1024 * 6;
@@ -137,8 +248,34 @@ impl StreamedFile {
         // Send the initial request, that gives us the total file length and the
         // beginning of the contents.  Use the total length for creating the backing
         // data storage.
-        let (total_length, mut initial_data) =
-            cdn.fetch_file_range(&url.url, 0, INITIAL_REQUEST_LENGTH)?;
+        // Try each of the hosts once before giving up, and resolve the URL again
+        // once if the CDN refuses it.
+        let mut hosts_left = url.host_count();
+        let mut is_resolved_again = false;
+        let (total_length, mut initial_data) = loop {
+            if url.is_expired() {
+                url = cdn.resolve_audio_file_url(path.file_id)?;
+                hosts_left = url.host_count();
+            }
+            match cdn.fetch_file_range(url.url(), 0, INITIAL_REQUEST_LENGTH) {
+                Ok(response) => break response,
+                Err(err) if !is_external && !is_resolved_again && cdn::is_url_refused(&err) => {
+                    log::info!("CDN has refused the URL, resolving it again");
+                    is_resolved_again = true;
+                    url.expire();
+                }
+                Err(err) if hosts_left > 1 && cdn::is_host_error(&err) => {
+                    log::warn!(
+                        "failed to fetch from {:?}, trying next host: {}",
+                        url.url(),
+                        err
+                    );
+                    hosts_left -= 1;
+                    url.rotate();
+                }
+                Err(err) => return Err(err),
+            }
+        };
         let storage = StreamStorage::new(total_length)?;
 
         // Pipe the initial data from the request body into storage.
@@ -147,72 +284,462 @@ impl StreamedFile {
         Ok(StreamedFile {
             path,
             storage,
-            url,
+            url: Mutex::new(url),
             cdn,
             cache,
+            blocking_callback,
         })
     }
 
-    fn service_streaming(&self) -> Result<(), Error> {
-        let mut last_url = self.url.clone();
-        let mut fresh_url = || -> Result<CdnUrl, Error> {
-            if last_url.is_expired() {
-                last_url = self.cdn.resolve_audio_file_url(self.path.file_id)?;
-            }
-            Ok(last_url.clone())
-        };
-        let mut download_range = |offset, length| -> Result<(), Error> {
-            let thread_name = format!(
-                "cdn-{}-{}..{}",
-                self.path.file_id.to_base16(),
-                offset,
-                offset + length
-            );
-            // TODO: We spawn threads here without any accounting.  Seems wrong.
-            thread::Builder::new().name(thread_name).spawn({
-                let url = fresh_url()?.url;
-                let cdn = self.cdn.clone();
-                let cache = self.cache.clone();
-                let mut writer = self.storage.writer()?;
-                let file_path = self.storage.path().to_path_buf();
-                let file_id = self.path.file_id;
-                move || {
-                    match load_range(&mut writer, &cdn, &url, offset, length) {
-                        Ok(_) => {
-                            // If the file is completely downloaded, copy it to cache.
-                            if writer.is_complete() && !cache.audio_file_path(file_id).exists() {
-                                // TODO: We should do this atomically.
-                                if let Err(err) = cache.save_audio_file(file_id, file_path) {
-                                    log::warn!("failed to save audio file to cache: {:?}", err);
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            log::error!("failed to download: {}", err);
-                            // Range failed to download, remove it from the requested set.
-                            writer.mark_as_not_requested(offset, length);
-                        }
-                    }
-                }
-            })?;
-
-            Ok(())
-        };
-
-        while let Ok(req) = self.storage.receiver().recv() {
+    fn service_streaming(
+        file: Weak<Self>,
+        requests: Receiver<StreamRequest>,
+        downloads: DownloadScheduler,
+    ) {
+        while let Ok(req) = requests.recv() {
+            let streamed_file = match file.upgrade() {
+                Some(streamed_file) => streamed_file,
+                None => break,
+            };
             match req {
                 StreamRequest::Preload { offset, length } => {
-                    if let Err(err) = download_range(offset, length) {
-                        log::error!("failed to request audio range: {:?}", err);
-                    }
+                    downloads.schedule(&file, offset, length);
                 }
                 StreamRequest::Blocked { offset } => {
                     log::info!("blocked at {}", offset);
+                    downloads.prioritize(&file, offset);
+                    (streamed_file.blocking_callback)(true);
+                }
+                StreamRequest::Unblocked { offset } => {
+                    log::info!("unblocked at {}", offset);
+                    (streamed_file.blocking_callback)(false);
                 }
             }
         }
+    }
+
+    /// Download a range of the file into the storage.  Transient failures are
+    /// retried with an exponential backoff, switching over to the next CDN host
+    /// if the current one seems to be at fault, or resolving the URL again if
+    /// the CDN refuses it, until the download succeeds, is cancelled, or runs
+    /// out of attempts.  Once the whole file is downloaded, copy it to the cache.
+    fn download_range(
+        &self,
+        offset: u64,
+        length: u64,
+        throughput: &Throughput,
+        is_cancelled: impl Fn() -> bool,
+    ) -> Result<(), Error> {
+        let end = offset + length;
+        let mut writer = self.storage.writer()?;
+        let mut position = offset;
+        let mut backoff = INITIAL_RETRY_BACKOFF;
+        let mut attempts = 1;
+        loop {
+            let result = self.fresh_url().and_then(|url| {
+                load_range(
+                    &mut writer,
+                    &self.cdn,
+                    url.url(),
+                    position,
+                    end - position,
+                    throughput,
+                    &is_cancelled,
+                )
+                .map_err(|err| {
+                    if cdn::is_url_refused(&err) {
+                        self.expire_url(&url);
+                    } else if cdn::is_host_error(&err) {
+                        self.rotate_url(&url);
+                    }
+                    err
+                })
+            });
+            match result {
+                Ok(_) => break,
+                Err(err)
+                    if cdn::is_transient_error(&err)
+                        && attempts < MAX_DOWNLOAD_ATTEMPTS
+                        && !is_cancelled() =>
+                {
+                    log::warn!("failed to download, retrying in {:?}: {}", backoff, err);
+                    attempts += 1;
+                    // Continue right after the data that made it into the storage.
+                    position = writer.stream_position()?.clamp(position, end);
+                    sleep_unless_cancelled(backoff, &is_cancelled);
+                    backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+                }
+                Err(err) => {
+                    // Range failed to download, let the readers waiting for it know, and
+                    // remove it from the requested set, so it can be requested again.
+                    writer.mark_as_failed(position, end - position);
+                    return Err(err);
+                }
+            }
+        }
+        let file_id = self.path.file_id;
+        if writer.is_complete() && !self.cache.has_audio_file(file_id) {
+            let file_path = self.storage.path().to_path_buf();
+            if let Err(err) = self.cache.save_audio_file(file_id, file_path) {
+                log::warn!("failed to save audio file to cache: {:?}", err);
+            }
+        }
         Ok(())
     }
+
+    fn fresh_url(&self) -> Result<CdnUrl, Error> {
+        let mut url = self.url.lock().expect("Failed to acquire URL lock");
+        if url.is_expired() {
+            *url = self.cdn.resolve_audio_file_url(self.path.file_id)?;
+        }
+        Ok(url.clone())
+    }
+
+    /// Make the next download resolve the URL again, unless other download did
+    /// it already since `refused` was in use.
+    fn expire_url(&self, refused: &CdnUrl) {
+        let mut url = self.url.lock().expect("Failed to acquire URL lock");
+        if url.url() == refused.url() {
+            log::info!("CDN has refused the URL, resolving it again");
+            url.expire();
+        }
+    }
+
+    /// Switch to the next CDN host, unless other download did it already since
+    /// `failed` was in use.
+    fn rotate_url(&self, failed: &CdnUrl) {
+        let mut url = self.url.lock().expect("Failed to acquire URL lock");
+        if url.url() == failed.url() && url.host_count() > 1 {
+            url.rotate();
+            log::info!("switching to next CDN host: {:?}", url.url());
+        }
+    }
+}
+
+// Delays between the attempts to download a range.
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(8);
+// Attempts to download a range before giving up, about half a minute with the
+// backoff above.
+const MAX_DOWNLOAD_ATTEMPTS: usize = 8;
+
+fn sleep_unless_cancelled(duration: Duration, is_cancelled: impl Fn() -> bool) {
+    const STEP: Duration = Duration::from_millis(100);
+    let mut remaining = duration;
+    while remaining > Duration::default() && !is_cancelled() {
+        let step = remaining.min(STEP);
+        thread::sleep(step);
+        remaining -= step;
+    }
+}
+
+/// Number of threads downloading the ranges of all streamed files.
+const DOWNLOAD_WORKERS: usize = 4;
+
+/// Queued ranges of a file that are adjacent to each other are downloaded in a
+/// single request, up to this length.
+const MAX_DOWNLOAD_LENGTH: u64 = 1024 * 1024;
+
+/// Downloads the requested ranges of streamed files on a fixed pool of worker
+/// threads.  Ranges a reader is blocked on are downloaded before the ones that
+/// are only prefetched, and ranges of files that have been dropped are never
+/// downloaded.
+#[derive(Clone)]
+pub struct DownloadScheduler {
+    pool: Arc<DownloadPool>,
+}
+
+impl DownloadScheduler {
+    pub fn new() -> Self {
+        let queue = Arc::new(DownloadQueue {
+            state: Mutex::new(DownloadQueueState {
+                downloads: Vec::new(),
+                next_order: 0,
+                is_closed: false,
+            }),
+            condvar: Condvar::new(),
+            throughput: Throughput::default(),
+        });
+        for n in 0..DOWNLOAD_WORKERS {
+            thread::Builder::new()
+                .name(format!("cdn-download-{}", n))
+                .spawn({
+                    let queue = Arc::clone(&queue);
+                    move || {
+                        while let Some(download) = queue.pop() {
+                            download.run(&queue.throughput);
+                        }
+                    }
+                })
+                .expect("Failed to spawn download thread");
+        }
+        Self {
+            pool: Arc::new(DownloadPool { queue }),
+        }
+    }
+
+    fn schedule(&self, file: &Weak<StreamedFile>, offset: u64, length: u64) {
+        let mut state = self.pool.queue.lock();
+        let order = state.next_order;
+        state.next_order += 1;
+        state.downloads.push(Download {
+            file: file.clone(),
+            priority: DownloadPriority::Prefetch,
+            order,
+            offset,
+            length,
+        });
+        self.pool.queue.condvar.notify_one();
+    }
+
+    /// Measured download throughput, in bytes per second, or `None` if there
+    /// has not been enough downloading to tell.
+    pub fn throughput(&self) -> Option<u64> {
+        self.pool.queue.throughput.bytes_per_second()
+    }
+
+    /// Move the queued download of `offset` in front of the prefetched ranges.
+    /// Does nothing if the range is already being downloaded.
+    fn prioritize(&self, file: &Weak<StreamedFile>, offset: u64) {
+        let mut state = self.pool.queue.lock();
+        for download in &mut state.downloads {
+            if Weak::ptr_eq(&download.file, file) && download.contains(offset) {
+                download.priority = DownloadPriority::Blocked;
+            }
+        }
+    }
+}
+
+impl Default for DownloadScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Shared by all clones of the scheduler, stops the workers once the last one is
+// dropped.
+struct DownloadPool {
+    queue: Arc<DownloadQueue>,
+}
+
+impl Drop for DownloadPool {
+    fn drop(&mut self) {
+        self.queue.lock().is_closed = true;
+        self.queue.condvar.notify_all();
+    }
+}
+
+struct DownloadQueue {
+    state: Mutex<DownloadQueueState>,
+    condvar: Condvar,
+    throughput: Throughput,
+}
+
+struct DownloadQueueState {
+    downloads: Vec<Download>,
+    // Order of the next scheduled download, downloads of the same priority are
+    // taken first come, first served.
+    next_order: u64,
+    is_closed: bool,
+}
+
+impl DownloadQueue {
+    fn lock(&self) -> MutexGuard<'_, DownloadQueueState> {
+        self.state
+            .lock()
+            .expect("Failed to acquire download queue lock")
+    }
+
+    /// Block until there is a download to run, and take it out of the queue.
+    /// Returns `None` once the scheduler is dropped.
+    fn pop(&self) -> Option<Download> {
+        let mut state = self.lock();
+        loop {
+            if state.is_closed {
+                return None;
+            }
+            // Forget the downloads of files that have been dropped.
+            state
+                .downloads
+                .retain(|download| download.file.strong_count() > 0);
+            if let Some(download) = state.take_next() {
+                return Some(download);
+            }
+            state = self
+                .condvar
+                .wait(state)
+                .expect("Failed to wait for a download");
+        }
+    }
+}
+
+impl DownloadQueueState {
+    fn take_next(&mut self) -> Option<Download> {
+        let (index, _) = self
+            .downloads
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, download)| (download.priority, Reverse(download.order)))?;
+        let mut download = self.downloads.swap_remove(index);
+        // Merge in the queued ranges of the same file that overlap or touch the
+        // taken one, so they are fetched in one request.
+        while let Some(index) = self
+            .downloads
+            .iter()
+            .position(|other| download.can_merge(other))
+        {
+            let other = self.downloads.swap_remove(index);
+            download.merge(other);
+        }
+        Some(download)
+    }
+}
+
+// Downloading time the throughput is averaged over.  Older measurements fade
+// out.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(20);
+
+// Least downloading time needed to estimate the throughput.
+const THROUGHPUT_MIN_TIME: Duration = Duration::from_secs(1);
+
+/// Aggregate throughput of all downloads.  Only the time with at least one
+/// download running is counted, so idle periods do not lower the estimate, and
+/// parallel downloads add up.
+#[derive(Default)]
+struct Throughput {
+    state: Mutex<ThroughputState>,
+}
+
+#[derive(Default)]
+struct ThroughputState {
+    running: usize,
+    // Start of the busy period that is not yet counted in `busy_time`.
+    busy_since: Option<Instant>,
+    bytes: f64,
+    busy_time: f64,
+}
+
+impl Throughput {
+    fn start(&self) -> ThroughputMeasurement<'_> {
+        let mut state = self.lock();
+        if state.running == 0 {
+            state.busy_since = Some(Instant::now());
+        }
+        state.running += 1;
+        ThroughputMeasurement {
+            throughput: self,
+            bytes: 0,
+        }
+    }
+
+    fn finish(&self, bytes: u64) {
+        let mut state = self.lock();
+        let now = Instant::now();
+        if let Some(busy_since) = state.busy_since.take() {
+            state.busy_time += now.duration_since(busy_since).as_secs_f64();
+        }
+        state.running -= 1;
+        if state.running > 0 {
+            state.busy_since = Some(now);
+        }
+        state.bytes += bytes as f64;
+        let window = THROUGHPUT_WINDOW.as_secs_f64();
+        if state.busy_time > window {
+            let fade = window / state.busy_time;
+            state.bytes *= fade;
+            state.busy_time *= fade;
+        }
+    }
+
+    fn bytes_per_second(&self) -> Option<u64> {
+        let state = self.lock();
+        if state.busy_time < THROUGHPUT_MIN_TIME.as_secs_f64() {
+            None
+        } else {
+            Some((state.bytes / state.busy_time) as u64)
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ThroughputState> {
+        self.state
+            .lock()
+            .expect("Failed to acquire throughput lock")
+    }
+}
+
+/// Running download, counted in the throughput until dropped.
+struct ThroughputMeasurement<'a> {
+    throughput: &'a Throughput,
+    bytes: u64,
+}
+
+impl ThroughputMeasurement<'_> {
+    fn add(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+}
+
+impl Drop for ThroughputMeasurement<'_> {
+    fn drop(&mut self) {
+        self.throughput.finish(self.bytes);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DownloadPriority {
+    Prefetch,
+    Blocked,
+}
+
+struct Download {
+    file: Weak<StreamedFile>,
+    priority: DownloadPriority,
+    order: u64,
+    offset: u64,
+    length: u64,
+}
+
+impl Download {
+    fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+
+    fn contains(&self, offset: u64) -> bool {
+        self.offset <= offset && offset < self.end()
+    }
+
+    fn can_merge(&self, other: &Self) -> bool {
+        let start = self.offset.min(other.offset);
+        let end = self.end().max(other.end());
+        Weak::ptr_eq(&self.file, &other.file)
+            && other.offset <= self.end()
+            && self.offset <= other.end()
+            && end - start <= MAX_DOWNLOAD_LENGTH
+    }
+
+    fn merge(&mut self, other: Self) {
+        let start = self.offset.min(other.offset);
+        let end = self.end().max(other.end());
+        self.offset = start;
+        self.length = end - start;
+        self.priority = self.priority.max(other.priority);
+        self.order = self.order.min(other.order);
+    }
+
+    fn run(self, throughput: &Throughput) {
+        let streamed_file = match self.file.upgrade() {
+            Some(streamed_file) => streamed_file,
+            None => return,
+        };
+        // In case we hold the last reference, the file has been dropped in the
+        // meantime, and there is no point in finishing the download.
+        let is_cancelled = || Arc::strong_count(&streamed_file) == 1;
+        if let Err(err) =
+            streamed_file.download_range(self.offset, self.length, throughput, is_cancelled)
+        {
+            log::error!("failed to download: {}", err);
+        }
+    }
 }
 
 pub struct CachedFile {
@@ -235,9 +762,14 @@ fn load_range(
     url: &str,
     offset: u64,
     length: u64,
+    throughput: &Throughput,
+    is_cancelled: impl Fn() -> bool,
 ) -> Result<(), Error> {
     log::trace!("downloading {}..{}", offset, offset + length);
 
+    // Measure from the request on, the latency is a part of the throughput we get.
+    let mut measurement = throughput.start();
+
     // Download range of data from the CDN.  Block until we a have reader of the
     // request body.
     let (_total_length, mut reader) = cdn.fetch_file_range(url, offset, length)?;
@@ -245,7 +777,23 @@ fn load_range(
     // Pipe it into storage. Blocks until fully written, but readers sleeping on
     // this file should be notified as soon as their offset is covered.
     writer.seek(SeekFrom::Start(offset))?;
-    io::copy(&mut reader, writer)?;
+    let mut buf = [0; 1024 * 16];
+    loop {
+        if is_cancelled() {
+            log::trace!("download cancelled at {}", writer.stream_position()?);
+            break;
+        }
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // Tell the network errors apart from the local ones, see
+            // `cdn::is_transient_error`.
+            Err(err) => return Err(Error::AudioFetchingError(Box::new(err))),
+        };
+        writer.write_all(&buf[..read])?;
+        measurement.add(read as u64);
+    }
 
     Ok(())
 }
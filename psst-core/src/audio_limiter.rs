@@ -0,0 +1,228 @@
+use std::{collections::VecDeque, time::Duration};
+
+use crate::{audio_output::AudioSample, audio_resample::AudioFormat};
+
+// How far ahead the limiter sees the peaks coming.  Delays the audio by the
+// same amount.
+const LOOKAHEAD: Duration = Duration::from_millis(5);
+
+// Points between two samples at which the peak detector interpolates the
+// waveform, i.e. it is oversampled 4x.
+const INTERPOLATED_POINTS: [f32; 3] = [0.25, 0.5, 0.75];
+
+// Interpolated peaks are found between the two samples preceding the newest
+// one, so the audio is delayed by this many frames more than the look-ahead.
+const DETECTOR_DELAY: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimiterConfig {
+    pub enabled: bool,
+    /// Level the output never exceeds, in dBFS.
+    pub threshold: f32,
+    /// How long it takes the gain to recover after a peak.
+    pub release: Duration,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: -1.0,
+            release: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Look-ahead peak limiter, working on an interleaved stream of samples.  Gain
+/// is computed from the loudest channel, and is lowered smoothly over the
+/// look-ahead window, so it reaches the required level right when the peak
+/// leaves the delay line.  Peaks are detected on an oversampled waveform, so
+/// the inter-sample peaks stay under the threshold as well.
+pub struct Limiter {
+    config: LimiterConfig,
+    format: AudioFormat,
+    // Linear threshold.
+    threshold: f32,
+    // Look-ahead, in frames.
+    lookahead: usize,
+    // Delayed samples, `lookahead + DETECTOR_DELAY` frames long, and how many of
+    // them are audio rather than the silence it started with.
+    delay: VecDeque<AudioSample>,
+    pending: usize,
+    // Last three samples of each channel, oldest first.
+    history: Vec<[AudioSample; 3]>,
+    // Peak of the frame being pushed, and the channel of the next sample.
+    peak: f32,
+    channel: usize,
+    // Index of the last frame, and the increasing sequence of the gains required
+    // by the frames in the look-ahead window, so the front is the minimum.
+    frame: u64,
+    minimum: VecDeque<(u64, f32)>,
+    // Window minimums of the last `lookahead` frames, averaged to smooth the attack.
+    attack: VecDeque<f32>,
+    attack_sum: f64,
+    // Gain applied to the output.
+    gain: f32,
+    // Per-frame release coefficient.
+    release: f32,
+    // Set for items that are not normalized, the gain only recovers then.
+    bypass: bool,
+}
+
+impl Limiter {
+    pub fn new(config: LimiterConfig, format: AudioFormat) -> Self {
+        let mut limiter = Self {
+            config,
+            format,
+            threshold: 1.0,
+            lookahead: 1,
+            delay: VecDeque::new(),
+            pending: 0,
+            history: Vec::new(),
+            peak: 0.0,
+            channel: 0,
+            frame: 0,
+            minimum: VecDeque::new(),
+            attack: VecDeque::new(),
+            attack_sum: 0.0,
+            gain: 1.0,
+            release: 1.0,
+            bypass: false,
+        };
+        limiter.set_format(format);
+        limiter
+    }
+
+    pub fn set_config(&mut self, config: LimiterConfig) {
+        self.config = config;
+        self.update_parameters();
+    }
+
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.format = format;
+        self.lookahead =
+            ((LOOKAHEAD.as_secs_f64() * f64::from(format.sample_rate)) as usize).max(1);
+        self.update_parameters();
+        self.reset();
+    }
+
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.bypass = bypass;
+    }
+
+    /// Throw away the delayed samples and the gain state, i.e. after seeking.
+    pub fn reset(&mut self) {
+        let channels = usize::from(self.format.channels.max(1));
+        self.delay.clear();
+        self.delay
+            .resize((self.lookahead + DETECTOR_DELAY) * channels, 0.0);
+        self.pending = 0;
+        self.history.clear();
+        self.history.resize(channels, [0.0; 3]);
+        self.peak = 0.0;
+        self.channel = 0;
+        self.minimum.clear();
+        self.attack.clear();
+        self.attack_sum = 0.0;
+        self.gain = 1.0;
+    }
+
+    pub fn process(&mut self, sample: AudioSample) -> AudioSample {
+        self.pending = (self.pending + 1).min(self.delay.len());
+        self.push(sample)
+    }
+
+    /// Returns the next of the delayed samples, pushing silence in its place, or
+    /// `None` if all of them have been returned already.  Used to play out the
+    /// end of the stream.
+    pub fn drain(&mut self) -> Option<AudioSample> {
+        if self.pending == 0 {
+            return None;
+        }
+        self.pending -= 1;
+        Some(self.push(0.0))
+    }
+
+    fn push(&mut self, sample: AudioSample) -> AudioSample {
+        self.delay.push_back(sample);
+        let peak = self.detect_peak(sample);
+        self.peak = self.peak.max(peak);
+        self.channel += 1;
+        if self.channel >= usize::from(self.format.channels.max(1)) {
+            self.channel = 0;
+            let peak = self.peak;
+            self.peak = 0.0;
+            self.push_frame_peak(peak);
+        }
+        self.delay.pop_front().unwrap_or(0.0) * self.gain
+    }
+
+    /// Peak of the waveform from the sample preceding the previous one of the
+    /// channel, up to `sample`.  Points in between the samples are interpolated
+    /// with a Catmull-Rom spline.
+    fn detect_peak(&mut self, sample: AudioSample) -> f32 {
+        let [x0, x1, x2] = self.history[self.channel];
+        self.history[self.channel] = [x1, x2, sample];
+        let x3 = sample;
+        let a = -x0 + 3.0 * x1 - 3.0 * x2 + x3;
+        let b = 2.0 * x0 - 5.0 * x1 + 4.0 * x2 - x3;
+        let c = -x0 + x2;
+        let d = 2.0 * x1;
+        INTERPOLATED_POINTS
+            .iter()
+            .map(|&t| (0.5 * (((a * t + b) * t + c) * t + d)).abs())
+            .fold(x3.abs(), f32::max)
+    }
+
+    fn is_active(&self) -> bool {
+        self.config.enabled && !self.bypass
+    }
+
+    fn push_frame_peak(&mut self, peak: f32) {
+        let required = if self.is_active() && peak > self.threshold {
+            self.threshold / peak
+        } else {
+            1.0
+        };
+
+        // Minimum of the required gains over the look-ahead window.
+        self.frame += 1;
+        while matches!(self.minimum.back(), Some(&(_, gain)) if gain >= required) {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((self.frame, required));
+        let oldest = self.frame.saturating_sub(self.lookahead as u64);
+        while matches!(self.minimum.front(), Some(&(frame, _)) if frame <= oldest) {
+            self.minimum.pop_front();
+        }
+        let window_minimum = self.minimum.front().map_or(1.0, |&(_, gain)| gain);
+
+        // Moving average of the window minimums.  Every minimum in the average
+        // already includes the peak that is about to leave the delay line, so the
+        // average cannot be above the gain it requires.
+        self.attack.push_back(window_minimum);
+        self.attack_sum += f64::from(window_minimum);
+        if self.attack.len() > self.lookahead {
+            if let Some(oldest) = self.attack.pop_front() {
+                self.attack_sum -= f64::from(oldest);
+            }
+        }
+        let target = (self.attack_sum / self.attack.len() as f64) as f32;
+
+        self.gain = if target < self.gain {
+            target
+        } else {
+            self.gain + (target - self.gain) * self.release
+        };
+    }
+
+    fn update_parameters(&mut self) {
+        self.threshold = 10.0_f32.powf(self.config.threshold.min(0.0) / 20.0);
+        let release_frames = self.config.release.as_secs_f32() * self.format.sample_rate as f32;
+        self.release = if release_frames > 0.0 {
+            1.0 - (-1.0 / release_frames).exp()
+        } else {
+            1.0
+        };
+    }
+}
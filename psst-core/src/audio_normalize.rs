@@ -20,6 +20,18 @@ pub struct NormalizationData {
     album_peak: f32,
 }
 
+impl Default for NormalizationData {
+    // Used for files without any normalization data, keeps the gain untouched.
+    fn default() -> Self {
+        Self {
+            track_gain_db: 0.0,
+            track_peak: 1.0,
+            album_gain_db: 0.0,
+            album_peak: 1.0,
+        }
+    }
+}
+
 impl NormalizationData {
     pub fn parse(mut file: impl Read + Seek) -> io::Result<Self> {
         const NORMALIZATION_OFFSET: u64 = 144;
@@ -39,17 +51,26 @@ impl NormalizationData {
         })
     }
 
-    pub fn factor_for_level(&self, level: NormalizationLevel, pregain: f32) -> f32 {
-        match level {
-            NormalizationLevel::None => 1.0,
-            NormalizationLevel::Track => Self::factor(pregain, self.track_gain_db, self.track_peak),
-            NormalizationLevel::Album => Self::factor(pregain, self.album_gain_db, self.album_peak),
-        }
+    /// Gain factor of the given normalization level.  Unless `is_limited`, the
+    /// gain is capped so the peak of the audio does not clip.  Otherwise, the
+    /// peaks are left for the limiter, and the gain is applied uniformly.
+    pub fn factor_for_level(
+        &self,
+        level: NormalizationLevel,
+        pregain: f32,
+        is_limited: bool,
+    ) -> f32 {
+        let (gain, peak) = match level {
+            NormalizationLevel::None => return 1.0,
+            NormalizationLevel::Track => (self.track_gain_db, self.track_peak),
+            NormalizationLevel::Album => (self.album_gain_db, self.album_peak),
+        };
+        Self::factor(pregain, gain, peak, is_limited)
     }
 
-    fn factor(pregain: f32, gain: f32, peak: f32) -> f32 {
+    fn factor(pregain: f32, gain: f32, peak: f32, is_limited: bool) -> f32 {
         let mut nf = f32::powf(10.0, (pregain + gain) / 20.0);
-        if nf * peak > 1.0 {
+        if nf * peak > 1.0 && !is_limited {
             nf = 1.0 / peak;
         }
         nf
@@ -1,15 +1,19 @@
-use std::sync::{Arc, Mutex};
-
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use miniaudio::{Context, Device, DeviceConfig, DeviceType, Format};
+use miniaudio::{Backend, Context, Device, DeviceConfig, DeviceId, DeviceInfo, DeviceType, Format};
 
-use crate::error::Error;
+use crate::{
+    audio_sink::{AudioSink, AudioSourceHandle},
+    error::Error,
+};
 
 pub type AudioSample = f32;
 
 pub trait AudioSource: Iterator<Item = AudioSample> {
     fn channels(&self) -> u8;
     fn sample_rate(&self) -> u32;
+    /// Called after the output device is opened, source should produce samples
+    /// in the format the device is using from then on.
+    fn set_output_format(&mut self, channels: u8, sample_rate: u32);
     fn normalization_factor(&self) -> Option<f32>;
 }
 
@@ -18,6 +22,10 @@ pub struct AudioOutputRemote {
 }
 
 impl AudioOutputRemote {
+    pub(crate) fn new(event_sender: Sender<InternalEvent>) -> Self {
+        Self { event_sender }
+    }
+
     pub fn close(&self) {
         self.send(InternalEvent::Close);
     }
@@ -34,19 +42,55 @@ impl AudioOutputRemote {
         self.send(InternalEvent::SetVolume(volume));
     }
 
+    /// Switch the playback to a device with given ID, see `AudioOutput::devices`.
+    /// `None` selects the default device.
+    pub fn switch_device(&self, device_id: Option<String>) {
+        self.send(InternalEvent::SwitchDevice(device_id));
+    }
+
     fn send(&self, event: InternalEvent) {
         self.event_sender.send(event).expect("Audio output died");
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioDevice {
+    /// Opaque identifier of the device, stable across restarts.
+    pub id: String,
+    pub name: String,
+    pub backend: &'static str,
+}
+
+// Backends we try when enumerating the devices, with their stable names.  Backends
+// that are not available on the current platform fail to initialize, and are skipped.
+const BACKENDS: &[(Backend, &str)] = &[
+    (Backend::Wasapi, "wasapi"),
+    (Backend::DSound, "dsound"),
+    (Backend::WinMM, "winmm"),
+    (Backend::CoreAudio, "coreaudio"),
+    (Backend::PulseAudio, "pulseaudio"),
+    (Backend::Alsa, "alsa"),
+    (Backend::Jack, "jack"),
+    (Backend::Sndio, "sndio"),
+    (Backend::Audio4, "audio4"),
+    (Backend::OSS, "oss"),
+];
+
 pub struct AudioOutput {
     context: Context,
+    device_id: Option<String>,
     event_sender: Sender<InternalEvent>,
     event_receiver: Receiver<InternalEvent>,
 }
 
 impl AudioOutput {
     pub fn open() -> Result<Self, Error> {
+        Self::open_with_device(None)
+    }
+
+    /// Open the output with a device from `AudioOutput::devices`.  In case the
+    /// device is not available, default device is used instead.
+    pub fn open_with_device(device_id: Option<String>) -> Result<Self, Error> {
         let backends = &[]; // Use default backend order.
         let config = None; // Use default context config.
         let context = Context::new(backends, config)?;
@@ -56,83 +100,250 @@ impl AudioOutput {
 
         Ok(Self {
             context,
+            device_id,
             event_sender,
             event_receiver,
         })
     }
 
-    pub fn remote(&self) -> AudioOutputRemote {
-        AudioOutputRemote {
-            event_sender: self.event_sender.clone(),
+    /// Return names of audio backends available on this system.
+    pub fn backends() -> Vec<&'static str> {
+        BACKENDS
+            .iter()
+            .filter(|(backend, _)| Context::new(&[*backend], None).is_ok())
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// Enumerate playback devices of all available backends.
+    pub fn devices() -> Result<Vec<AudioDevice>, Error> {
+        let mut devices = Vec::new();
+        for &(backend, backend_name) in BACKENDS {
+            let context = match Context::new(&[backend], None) {
+                Ok(context) => context,
+                Err(_) => continue, // Backend is not available.
+            };
+            context.with_devices(|playback_devices, _capture_devices| {
+                let ids = device_ids(backend_name, playback_devices);
+                for (info, id) in playback_devices.iter().zip(ids) {
+                    devices.push(AudioDevice {
+                        id,
+                        name: info.name().to_string(),
+                        backend: backend_name,
+                    });
+                }
+            })?;
         }
+        Ok(devices)
+    }
+
+    /// Find the context and the miniaudio ID of a device, by its `AudioDevice::id`.
+    fn find_device(device_id: &str) -> Result<Option<(Context, DeviceId)>, Error> {
+        let backend_name = match device_id.split_once(':') {
+            Some((backend_name, _)) => backend_name,
+            None => return Ok(None),
+        };
+        let backend = match BACKENDS.iter().find(|(_, name)| *name == backend_name) {
+            Some(&(backend, _)) => backend,
+            None => return Ok(None),
+        };
+        let context = Context::new(&[backend], None)?;
+        let mut found = None;
+        context.with_devices(|playback_devices, _capture_devices| {
+            let ids = device_ids(backend_name, playback_devices);
+            found = playback_devices
+                .iter()
+                .zip(ids)
+                .find(|(_, id)| id == device_id)
+                .map(|(info, _)| info.id().clone());
+        })?;
+        Ok(found.map(|id| (context, id)))
+    }
+
+    /// Create a device from `config`, preferring the device with `device_id`, and
+    /// falling back to the default device of the default backend.
+    fn create_device(
+        &self,
+        config: &mut DeviceConfig,
+        device_id: Option<&str>,
+    ) -> Result<Device, Error> {
+        if let Some(device_id) = device_id {
+            match Self::find_device(device_id) {
+                Ok(Some((context, id))) => {
+                    config.playback_mut().set_device_id(Some(id));
+                    match Device::new(Some(context), config) {
+                        Ok(device) => {
+                            log::info!("using audio device {:?}", device_id);
+                            return Ok(device);
+                        }
+                        Err(err) => {
+                            log::error!("failed to open audio device {:?}: {}", device_id, err);
+                        }
+                    }
+                }
+                Ok(None) => {
+                    log::warn!("audio device {:?} not found, using default", device_id);
+                }
+                Err(err) => {
+                    log::error!("failed to find audio device {:?}: {}", device_id, err);
+                }
+            }
+        }
+        config.playback_mut().set_device_id(None);
+        let context = self.context.clone();
+        let device = Device::new(Some(context), config)?;
+        Ok(device)
+    }
+}
+
+/// Identifiers of the playback devices of a backend, see `AudioDevice::id`.
+/// Devices sharing a name, like two units of the same model, are told apart by
+/// their order, the first one keeps the plain name.
+fn device_ids(backend_name: &str, devices: &[DeviceInfo]) -> Vec<String> {
+    devices
+        .iter()
+        .enumerate()
+        .map(|(i, info)| {
+            let same_name_before = devices[..i]
+                .iter()
+                .filter(|other| other.name() == info.name())
+                .count();
+            if same_name_before == 0 {
+                format!("{}:{}", backend_name, info.name())
+            } else {
+                format!("{}:{}#{}", backend_name, info.name(), same_name_before + 1)
+            }
+        })
+        .collect()
+}
+
+impl AudioSink for AudioOutput {
+    fn remote(&self) -> AudioOutputRemote {
+        AudioOutputRemote::new(self.event_sender.clone())
     }
 
-    pub fn start_playback<T>(&self, source: Arc<Mutex<T>>) -> Result<(), Error>
-    where
-        T: AudioSource + Send + 'static,
-    {
+    fn start_playback(&self, source: AudioSourceHandle) -> Result<(), Error> {
         // Create a device config that describes the kind of device we want to use.
         let mut config = DeviceConfig::new(DeviceType::Playback);
 
         {
-            // Setup the device config for playback with the channel count and sample rate
-            // from the audio source.
+            // Setup the device config for playback with the channel count from the audio
+            // source.  We let the device pick its native sample rate, and the source is
+            // going to resample the audio itself.
             let source = source.lock().expect("Failed to acquire audio source lock");
             config.playback_mut().set_format(Format::F32);
             config.playback_mut().set_channels(source.channels().into());
-            config.set_sample_rate(source.sample_rate());
+            config.set_sample_rate(0);
         };
 
-        // Move the source into the config's data callback.  Callback will get cloned
+        // Move a handle to the source into the data callback.  Callback will get cloned
         // for each device we create.
-        config.set_data_callback(move |_device, output, _frames| {
-            let mut source = source.lock().expect("Failed to acquire audio source lock");
-            // Get the audio normalization factor.
-            let norm_factor = source.normalization_factor().unwrap_or(1.0);
-            // Fill the buffer with audio samples from the source.
-            for sample in output.as_samples_mut() {
-                let s = source.next().unwrap_or(0.0); // Use silence in case the
-                                                      // source has finished.
-                *sample = s * norm_factor;
+        config.set_data_callback({
+            let source = source.clone();
+            move |_device, output, _frames| {
+                let mut source = source.lock().expect("Failed to acquire audio source lock");
+                // Get the audio normalization factor.
+                let norm_factor = source.normalization_factor().unwrap_or(1.0);
+                // Fill the buffer with audio samples from the source.
+                for sample in output.as_samples_mut() {
+                    let s = source.next().unwrap_or(0.0); // Use silence in case the
+                                                          // source has finished.
+                    *sample = s * norm_factor;
+                }
             }
         });
 
-        let device = {
-            let context = self.context.clone();
-            Device::new(Some(context), &config)?
-        };
+        // Get notified when the device stops, so we can recover in case it has been
+        // disconnected.
+        config.set_stop_callback({
+            let event_sender = self.event_sender.clone();
+            move |_device| {
+                // Sending fails only if the output is closed, and we do not care then.
+                let _ = event_sender.send(InternalEvent::DeviceStopped);
+            }
+        });
+
+        let device = self.create_device(&mut config, self.device_id.as_deref())?;
+        set_source_output_format(&source, &device);
+        // Device is missing only in case we have failed to switch to another one.  We
+        // stay idle then, until another device is selected.
+        let mut device = Some(device);
+
+        // State we need to restore in case the device changes.
+        let mut is_playing = false;
+        let mut volume = None;
 
         for event in self.event_receiver.iter() {
             match event {
                 InternalEvent::Close => {
                     log::debug!("closing audio output");
-                    if device.is_started() {
-                        if let Err(err) = device.stop() {
-                            log::error!("failed to stop device: {}", err);
-                        }
+                    if let Some(device) = &device {
+                        stop_device(device);
                     }
                     break;
                 }
                 InternalEvent::Pause => {
                     log::debug!("pausing audio output");
-                    if device.is_started() {
-                        if let Err(err) = device.stop() {
-                            log::error!("failed to stop device: {}", err);
-                        }
+                    is_playing = false;
+                    if let Some(device) = &device {
+                        stop_device(device);
                     }
                 }
                 InternalEvent::Resume => {
                     log::debug!("resuming audio output");
-                    if !device.is_started() {
-                        if let Err(err) = device.start() {
-                            log::error!("failed to start device: {}", err);
+                    is_playing = true;
+                    if let Some(device) = &device {
+                        if !device.is_started() {
+                            if let Err(err) = device.start() {
+                                log::error!("failed to start device: {}", err);
+                            }
                         }
                     }
                 }
-                InternalEvent::SetVolume(volume) => {
+                InternalEvent::SetVolume(new_volume) => {
                     log::debug!("volume has changed");
-                    if let Err(err) = device.set_master_volume(volume as f32) {
-                        log::error!("failed to set volume: {}", err);
+                    volume.replace(new_volume);
+                    if let Some(device) = &device {
+                        if let Err(err) = device.set_master_volume(new_volume as f32) {
+                            log::error!("failed to set volume: {}", err);
+                        }
+                    }
+                }
+                InternalEvent::SwitchDevice(device_id) => {
+                    log::debug!("switching audio device");
+                    if let Some(device) = device.take() {
+                        stop_device(&device);
+                    }
+                    device = match self.create_device(&mut config, device_id.as_deref()) {
+                        Ok(device) => Some(device),
+                        Err(err) => {
+                            log::error!("failed to switch audio device: {}", err);
+                            None
+                        }
+                    };
+                    if let Some(device) = &device {
+                        restore_device_state(&source, device, is_playing, volume);
+                    }
+                }
+                InternalEvent::DeviceStopped => {
+                    let is_lost = match &device {
+                        Some(device) => is_playing && !device.is_started(),
+                        None => false,
+                    };
+                    if is_lost {
+                        // We did not ask the device to stop, it has most probably been
+                        // disconnected.  Fall back to the default device and keep playing.
+                        log::warn!("audio device stopped unexpectedly, switching to default");
+                        device = match self.create_device(&mut config, None) {
+                            Ok(device) => Some(device),
+                            Err(err) => {
+                                log::error!("failed to open default audio device: {}", err);
+                                None
+                            }
+                        };
+                        if let Some(device) = &device {
+                            restore_device_state(&source, device, is_playing, volume);
+                        }
                     }
                 }
             }
@@ -142,11 +353,48 @@ impl AudioOutput {
     }
 }
 
-enum InternalEvent {
+fn set_source_output_format(source: &AudioSourceHandle, device: &Device) {
+    // Let the source know the actual format of the device, before it gets started.
+    source
+        .lock()
+        .expect("Failed to acquire audio source lock")
+        .set_output_format(device.playback().channels() as u8, device.sample_rate());
+}
+
+fn stop_device(device: &Device) {
+    if device.is_started() {
+        if let Err(err) = device.stop() {
+            log::error!("failed to stop device: {}", err);
+        }
+    }
+}
+
+fn restore_device_state(
+    source: &AudioSourceHandle,
+    device: &Device,
+    is_playing: bool,
+    volume: Option<f64>,
+) {
+    set_source_output_format(source, device);
+    if let Some(volume) = volume {
+        if let Err(err) = device.set_master_volume(volume as f32) {
+            log::error!("failed to set volume: {}", err);
+        }
+    }
+    if is_playing {
+        if let Err(err) = device.start() {
+            log::error!("failed to start device: {}", err);
+        }
+    }
+}
+
+pub(crate) enum InternalEvent {
     Close,
     Pause,
     Resume,
     SetVolume(f64),
+    SwitchDevice(Option<String>),
+    DeviceStopped,
 }
 
 impl From<miniaudio::Error> for Error {
@@ -1,6 +1,10 @@
 use std::{
+    collections::VecDeque,
+    f32::consts::FRAC_PI_2,
     mem,
-    sync::{Arc, Mutex},
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex, Weak},
     thread,
     thread::JoinHandle,
     time::Duration,
@@ -9,34 +13,69 @@ use std::{
 use crossbeam_channel::{unbounded, Receiver, Sender};
 
 use crate::{
-    audio_file::{AudioFile, AudioPath, FileAudioSource},
+    audio_buffer::{audio_buffer, AudioBufferConsumer, AudioBufferProducer},
+    audio_equalizer::{Equalizer, EqualizerConfig},
+    audio_file::{AudioFile, AudioPath, DownloadScheduler, FileAudioSource},
     audio_key::AudioKey,
+    audio_limiter::{Limiter, LimiterConfig},
     audio_normalize::NormalizationLevel,
     audio_output::{AudioOutputRemote, AudioSample, AudioSource},
-    audio_queue::{Queue, QueueBehavior},
-    cache::CacheHandle,
+    audio_queue::{Queue, QueueBehavior, QueueTier},
+    audio_resample::{AudioFormat, AudioResampler},
+    cache::{CacheHandle, ResumePosition},
     cdn::CdnHandle,
     error::Error,
     item_id::{ItemId, ItemIdType},
-    metadata::{Fetch, ToAudioPath},
-    protocol::metadata::Track,
+    local_library::{LocalLibrary, LocalTrack},
+    metadata::{FetchCached, ToAudioPath, TrackExt},
+    protocol::metadata::{Episode, Track},
     session::SessionService,
 };
 
 const PREVIOUS_TRACK_THRESHOLD: Duration = Duration::from_secs(3);
 const STOP_AFTER_CONSECUTIVE_LOADING_FAILURES: usize = 3;
+const SAVE_RESUME_POSITION_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct PlaybackConfig {
     pub bitrate: usize,
+    /// Follow the measured download throughput, and play the upcoming items in
+    /// a lower bitrate than `bitrate` in case the connection is too slow.
+    pub adaptive_bitrate: bool,
     pub pregain: f32,
+    /// Length of the overlap between the end of a track and the beginning of the
+    /// following one.  Zero disables the crossfade.
+    pub crossfade: Duration,
+    /// Tracks at least this long remember the position the playback stopped at,
+    /// and resume from it the next time.  Podcast episodes always do.
+    pub resume_min_duration: Duration,
+    /// Fraction of the duration after which a resumable item is considered
+    /// finished, and starts from the beginning the next time.
+    pub resume_finished_threshold: f64,
+    /// Folder local files are played from, see `LocalLibrary`.
+    pub local_music_dir: Option<PathBuf>,
+    pub equalizer: EqualizerConfig,
+    /// Limiter keeping the normalized audio from clipping, so `pregain` can be
+    /// applied to all tracks the same.
+    pub limiter: LimiterConfig,
+}
+
+impl PlaybackConfig {
+    pub const MAX_CROSSFADE: Duration = Duration::from_secs(12);
 }
 
 impl Default for PlaybackConfig {
     fn default() -> Self {
         Self {
             bitrate: 320,
+            adaptive_bitrate: false,
             pregain: 3.0,
+            crossfade: Duration::default(),
+            resume_min_duration: Duration::from_secs(20 * 60),
+            resume_finished_threshold: 0.95,
+            local_music_dir: None,
+            equalizer: EqualizerConfig::default(),
+            limiter: LimiterConfig::default(),
         }
     }
 }
@@ -53,35 +92,119 @@ impl PlaybackItem {
         session: &SessionService,
         cdn: CdnHandle,
         cache: CacheHandle,
+        downloads: DownloadScheduler,
         config: &PlaybackConfig,
+        event_sender: Sender<PlayerEvent>,
     ) -> Result<LoadedPlaybackItem, Error> {
-        let path = load_audio_path(self.item_id, session, &cache, config)?;
-        let key = load_audio_key(&path, session, &cache)?;
-        let file = AudioFile::open(path, cdn, cache)?;
+        let (path, location) = load_audio_path(self.item_id, session, &cache, config)?;
+        // Only the files hosted by Spotify are encrypted.
+        let key = match location {
+            FileLocation::Spotify => Some(load_audio_key(&path, session, &cache)?),
+            FileLocation::External(_) | FileLocation::Local(_) => None,
+        };
+        let blocking_callback = move |is_blocked| {
+            // Sending fails only if the player is gone, and we do not care then.
+            let _ = event_sender.send(PlayerEvent::StreamBlocked { path, is_blocked });
+        };
+        let file = match location {
+            FileLocation::Spotify => {
+                AudioFile::open(path, None, cdn, cache, downloads, blocking_callback)?
+            }
+            FileLocation::External(url) => {
+                AudioFile::open(path, Some(url), cdn, cache, downloads, blocking_callback)?
+            }
+            FileLocation::Local(file_path) => AudioFile::open_local(path, file_path)?,
+        };
         let (source, norm_data) = file.audio_source(key)?;
-        let norm_factor = norm_data.factor_for_level(self.norm_level, config.pregain);
+        let norm_factor =
+            norm_data.factor_for_level(self.norm_level, config.pregain, config.limiter.enabled);
         Ok(LoadedPlaybackItem {
             file,
             source,
+            norm_level: self.norm_level,
             norm_factor,
         })
     }
 }
 
+/// Where the contents of an audio file come from.
+enum FileLocation {
+    /// Encrypted file from the Spotify CDN.
+    Spotify,
+    /// File hosted outside of Spotify, at the given URL.
+    External(String),
+    /// File of the local music folder.
+    Local(PathBuf),
+}
+
+/// Resolve the audio file of `item_id`, and the location of its contents.
 fn load_audio_path(
     item_id: ItemId,
     session: &SessionService,
     cache: &CacheHandle,
     config: &PlaybackConfig,
-) -> Result<AudioPath, Error> {
+) -> Result<(AudioPath, FileLocation), Error> {
     match item_id.id_type {
         ItemIdType::Track => {
-            load_audio_path_from_track_or_alternative(item_id, session, cache, config)
+            let path = load_audio_path_from_track_or_alternative(item_id, session, cache, config)?;
+            Ok((path, FileLocation::Spotify))
+        }
+        ItemIdType::Podcast => load_audio_path_from_episode(item_id, session, cache, config),
+        ItemIdType::LocalFile => load_audio_path_from_local_file(item_id, config),
+        ItemIdType::Album | ItemIdType::Artist | ItemIdType::Show | ItemIdType::Unknown => {
+            Err(Error::AudioFileNotFound)
         }
-        ItemIdType::Podcast | ItemIdType::Unknown => unimplemented!(),
     }
 }
 
+fn load_audio_path_from_episode(
+    item_id: ItemId,
+    session: &SessionService,
+    cache: &CacheHandle,
+    config: &PlaybackConfig,
+) -> Result<(AudioPath, FileLocation), Error> {
+    let episode = load_episode(item_id, session, cache)?;
+    if let Some(user_country) = get_country_code(session, cache) {
+        if episode.is_restricted_in_region(&user_country) {
+            return Err(Error::AudioFileNotFound);
+        }
+    }
+    if let Some(path) = episode.to_audio_path(config.bitrate, cache) {
+        return Ok((path, FileLocation::Spotify));
+    }
+    // Episode is not hosted by Spotify, stream it from the publisher.
+    let url = episode.external_url.ok_or(Error::AudioFileNotFound)?;
+    let duration = Duration::from_millis(episode.duration.unwrap_or(0) as u64);
+    let path = AudioPath {
+        item_id,
+        played_item_id: item_id,
+        file_id: AudioFile::external_file_id(&url),
+        file_format: AudioFile::EXTERNAL_FORMAT,
+        duration,
+    };
+    Ok((path, FileLocation::External(url)))
+}
+
+fn load_audio_path_from_local_file(
+    item_id: ItemId,
+    config: &PlaybackConfig,
+) -> Result<(AudioPath, FileLocation), Error> {
+    let dir = config
+        .local_music_dir
+        .as_ref()
+        .ok_or(Error::AudioFileNotFound)?;
+    let file_path = LocalLibrary::find_file(dir, item_id).ok_or(Error::AudioFileNotFound)?;
+    let track = LocalTrack::read(dir, file_path)?;
+    let path = AudioPath {
+        item_id,
+        played_item_id: item_id,
+        file_id: track.file_id,
+        file_format: AudioFile::LOCAL_FORMAT,
+        duration: track.duration,
+    };
+    Ok((path, FileLocation::Local(track.path)))
+}
+
 fn load_audio_path_from_track_or_alternative(
     item_id: ItemId,
     session: &SessionService,
@@ -99,11 +222,11 @@ fn load_audio_path_from_track_or_alternative(
                 .ok_or(Error::AudioFileNotFound)?;
             let alt_track = load_track(alt_id, session, cache)?;
             let alt_path = alt_track
-                .to_audio_path(config.bitrate)
+                .to_audio_path(config.bitrate, cache)
                 .ok_or(Error::AudioFileNotFound)?;
-            // We've found an alternative track with a fitting audio file.  Let's cheat a
-            // little and pretend we've obtained it from the requested track.
-            // TODO: We should be honest and display the real track information.
+            // We've found an alternative track with a fitting audio file.  Keep the
+            // requested track as the identity of the item, but remember which track
+            // is really playing.
             AudioPath {
                 item_id,
                 ..alt_path
@@ -113,7 +236,7 @@ fn load_audio_path_from_track_or_alternative(
             // Either we do not have a country code loaded or the track is available, return
             // it.
             track
-                .to_audio_path(config.bitrate)
+                .to_audio_path(config.bitrate, cache)
                 .ok_or(Error::AudioFileNotFound)?
         }
     };
@@ -137,15 +260,15 @@ fn load_track(
     session: &SessionService,
     cache: &CacheHandle,
 ) -> Result<Track, Error> {
-    if let Some(cached_track) = cache.get_track(item_id) {
-        Ok(cached_track)
-    } else {
-        let track = Track::fetch(session, item_id)?;
-        if let Err(err) = cache.save_track(item_id, &track) {
-            log::warn!("failed to save track to cache: {:?}", err);
-        }
-        Ok(track)
-    }
+    Track::fetch_cached(session, cache, item_id)
+}
+
+fn load_episode(
+    item_id: ItemId,
+    session: &SessionService,
+    cache: &CacheHandle,
+) -> Result<Episode, Error> {
+    Episode::fetch_cached(session, cache, item_id)
 }
 
 fn load_audio_key(
@@ -153,22 +276,51 @@ fn load_audio_key(
     session: &SessionService,
     cache: &CacheHandle,
 ) -> Result<AudioKey, Error> {
-    if let Some(cached_key) = cache.get_audio_key(path.item_id, path.file_id) {
+    // Keys belong to the track the file has been obtained from.
+    let item_id = path.played_item_id;
+    if let Some(cached_key) = cache.get_audio_key(item_id, path.file_id) {
         Ok(cached_key)
     } else {
-        let key = session
-            .connected()?
-            .get_audio_key(path.item_id, path.file_id)?;
-        if let Err(err) = cache.save_audio_key(path.item_id, path.file_id, &key) {
+        let key = session.connected()?.get_audio_key(item_id, path.file_id)?;
+        if let Err(err) = cache.save_audio_key(item_id, path.file_id, &key) {
             log::warn!("failed to save audio key to cache: {:?}", err);
         }
         Ok(key)
     }
 }
 
+// Throughput needed to keep playing in a bitrate, as a multiple of it.  We step
+// up only with a bigger margin than we step down with, so the bitrate does not
+// flip back and forth.
+const BITRATE_DOWN_HEADROOM: u64 = 2;
+const BITRATE_UP_HEADROOM: u64 = 4;
+
+/// Pick the bitrate, at most `max_bitrate`, that the download `throughput` (in
+/// bytes per second) can sustain, starting from the `current` one.
+fn adapt_bitrate(current: usize, max_bitrate: usize, throughput: u64) -> usize {
+    let kbits = throughput * 8 / 1000;
+    let sustains = |bitrate: usize, headroom: u64| kbits >= bitrate as u64 * headroom;
+    let allowed = AudioFile::BITRATES
+        .iter()
+        .copied()
+        .filter(|&bitrate| bitrate <= max_bitrate);
+    if sustains(current, BITRATE_DOWN_HEADROOM) {
+        allowed
+            .filter(|&bitrate| bitrate <= current || sustains(bitrate, BITRATE_UP_HEADROOM))
+            .max()
+            .unwrap_or(current)
+    } else {
+        allowed
+            .filter(|&bitrate| bitrate < current && sustains(bitrate, BITRATE_DOWN_HEADROOM))
+            .max()
+            .unwrap_or(AudioFile::BITRATES[0])
+    }
+}
+
 pub struct LoadedPlaybackItem {
     file: AudioFile,
     source: FileAudioSource,
+    norm_level: NormalizationLevel,
     norm_factor: f32,
 }
 
@@ -178,13 +330,27 @@ pub struct Player {
     session: SessionService,
     cdn: CdnHandle,
     cache: CacheHandle,
+    downloads: DownloadScheduler,
     config: PlaybackConfig,
+    // Bitrate the upcoming items are loaded in, in case of `adaptive_bitrate`.
+    adaptive_bitrate: usize,
     queue: Queue,
     event_sender: Sender<PlayerEvent>,
     event_receiver: Receiver<PlayerEvent>,
     audio_source: Arc<Mutex<PlayerAudioSource>>,
+    buffered_source: Arc<Mutex<BufferedAudioSource>>,
     audio_output_remote: AudioOutputRemote,
     consecutive_loading_failures: usize,
+    // Reading of the current item is waiting for the download.
+    is_stream_blocked: bool,
+    // Audio output has run out of decoded samples.
+    is_underrun: bool,
+    // Last blocking state reported through `PlayerEvent::Blocked`.
+    is_blocked: bool,
+    // Progress of the current item last saved as its resume position.
+    resume_saved_at: Duration,
+    // Buffered ranges last reported through `PlayerEvent::Buffered`.
+    reported_buffered: Option<(AudioPath, Vec<Range<Duration>>)>,
 }
 
 impl Player {
@@ -196,28 +362,55 @@ impl Player {
         audio_output_remote: AudioOutputRemote,
     ) -> Self {
         let (event_sender, event_receiver) = unbounded();
-        let audio_source = {
-            let event_sender = event_sender.clone();
-            Arc::new(Mutex::new(PlayerAudioSource::new(event_sender)))
-        };
+        let (marker_sender, marker_receiver) = unbounded();
+        let (producer, consumer) = audio_buffer(AUDIO_BUFFER_CAPACITY);
+        let audio_source = Arc::new(Mutex::new(PlayerAudioSource::new(
+            producer,
+            marker_sender,
+            event_sender.clone(),
+            config.equalizer.clone(),
+            config.limiter.clone(),
+        )));
+        let buffered_source = Arc::new(Mutex::new(BufferedAudioSource {
+            buffer: consumer,
+            markers: marker_receiver,
+            pending_marker: None,
+            source: audio_source.clone(),
+            event_sender: event_sender.clone(),
+            is_underrun: false,
+        }));
+        // Decode the audio in a separate thread, so the audio output never has to
+        // wait for I/O.
+        thread::spawn({
+            let audio_source = Arc::downgrade(&audio_source);
+            move || decode_into_buffer(audio_source)
+        });
         Self {
             session,
             cdn,
             cache,
+            downloads: DownloadScheduler::new(),
+            adaptive_bitrate: config.bitrate,
             config,
             event_sender,
             event_receiver,
             audio_source,
+            buffered_source,
             audio_output_remote,
             state: PlayerState::Stopped,
             preload: PreloadState::None,
             queue: Queue::new(),
             consecutive_loading_failures: 0,
+            is_stream_blocked: false,
+            is_underrun: false,
+            is_blocked: false,
+            resume_saved_at: Duration::default(),
+            reported_buffered: None,
         }
     }
 
     pub fn audio_source(&self) -> Arc<Mutex<impl AudioSource>> {
-        self.audio_source.clone()
+        self.buffered_source.clone()
     }
 
     pub fn event_sender(&self) -> Sender<PlayerEvent> {
@@ -242,21 +435,42 @@ impl Player {
             PlayerEvent::Progress { duration, path } => {
                 self.handle_progress(duration, path);
             }
-            PlayerEvent::Finished { .. } => {
-                self.handle_finished();
+            PlayerEvent::Finished { path } => {
+                self.handle_finished(path);
+            }
+            PlayerEvent::Failed { path, error } => {
+                log::error!("playback of {:?} failed: {}", path.item_id, error);
+            }
+            PlayerEvent::StreamBlocked { path, is_blocked } => {
+                self.handle_stream_blocked(path, is_blocked);
+            }
+            PlayerEvent::Underrun { is_underrun } => {
+                self.is_underrun = is_underrun;
+                self.report_blocking();
             }
             PlayerEvent::Loading { .. }
             | PlayerEvent::Playing { .. }
             | PlayerEvent::Pausing { .. }
             | PlayerEvent::Resuming { .. }
             | PlayerEvent::Stopped { .. }
-            | PlayerEvent::Blocked => {}
+            | PlayerEvent::Blocked { .. }
+            | PlayerEvent::Unblocked { .. }
+            | PlayerEvent::Buffered { .. }
+            | PlayerEvent::QueueChanged { .. } => {}
         };
     }
 
     fn handle_command(&mut self, cmd: PlayerCommand) {
         match cmd {
             PlayerCommand::LoadQueue { items, position } => self.load_queue(items, position),
+            PlayerCommand::RestoreQueue {
+                items,
+                positions,
+                position,
+                manual,
+                tier,
+                progress,
+            } => self.restore_queue(items, positions, position, manual, tier, progress),
             PlayerCommand::LoadAndPlay { item } => self.load_and_play(item),
             PlayerCommand::Preload { item } => self.preload(item),
             PlayerCommand::Pause => self.pause(),
@@ -267,8 +481,13 @@ impl Player {
             PlayerCommand::Stop => self.stop(),
             PlayerCommand::Seek { position } => self.seek(position),
             PlayerCommand::Configure { config } => self.configure(config),
-            PlayerCommand::SetQueueBehavior { behavior } => self.queue.set_behaviour(behavior),
+            PlayerCommand::SetQueueBehavior { behavior } => self.set_queue_behavior(behavior),
+            PlayerCommand::InsertNext { item } => self.insert_next(item),
+            PlayerCommand::Append { item } => self.append(item),
+            PlayerCommand::Remove { tier, index } => self.remove(tier, index),
+            PlayerCommand::Move { tier, from, to } => self.move_item(tier, from, to),
             PlayerCommand::SetVolume { volume } => self.set_volume(volume),
+            PlayerCommand::ClearResumePositions => self.clear_resume_positions(),
         }
     }
 
@@ -276,11 +495,13 @@ impl Player {
         match self.state {
             PlayerState::Loading {
                 item: requested_item,
+                autoplay,
+                start,
                 ..
             } if item == requested_item => match result {
                 Ok(loaded_item) => {
                     self.consecutive_loading_failures = 0;
-                    self.play_loaded(loaded_item);
+                    self.play_loaded(loaded_item, autoplay, start);
                 }
                 Err(err) => {
                     self.consecutive_loading_failures += 1;
@@ -308,6 +529,7 @@ impl Player {
                 Ok(loaded_item) => {
                     log::info!("preloaded audio file");
                     self.preload = PreloadState::Preloaded { item, loaded_item };
+                    self.queue_preloaded_item();
                 }
                 Err(err) => {
                     log::error!("failed to preload audio file, error while opening: {}", err);
@@ -329,24 +551,101 @@ impl Player {
                 log::warn!("received unexpected progress report");
             }
         }
+        if progress < self.resume_saved_at
+            || progress - self.resume_saved_at >= SAVE_RESUME_POSITION_INTERVAL
+        {
+            self.save_resume_position(path, progress);
+        }
+        self.report_buffered();
         const PRELOAD_BEFORE_END_OF_TRACK: Duration = Duration::from_secs(30);
         if let Some(&item_to_preload) = self.queue.get_following() {
             let time_until_end_of_track = path.duration.checked_sub(progress).unwrap_or_default();
             if time_until_end_of_track <= PRELOAD_BEFORE_END_OF_TRACK {
                 self.preload(item_to_preload);
+                self.queue_preloaded_item();
             }
         }
     }
 
-    fn handle_finished(&mut self) {
+    fn handle_finished(&mut self, finished_path: AudioPath) {
+        match self.state {
+            PlayerState::Playing { path, .. } | PlayerState::Paused { path, .. }
+                if path == finished_path => {}
+            _ => {
+                log::info!("stale finish report received, ignoring");
+                return;
+            }
+        }
+        if self.is_resumable(&finished_path) {
+            self.store_resume_position(finished_path.item_id, ResumePosition::Finished);
+        }
         self.queue.skip_to_following();
         if let Some(&item) = self.queue.get_current() {
-            self.load_and_play(item);
+            match self.preload {
+                PreloadState::Queued {
+                    item: queued_item,
+                    path,
+                } if queued_item == item => {
+                    // Audio source has already switched to the queued item, we only need
+                    // to catch up with it.
+                    self.preload = PreloadState::None;
+                    self.continue_with_queued(path);
+                }
+                _ => {
+                    self.load_and_play(item);
+                }
+            }
         } else {
             self.stop();
         }
     }
 
+    fn handle_stream_blocked(&mut self, blocked_path: AudioPath, is_blocked: bool) {
+        match self.state {
+            PlayerState::Playing { path, .. } | PlayerState::Paused { path, .. }
+                if path == blocked_path =>
+            {
+                self.is_stream_blocked = is_blocked;
+                self.report_blocking();
+            }
+            _ => {
+                // Either a preloaded item, or an item that is not playing anymore.
+            }
+        }
+    }
+
+    /// Report a change of the blocking state of the playing item, either to
+    /// `Blocked` or `Unblocked`.  Blocking is only reported during playback.
+    fn report_blocking(&mut self) {
+        let path = match self.state {
+            PlayerState::Playing { path, .. } => path,
+            _ => return,
+        };
+        let is_blocked = self.is_stream_blocked || self.is_underrun;
+        if is_blocked == self.is_blocked {
+            return;
+        }
+        self.is_blocked = is_blocked;
+        if is_blocked {
+            log::info!("playback is blocked");
+            self.event_sender
+                .send(PlayerEvent::Blocked { path })
+                .expect("Failed to send PlayerEvent::Blocked");
+        } else {
+            log::info!("playback is unblocked");
+            self.event_sender
+                .send(PlayerEvent::Unblocked { path })
+                .expect("Failed to send PlayerEvent::Unblocked");
+        }
+    }
+
+    /// Start tracking the blocking state of a new playing item.
+    fn reset_blocking(&mut self) {
+        self.is_stream_blocked = false;
+        self.is_blocked = false;
+        self.report_blocking();
+    }
+
     fn load_queue(&mut self, items: Vec<PlaybackItem>, position: usize) {
         self.queue.fill(items, position);
         if let Some(&item) = self.queue.get_current() {
@@ -356,7 +655,34 @@ impl Player {
         }
     }
 
+    fn restore_queue(
+        &mut self,
+        items: Vec<PlaybackItem>,
+        positions: Vec<usize>,
+        position: usize,
+        manual: Vec<PlaybackItem>,
+        tier: QueueTier,
+        progress: Duration,
+    ) {
+        self.queue.restore(items, positions, position, manual, tier);
+        if let Some(&item) = self.queue.get_current() {
+            self.load(item, false, progress);
+        } else {
+            self.stop();
+        }
+    }
+
     fn load_and_play(&mut self, item: PlaybackItem) {
+        self.load(item, true, Duration::default());
+    }
+
+    /// Load `item` and start playing it from `start`, or only seek to `start`
+    /// and stay paused if `autoplay` is false.
+    fn load(&mut self, item: PlaybackItem, autoplay: bool, start: Duration) {
+        self.report_queue();
+        // If we have handed a preloaded item over to the audio source, take it back,
+        // because the current item is changing.
+        self.unqueue_preloaded_item();
         // Check if the item is already preloaded, and if so, take it out of the
         // preloader state, and start the playback.
         match mem::replace(&mut self.preload, PreloadState::None) {
@@ -364,7 +690,7 @@ impl Player {
                 item: preloaded_item,
                 loaded_item,
             } if preloaded_item == item => {
-                self.play_loaded(loaded_item);
+                self.play_loaded(loaded_item, autoplay, start);
                 return;
             }
             preloading_or_none => {
@@ -379,9 +705,17 @@ impl Player {
             let session = self.session.clone();
             let cdn = self.cdn.clone();
             let cache = self.cache.clone();
-            let config = self.config.clone();
+            let downloads = self.downloads.clone();
+            let config = self.loading_config();
             move || {
-                let result = item.load(&session, cdn, cache, &config);
+                let result = item.load(
+                    &session,
+                    cdn,
+                    cache,
+                    downloads,
+                    &config,
+                    event_sender.clone(),
+                );
                 event_sender
                     .send(PlayerEvent::Loaded { item, result })
                     .expect("Failed to send PlayerEvent::Loaded");
@@ -390,10 +724,15 @@ impl Player {
         // Make sure the output is paused, so any currently playing item is stopped.
         self.audio_output_remote.pause();
         self.event_sender
-            .send(PlayerEvent::Loading { item })
+            .send(PlayerEvent::Loading {
+                item,
+                tier: self.queue.current_tier(),
+            })
             .expect("Failed to send PlayerEvent::Loading");
         self.state = PlayerState::Loading {
             item,
+            autoplay,
+            start,
             _loading_handle: loading_handle,
         };
     }
@@ -407,9 +746,17 @@ impl Player {
             let session = self.session.clone();
             let cdn = self.cdn.clone();
             let cache = self.cache.clone();
-            let config = self.config.clone();
+            let downloads = self.downloads.clone();
+            let config = self.loading_config();
             move || {
-                let result = item.load(&session, cdn, cache, &config);
+                let result = item.load(
+                    &session,
+                    cdn,
+                    cache,
+                    downloads,
+                    &config,
+                    event_sender.clone(),
+                );
                 event_sender
                     .send(PlayerEvent::Preloaded { item, result })
                     .expect("Failed to send PlayerEvent::Preloaded");
@@ -421,29 +768,175 @@ impl Player {
         };
     }
 
+    /// Configuration to load an item with.  In case of the adaptive bitrate, the
+    /// bitrate is adjusted to the measured download throughput first.
+    fn loading_config(&mut self) -> PlaybackConfig {
+        let mut config = self.config.clone();
+        if config.adaptive_bitrate {
+            if let Some(throughput) = self.downloads.throughput() {
+                let bitrate = adapt_bitrate(self.adaptive_bitrate, config.bitrate, throughput);
+                if bitrate != self.adaptive_bitrate {
+                    log::info!(
+                        "switching to {}kbit, throughput is {}kbit/s",
+                        bitrate,
+                        throughput * 8 / 1000
+                    );
+                    self.adaptive_bitrate = bitrate;
+                }
+            }
+            config.bitrate = self.adaptive_bitrate.min(config.bitrate);
+        }
+        config
+    }
+
     fn set_volume(&mut self, volume: f64) {
         self.audio_output_remote.set_volume(volume);
     }
 
-    fn play_loaded(&mut self, loaded_item: LoadedPlaybackItem) {
+    fn play_loaded(&mut self, loaded_item: LoadedPlaybackItem, autoplay: bool, start: Duration) {
         log::info!("starting playback");
         let path = loaded_item.file.path();
-        let duration = Duration::default();
+        let start = if start == Duration::default() {
+            self.resume_position(&path).unwrap_or(start)
+        } else {
+            start
+        };
+        let duration = start;
+        self.resume_saved_at = start;
         self.audio_source
             .lock()
             .expect("Failed to acquire audio source lock")
             .play_now(loaded_item);
         self.event_sender
-            .send(PlayerEvent::Playing { path, duration })
+            .send(PlayerEvent::Playing {
+                path,
+                duration,
+                tier: self.queue.current_tier(),
+            })
+            .expect("Failed to send PlayerEvent::Playing");
+        self.report_buffered();
+        if autoplay {
+            self.state = PlayerState::Playing { path, duration };
+            self.audio_output_remote.resume();
+        } else {
+            log::info!("pausing playback");
+            self.event_sender
+                .send(PlayerEvent::Pausing { path, duration })
+                .expect("Failed to send PlayerEvent::Pausing");
+            self.state = PlayerState::Paused { path, duration };
+            self.audio_output_remote.pause();
+        }
+        self.reset_blocking();
+        if start > Duration::default() {
+            self.seek(start);
+        }
+    }
+
+    fn continue_with_queued(&mut self, path: AudioPath) {
+        log::info!("continuing playback with queued item");
+        self.report_queue();
+        let duration = Duration::default();
+        self.event_sender
+            .send(PlayerEvent::Playing {
+                path,
+                duration,
+                tier: self.queue.current_tier(),
+            })
             .expect("Failed to send PlayerEvent::Playing");
+        self.report_buffered();
         self.state = PlayerState::Playing { path, duration };
-        self.audio_output_remote.resume();
+        self.resume_saved_at = duration;
+        self.reset_blocking();
+    }
+
+    /// If the following item is already preloaded, hand it over to the audio
+    /// source, so it can start playing it right after the current item ends,
+    /// without any gap.
+    fn queue_preloaded_item(&mut self) {
+        if !matches!(
+            self.state,
+            PlayerState::Playing { .. } | PlayerState::Paused { .. }
+        ) {
+            return;
+        }
+        let following = match self.queue.get_following() {
+            Some(&following) => following,
+            None => return,
+        };
+        match mem::replace(&mut self.preload, PreloadState::None) {
+            // Items with a stored position need to be sought first, so they are
+            // loaded through `play_loaded` instead.
+            PreloadState::Preloaded { item, loaded_item }
+                if item == following
+                    && self.resume_position(&loaded_item.file.path()).is_none() =>
+            {
+                let path = loaded_item.file.path();
+                let crossfade = self.crossfade_before(&path, item.norm_level);
+                self.audio_source
+                    .lock()
+                    .expect("Failed to acquire audio source lock")
+                    .queue_next(loaded_item, crossfade);
+                self.preload = PreloadState::Queued { item, path };
+            }
+            preloading_or_none => {
+                self.preload = preloading_or_none;
+            }
+        }
+    }
+
+    /// Return the length of the crossfade between the current item and the
+    /// following one, playing `next` normalized to `norm_level`.  Consecutive
+    /// tracks of an album played with album normalization are expected to flow
+    /// into each other, so we do not fade them.
+    fn crossfade_before(&self, next: &AudioPath, norm_level: NormalizationLevel) -> Duration {
+        let current = match &self.state {
+            PlayerState::Playing { path, .. } | PlayerState::Paused { path, .. } => {
+                self.album_of(path)
+            }
+            _ => None,
+        };
+        match (current, self.album_of(next)) {
+            (Some(current), Some(next))
+                if norm_level == NormalizationLevel::Album && current == next =>
+            {
+                Duration::default()
+            }
+            _ => self.config.crossfade.min(PlaybackConfig::MAX_CROSSFADE),
+        }
+    }
+
+    /// Album of the track playing at `path`, looked up in the metadata cached
+    /// while loading the item.
+    fn album_of(&self, path: &AudioPath) -> Option<ItemId> {
+        match path.played_item_id.id_type {
+            ItemIdType::Track => self.cache.get_track(path.played_item_id)?.album_id(),
+            _ => None,
+        }
+    }
+
+    /// Take the item previously handed over to the audio source back into the
+    /// preloader.
+    fn unqueue_preloaded_item(&mut self) {
+        if let PreloadState::Queued { item, .. } = self.preload {
+            let taken = self
+                .audio_source
+                .lock()
+                .expect("Failed to acquire audio source lock")
+                .take_next();
+            self.preload = match taken {
+                Some(loaded_item) => PreloadState::Preloaded { item, loaded_item },
+                // Audio source has already started playing the item, and the finish
+                // report is on its way.  We cannot take it back anymore.
+                None => PreloadState::None,
+            };
+        }
     }
 
     fn pause(&mut self) {
         match mem::replace(&mut self.state, PlayerState::Invalid) {
             PlayerState::Playing { path, duration } | PlayerState::Paused { path, duration } => {
                 log::info!("pausing playback");
+                self.save_resume_position(path, duration);
                 self.event_sender
                     .send(PlayerEvent::Pausing { path, duration })
                     .expect("Failed to send PlayerEvent::Paused");
@@ -465,6 +958,10 @@ impl Player {
                     .expect("Failed to send PlayerEvent::Resuming");
                 self.state = PlayerState::Playing { path, duration };
                 self.audio_output_remote.resume();
+                // Resuming clears the blocking state, report it again if the playback is
+                // still blocked.
+                self.is_blocked = false;
+                self.report_blocking();
             }
             _ => {
                 log::warn!("invalid state transition");
@@ -484,6 +981,7 @@ impl Player {
 
     fn previous(&mut self) {
         if self.is_near_playback_start() {
+            self.save_current_resume_position();
             self.queue.skip_to_previous();
             if let Some(&item) = self.queue.get_current() {
                 self.load_and_play(item);
@@ -496,6 +994,7 @@ impl Player {
     }
 
     fn next(&mut self) {
+        self.save_current_resume_position();
         self.queue.skip_to_next();
         if let Some(&item) = self.queue.get_current() {
             self.load_and_play(item);
@@ -505,11 +1004,13 @@ impl Player {
     }
 
     fn stop(&mut self) {
+        self.save_current_resume_position();
         self.event_sender
             .send(PlayerEvent::Stopped)
             .expect("Failed to send PlayerEvent::Stopped");
         self.state = PlayerState::Stopped;
         self.audio_output_remote.pause();
+        self.unqueue_preloaded_item();
         self.queue.clear();
         self.consecutive_loading_failures = 0;
     }
@@ -522,9 +1023,165 @@ impl Player {
     }
 
     fn configure(&mut self, config: PlaybackConfig) {
+        if config.equalizer != self.config.equalizer {
+            self.audio_source
+                .lock()
+                .expect("Failed to acquire audio source lock")
+                .set_equalizer_config(config.equalizer.clone());
+        }
+        if config.limiter != self.config.limiter {
+            self.audio_source
+                .lock()
+                .expect("Failed to acquire audio source lock")
+                .limiter
+                .set_config(config.limiter.clone());
+        }
         self.config = config;
     }
 
+    /// Return true if the playback position of the item at `path` should be
+    /// remembered, see `PlaybackConfig::resume_min_duration`.
+    fn is_resumable(&self, path: &AudioPath) -> bool {
+        path.item_id.id_type == ItemIdType::Podcast
+            || path.duration >= self.config.resume_min_duration
+    }
+
+    /// Return the stored position to resume the item at `path` from, if any.
+    fn resume_position(&self, path: &AudioPath) -> Option<Duration> {
+        if !self.is_resumable(path) {
+            return None;
+        }
+        match self.cache.get_resume_position(path.item_id)? {
+            ResumePosition::Position(position) if position < path.duration => Some(position),
+            _ => None,
+        }
+    }
+
+    /// Remember `progress` as the resume position of the item at `path`, or
+    /// mark the item as finished if it is past the configured threshold.
+    fn save_resume_position(&mut self, path: AudioPath, progress: Duration) {
+        if !self.is_resumable(&path) {
+            return;
+        }
+        self.resume_saved_at = progress;
+        let threshold = path
+            .duration
+            .mul_f64(self.config.resume_finished_threshold.clamp(0.0, 1.0));
+        let position = if progress >= threshold {
+            ResumePosition::Finished
+        } else {
+            ResumePosition::Position(progress)
+        };
+        self.store_resume_position(path.item_id, position);
+    }
+
+    fn save_current_resume_position(&mut self) {
+        match self.state {
+            PlayerState::Playing { path, duration } | PlayerState::Paused { path, duration } => {
+                self.save_resume_position(path, duration);
+            }
+            _ => {}
+        }
+    }
+
+    fn store_resume_position(&self, item_id: ItemId, position: ResumePosition) {
+        if let Err(err) = self.cache.save_resume_position(item_id, position) {
+            log::warn!("failed to save resume position to cache: {:?}", err);
+        }
+    }
+
+    fn clear_resume_positions(&mut self) {
+        if let Err(err) = self.cache.clear_resume_positions() {
+            log::error!("failed to clear resume positions: {:?}", err);
+        }
+    }
+
+    fn set_queue_behavior(&mut self, behavior: QueueBehavior) {
+        // The following item is likely to change, so make sure the audio source does
+        // not continue with a stale one.
+        self.unqueue_preloaded_item();
+        self.queue.set_behaviour(behavior);
+        self.queue_preloaded_item();
+        self.report_queue();
+    }
+
+    fn insert_next(&mut self, item: PlaybackItem) {
+        self.edit_queue(|queue| queue.insert_next(item));
+    }
+
+    fn append(&mut self, item: PlaybackItem) {
+        self.edit_queue(|queue| queue.append(item));
+    }
+
+    fn remove(&mut self, tier: QueueTier, index: usize) {
+        let is_current_removed = self.edit_queue(|queue| queue.remove(tier, index));
+        if is_current_removed {
+            // Continue with the item that took the place of the removed one.
+            if let Some(&item) = self.queue.get_current() {
+                self.load_and_play(item);
+            } else {
+                self.stop();
+            }
+        }
+    }
+
+    fn move_item(&mut self, tier: QueueTier, from: usize, to: usize) {
+        self.edit_queue(|queue| queue.move_item(tier, from, to));
+    }
+
+    /// Change the queue with `edit`, making sure the item queued in the audio
+    /// source still follows the current one.
+    fn edit_queue<T>(&mut self, edit: impl FnOnce(&mut Queue) -> T) -> T {
+        self.unqueue_preloaded_item();
+        let result = edit(&mut self.queue);
+        self.queue_preloaded_item();
+        self.report_queue();
+        result
+    }
+
+    /// Report the buffered ranges of the item in the audio source, in case they
+    /// have changed since the last report.
+    fn report_buffered(&mut self) {
+        let (path, ranges, is_streamed) = {
+            let source = self
+                .audio_source
+                .lock()
+                .expect("Failed to acquire audio source lock");
+            match &source.current {
+                Some(current) => (
+                    current.file.path(),
+                    current.file.buffered_ranges(),
+                    current.file.is_streamed(),
+                ),
+                None => return,
+            }
+        };
+        if let Some((reported_path, reported_ranges)) = &self.reported_buffered {
+            if *reported_path == path && *reported_ranges == ranges {
+                return;
+            }
+        }
+        self.reported_buffered = Some((path, ranges.clone()));
+        self.event_sender
+            .send(PlayerEvent::Buffered {
+                path,
+                ranges,
+                is_streamed,
+            })
+            .expect("Failed to send PlayerEvent::Buffered");
+    }
+
+    /// Let the listeners know about the current order of the context queue, so
+    /// they can save it for `PlayerCommand::RestoreQueue`.
+    fn report_queue(&self) {
+        self.event_sender
+            .send(PlayerEvent::QueueChanged {
+                positions: self.queue.positions().to_vec(),
+                position: self.queue.position(),
+            })
+            .expect("Failed to send PlayerEvent::QueueChanged");
+    }
+
     fn is_near_playback_start(&self) -> bool {
         match self.state {
             PlayerState::Playing { duration, .. } | PlayerState::Paused { duration, .. } => {
@@ -537,7 +1194,8 @@ impl Player {
     fn is_in_preload(&self, item: PlaybackItem) -> bool {
         match self.preload {
             PreloadState::Preloading { item: p_item, .. }
-            | PreloadState::Preloaded { item: p_item, .. } => p_item == item,
+            | PreloadState::Preloaded { item: p_item, .. }
+            | PreloadState::Queued { item: p_item, .. } => p_item == item,
             _ => false,
         }
     }
@@ -548,6 +1206,17 @@ pub enum PlayerCommand {
         items: Vec<PlaybackItem>,
         position: usize,
     },
+    /// Restore a previously saved queue, see `PlayerEvent::QueueChanged` and
+    /// `Queue::restore`.  The current item gets loaded and sought to `progress`,
+    /// but the playback stays paused.
+    RestoreQueue {
+        items: Vec<PlaybackItem>,
+        positions: Vec<usize>,
+        position: usize,
+        manual: Vec<PlaybackItem>,
+        tier: QueueTier,
+        progress: Duration,
+    },
     LoadAndPlay {
         item: PlaybackItem,
     },
@@ -569,10 +1238,33 @@ pub enum PlayerCommand {
     SetQueueBehavior {
         behavior: QueueBehavior,
     },
+    /// Insert `item` to the front of the manual queue, so it plays right
+    /// after the current item.
+    InsertNext {
+        item: PlaybackItem,
+    },
+    /// Append `item` to the end of the manual queue.
+    Append {
+        item: PlaybackItem,
+    },
+    /// Remove the item at `index` of a queue tier, see `Queue::remove`.  If it
+    /// is the current item, playback continues with the following one.
+    Remove {
+        tier: QueueTier,
+        index: usize,
+    },
+    /// Move the item at index `from` of a queue tier to index `to`.
+    Move {
+        tier: QueueTier,
+        from: usize,
+        to: usize,
+    },
     /// Change playback volume to a value in 0.0..=1.0 range.
     SetVolume {
         volume: f64,
     },
+    /// Forget the stored resume positions of all items.
+    ClearResumePositions,
 }
 
 pub enum PlayerEvent {
@@ -580,6 +1272,7 @@ pub enum PlayerEvent {
     /// Track has started loading.  `Loaded` follows.
     Loading {
         item: PlaybackItem,
+        tier: QueueTier,
     },
     /// Track loading either succeeded or failed.  `Playing` follows in case of
     /// success.
@@ -594,9 +1287,12 @@ pub enum PlayerEvent {
         result: Result<LoadedPlaybackItem, Error>,
     },
     /// Player has started playing new track.  `Progress` events will follow.
+    /// If `path` is a substitute, `path.played_item_id` is the track that
+    /// actually plays.
     Playing {
         path: AudioPath,
         duration: Duration,
+        tier: QueueTier,
     },
     /// Player is in a paused state.  `Resuming` might follow.
     Pausing {
@@ -615,10 +1311,53 @@ pub enum PlayerEvent {
         duration: Duration,
     },
     /// Player would like to continue playing, but is blocked, waiting for I/O.
-    Blocked,
+    /// `Unblocked` follows once the playback continues.
+    Blocked {
+        path: AudioPath,
+    },
+    /// Playback has been blocked, but can continue now.
+    Unblocked {
+        path: AudioPath,
+    },
+    /// Reading of an audio file has started (`is_blocked` is true) or stopped
+    /// waiting for the data to download.  Handled internally, see `Blocked`.
+    StreamBlocked {
+        path: AudioPath,
+        is_blocked: bool,
+    },
+    /// Parts of the current item that are downloaded, as time ranges from the
+    /// beginning, sent whenever they change.  `is_streamed` is false for items
+    /// read from the cache or the local files, these are available whole.
+    Buffered {
+        path: AudioPath,
+        ranges: Vec<Range<Duration>>,
+        is_streamed: bool,
+    },
+    /// Audio output has run out of decoded samples (`is_underrun` is true), or
+    /// has received them again.  Handled internally, see `Blocked`.
+    Underrun {
+        is_underrun: bool,
+    },
     /// Player has finished playing a track.  `Loading` or `Playing` might
-    /// follow if the queue is not empty, `Stopped` will follow if it is.
-    Finished,
+    /// follow if the queue is not empty, `Stopped` will follow if it is.  If the
+    /// following item has been queued in the audio source, its playback has
+    /// already started without a gap.
+    Finished {
+        path: AudioPath,
+    },
+    /// Decoding of a track has failed, and could not recover by skipping the
+    /// broken part of the file.  `Finished` follows, so the playback continues
+    /// with the next item.
+    Failed {
+        path: AudioPath,
+        error: Error,
+    },
+    /// Order of the context queue, or the position in it, has changed.  See
+    /// `Queue::positions` and `Queue::position`.
+    QueueChanged {
+        positions: Vec<usize>,
+        position: usize,
+    },
     /// The queue is empty.
     Stopped,
 }
@@ -626,6 +1365,8 @@ pub enum PlayerEvent {
 enum PlayerState {
     Loading {
         item: PlaybackItem,
+        autoplay: bool,
+        start: Duration,
         _loading_handle: JoinHandle<()>,
     },
     Playing {
@@ -649,57 +1390,318 @@ enum PreloadState {
         item: PlaybackItem,
         loaded_item: LoadedPlaybackItem,
     },
+    /// Preloaded item has been handed over to `PlayerAudioSource`, to be played
+    /// right after the current one.
+    Queued {
+        item: PlaybackItem,
+        path: AudioPath,
+    },
     None,
 }
 
-const OUTPUT_CHANNELS: u8 = 2;
-const OUTPUT_SAMPLE_RATE: u32 = 44100;
-const PROGRESS_PRECISION_SAMPLES: u64 = OUTPUT_SAMPLE_RATE as u64 * OUTPUT_CHANNELS as u64; // 1 second.
+// Output format used until the output device tells us its own.
+const DEFAULT_OUTPUT_FORMAT: AudioFormat = AudioFormat {
+    channels: 2,
+    sample_rate: 44100,
+};
+const PROGRESS_PRECISION: Duration = Duration::from_secs(1);
+
+// Count of decoded samples buffered in front of the audio output.
+const AUDIO_BUFFER_CAPACITY: usize = 64 * 1024;
+// Maximal count of samples decoded in one pass of the decoding thread.
+const DECODE_CHUNK_SIZE: usize = 4 * 1024;
+// How long the decoding thread sleeps if the buffer is full or there's nothing
+// to decode.
+const DECODE_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Decoder of an item.  Reading from it can block on the network, so the
+/// decoding thread reads from it without holding the audio source lock.
+type SharedDecoder = Arc<Mutex<AudioResampler<FileAudioSource>>>;
 
 struct CurrentPlaybackItem {
     file: AudioFile,
-    source: FileAudioSource,
+    decoder: SharedDecoder,
+    // Format of the decoded input, before resampling.
+    input: AudioFormat,
+    // Samples read from the decoder, waiting to be processed.
+    decoded: VecDeque<AudioSample>,
+    // True once the decoder has no more samples to give.
+    is_decoded: bool,
+    // Input frame the decoder should seek to before the next read.
+    pending_seek: Option<u64>,
     norm_factor: f32,
+    // Error that has ended the playback of this item early.
+    error: Option<Error>,
+}
+
+impl CurrentPlaybackItem {
+    fn is_finished(&self) -> bool {
+        self.error.is_some() || (self.is_decoded && self.decoded.is_empty())
+    }
+
+    fn is_waiting_for_decoder(&self) -> bool {
+        self.decoded.is_empty() && !self.is_finished()
+    }
+
+    /// Request a read of the decoder, so there are at least `count` decoded
+    /// samples waiting.
+    fn read_request(
+        &mut self,
+        count: usize,
+        output: AudioFormat,
+        generation: u64,
+    ) -> Option<DecoderRead> {
+        if self.is_finished() || self.is_decoded || self.decoded.len() >= count {
+            return None;
+        }
+        Some(DecoderRead {
+            decoder: self.decoder.clone(),
+            generation,
+            seek: self.pending_seek.take(),
+            output,
+            count: count - self.decoded.len(),
+            samples: Vec::new(),
+            is_end: false,
+            error: None,
+        })
+    }
+}
+
+/// Read of samples from the decoder of an item, done by the decoding thread
+/// without holding the audio source lock.
+struct DecoderRead {
+    decoder: SharedDecoder,
+    // Generation of the audio source the read has been requested in.  Samples of
+    // reads from older generations are thrown away.
+    generation: u64,
+    seek: Option<u64>,
+    output: AudioFormat,
+    count: usize,
+    samples: Vec<AudioSample>,
+    is_end: bool,
+    error: Option<Error>,
+}
+
+impl DecoderRead {
+    fn run(mut self) -> Self {
+        let mut decoder = self.decoder.lock().expect("Failed to acquire decoder lock");
+        if decoder.output_format() != self.output {
+            decoder.set_output_format(self.output);
+        }
+        if let Some(frame) = self.seek {
+            if let Err(err) = decoder.source_mut().seek(frame) {
+                // Position of the decoder is unknown now, end the item.
+                log::error!("failed to seek: {}", err);
+                self.error.replace(err);
+            }
+            decoder.reset();
+        }
+        self.samples.reserve(self.count);
+        while self.error.is_none() && self.samples.len() < self.count {
+            match decoder.next() {
+                Some(sample) => self.samples.push(sample),
+                None => {
+                    self.is_end = true;
+                    break;
+                }
+            }
+        }
+        drop(decoder);
+        self
+    }
+}
+
+struct QueuedPlaybackItem {
+    loaded_item: LoadedPlaybackItem,
+    crossfade: Duration,
+}
+
+struct FadingPlaybackItem {
+    item: CurrentPlaybackItem,
+    // Equalizer keeping the filter state of the fading item.
+    equalizer: Equalizer,
+    // Progress and total length of the crossfade, in samples.
+    position: u64,
+    length: u64,
 }
 
+/// Event that should be reported once the audio output reaches a position in
+/// the buffer.
+type Marker = (u64, PlayerEvent);
+
 struct PlayerAudioSource {
     current: Option<CurrentPlaybackItem>,
+    next: Option<QueuedPlaybackItem>,
+    fading: Option<FadingPlaybackItem>,
+    buffer: AudioBufferProducer,
+    markers: Sender<Marker>,
     event_sender: Sender<PlayerEvent>,
+    output: AudioFormat,
+    // Position in the current item, in output samples.
     samples: u64,
+    // Changes on every seek and output format change, see `DecoderRead`.
+    generation: u64,
+    equalizer: Equalizer,
+    limiter: Limiter,
 }
 
 impl PlayerAudioSource {
-    fn new(event_sender: Sender<PlayerEvent>) -> Self {
+    fn new(
+        buffer: AudioBufferProducer,
+        markers: Sender<Marker>,
+        event_sender: Sender<PlayerEvent>,
+        equalizer: EqualizerConfig,
+        limiter: LimiterConfig,
+    ) -> Self {
         Self {
+            buffer,
+            markers,
             event_sender,
             current: None,
+            next: None,
+            fading: None,
+            output: DEFAULT_OUTPUT_FORMAT,
             samples: 0,
+            generation: 0,
+            equalizer: Equalizer::new(equalizer, DEFAULT_OUTPUT_FORMAT),
+            limiter: Limiter::new(limiter, DEFAULT_OUTPUT_FORMAT),
         }
     }
 
     fn seek(&mut self, position: Duration) {
         if let Some(current) = &mut self.current {
+            // Decoder is seeking in the frames of the input, while we are counting the
+            // samples of the output.  The decoding thread seeks before its next read.
             let seconds = position.as_secs_f64();
-            let frames = seconds * f64::from(OUTPUT_SAMPLE_RATE);
-            let samples = frames * f64::from(OUTPUT_CHANNELS);
-            current.source.seek(frames as u64);
-            self.samples = samples as u64;
-            self.report_audio_position();
+            let input_frames = seconds * f64::from(current.input.sample_rate);
+            current.pending_seek.replace(input_frames as u64);
+            current.decoded.clear();
+            current.is_decoded = false;
+            self.generation += 1;
+            self.samples = duration_to_samples(position, self.output);
+            self.fading.take();
+            self.limiter.reset();
+            self.buffer.discard();
+            self.buffer.set_producing(true);
+            // Audio output is going to continue from the new position right away, so
+            // we can report it directly.
+            let path = current.file.path();
+            let duration = self.item_position();
+            self.event_sender
+                .send(PlayerEvent::Progress { duration, path })
+                .expect("Failed to send PlayerEvent::Progress");
         }
     }
 
+    /// Start playing `item` right away, throwing away the buffered samples.
     fn play_now(&mut self, item: LoadedPlaybackItem) {
+        self.switch_to(item);
+        self.limiter.reset();
+        self.buffer.discard();
+        self.buffer.set_producing(true);
+    }
+
+    /// Continue with `item` after the samples buffered so far, used when handing
+    /// over to the queued item.
+    fn switch_to(&mut self, item: LoadedPlaybackItem) {
+        let input = AudioFormat {
+            channels: item.source.channels(),
+            sample_rate: item.source.sample_rate(),
+        };
+        // Items that are not normalized are played as they are.
+        self.limiter
+            .set_bypass(item.norm_level == NormalizationLevel::None);
         self.current.replace(CurrentPlaybackItem {
             norm_factor: item.norm_factor,
-            source: item.source,
+            decoder: Arc::new(Mutex::new(AudioResampler::new(
+                item.source,
+                input,
+                self.output,
+            ))),
+            input,
+            decoded: VecDeque::new(),
+            is_decoded: false,
+            pending_seek: None,
             file: item.file,
+            error: None,
         });
         self.samples = 0;
+        self.fading.take();
+    }
+
+    fn queue_next(&mut self, loaded_item: LoadedPlaybackItem, crossfade: Duration) {
+        self.next.replace(QueuedPlaybackItem {
+            loaded_item,
+            crossfade,
+        });
+    }
+
+    fn take_next(&mut self) -> Option<LoadedPlaybackItem> {
+        self.next.take().map(|next| next.loaded_item)
+    }
+
+    fn is_crossfade_due(&self) -> bool {
+        match (&self.current, &self.next) {
+            (Some(current), Some(next)) if next.crossfade > Duration::default() => {
+                // Start only on a frame boundary, so the channels of both items stay
+                // aligned.
+                let is_on_frame_boundary = self.samples % u64::from(self.output.channels) == 0;
+                let remaining = current
+                    .file
+                    .path()
+                    .duration
+                    .saturating_sub(self.item_position());
+                is_on_frame_boundary && remaining <= next.crossfade
+            }
+            _ => false,
+        }
+    }
+
+    fn start_crossfade(&mut self) {
+        if let (Some(current), Some(next)) = (self.current.take(), self.next.take()) {
+            let path = current.file.path();
+            let remaining = path.duration.saturating_sub(self.item_position());
+            let length = duration_to_samples(next.crossfade.min(remaining), self.output);
+            // From the player's point of view, the current item is finished, and the next
+            // one is playing.
+            self.report_audio_end(path, None);
+            self.switch_to(next.loaded_item);
+            // Incoming item starts from silence, so it gets a fresh filter state, while
+            // the fading one keeps its own.
+            let equalizer = Equalizer::new(self.equalizer.config().clone(), self.output);
+            self.fading.replace(FadingPlaybackItem {
+                item: current,
+                equalizer: mem::replace(&mut self.equalizer, equalizer),
+                position: 0,
+                length,
+            });
+        }
+    }
+
+    fn mix_in_fading(&mut self, sample: AudioSample) -> AudioSample {
+        if let Some(fading) = &mut self.fading {
+            if fading.position < fading.length {
+                // Use an equal-power curve, so the loudness stays constant during the fade.
+                let x = fading.position as f32 / fading.length as f32;
+                let fade_in = (x * FRAC_PI_2).sin();
+                let fade_out = (x * FRAC_PI_2).cos();
+                let faded = fading.item.decoded.pop_front().unwrap_or(0.0);
+                let faded = fading.equalizer.process(faded) * fading.item.norm_factor;
+                fading.position += 1;
+                return sample * fade_in + faded * fade_out;
+            }
+        }
+        self.fading.take();
+        sample
     }
 
     fn next_sample(&mut self) -> Option<AudioSample> {
         if let Some(current) = &mut self.current {
-            let sample = current.source.next();
+            let sample = if current.error.is_none() {
+                current.decoded.pop_front()
+            } else {
+                None
+            };
             if sample.is_some() {
                 self.samples += 1;
             } else {
@@ -711,57 +1713,328 @@ impl PlayerAudioSource {
         }
     }
 
+    /// Position in the current item.  Not called `position`, which would resolve
+    /// to `Iterator::position` in the methods taking `&mut self`.
+    fn item_position(&self) -> Duration {
+        samples_to_duration(self.samples, self.output)
+    }
+
     fn report_audio_position(&self) {
         if let Some(current) = &self.current {
-            let duration = Duration::from_secs_f64(
-                self.samples as f64 / f64::from(OUTPUT_SAMPLE_RATE) / f64::from(OUTPUT_CHANNELS),
-            );
+            let duration = self.item_position();
             let path = current.file.path();
-            self.event_sender
-                .send(PlayerEvent::Progress { duration, path })
-                .expect("Failed to send PlayerEvent::Progress");
+            self.report_at_buffer_position(PlayerEvent::Progress { duration, path });
         }
     }
 
-    fn report_audio_end(&self) {
-        self.event_sender
-            .send(PlayerEvent::Finished)
-            .expect("Failed to send PlayerEvent::Finished");
+    fn report_audio_end(&self, path: AudioPath, error: Option<Error>) {
+        if let Some(error) = error {
+            self.report_at_buffer_position(PlayerEvent::Failed { path, error });
+        }
+        self.report_at_buffer_position(PlayerEvent::Finished { path });
+    }
+
+    /// Report `event` once the audio output plays the samples produced so far.
+    fn report_at_buffer_position(&self, event: PlayerEvent) {
+        self.markers
+            .send((self.buffer.position(), event))
+            .expect("Failed to send audio buffer marker");
+    }
+
+    fn set_output_format(&mut self, output: AudioFormat) {
+        if output == self.output {
+            return;
+        }
+        log::info!("audio output format: {:?}", output);
+        // Keep the reported position intact.
+        self.samples = duration_to_samples(self.item_position(), output);
+        self.output = output;
+        // Decoded samples are in the previous format, the decoding thread switches
+        // the decoder over before its next read.
+        if let Some(current) = &mut self.current {
+            current.decoded.clear();
+            current.is_decoded = false;
+        }
+        self.generation += 1;
+        self.equalizer.set_format(output);
+        self.limiter.set_format(output);
+        self.fading.take();
+        // Buffered samples are in the previous format, throw them away.
+        self.buffer.discard();
+    }
+
+    fn normalization_factor(&self) -> Option<f32> {
+        self.current.as_ref().map(|current| current.norm_factor)
+    }
+
+    fn is_waiting_for_decoder(&self) -> bool {
+        let current = self
+            .current
+            .as_ref()
+            .map_or(false, CurrentPlaybackItem::is_waiting_for_decoder);
+        let fading = self.fading.as_ref().map_or(false, |fading| {
+            fading.position < fading.length && fading.item.is_waiting_for_decoder()
+        });
+        current || fading
+    }
+
+    fn set_equalizer_config(&mut self, config: EqualizerConfig) {
+        if let Some(fading) = &mut self.fading {
+            fading.equalizer.set_config(config.clone());
+        }
+        self.equalizer.set_config(config);
+    }
+
+    /// Request the decoder reads needed to fill at most `max_samples` into the
+    /// buffer.
+    fn read_requests(&mut self, max_samples: usize) -> Vec<DecoderRead> {
+        let count = self.buffer.free().min(max_samples);
+        let (output, generation) = (self.output, self.generation);
+        let mut requests = Vec::new();
+        if let Some(fading) = &mut self.fading {
+            let remaining = fading.length.saturating_sub(fading.position) as usize;
+            let count = count.min(remaining);
+            requests.extend(fading.item.read_request(count, output, generation));
+        }
+        if let Some(current) = &mut self.current {
+            requests.extend(current.read_request(count, output, generation));
+        }
+        requests
+    }
+
+    /// Hand the samples of a finished read over to their item, unless the item
+    /// has been replaced, sought, or the output format has changed since.
+    fn add_decoded(&mut self, read: DecoderRead) {
+        if read.generation != self.generation {
+            return;
+        }
+        let fading = self.fading.as_mut().map(|fading| &mut fading.item);
+        let item = self
+            .current
+            .as_mut()
+            .into_iter()
+            .chain(fading)
+            .find(|item| Arc::ptr_eq(&item.decoder, &read.decoder));
+        if let Some(item) = item {
+            item.decoded.extend(read.samples);
+            item.is_decoded |= read.is_end;
+            if let Some(err) = read.error {
+                item.error.get_or_insert(err);
+            }
+        }
+    }
+
+    /// Process the decoded samples of `reads` into the buffer, at most
+    /// `max_samples` of them.  Returns the count of samples produced.
+    fn fill_buffer(&mut self, reads: Vec<DecoderRead>, max_samples: usize) -> usize {
+        for read in reads {
+            self.add_decoded(read);
+        }
+        let count = self.buffer.free().min(max_samples);
+        let mut decoded = 0;
+        while decoded < count {
+            match self.next() {
+                Some(sample) => {
+                    self.buffer.push(sample);
+                    decoded += 1;
+                }
+                None => break,
+            }
+        }
+        // Let the consumer know if an empty buffer means an underrun, before the last
+        // samples get published.
+        self.buffer.set_producing(self.current.is_some());
+        self.buffer.publish();
+        decoded
     }
 }
 
-impl AudioSource for PlayerAudioSource {
+impl Iterator for PlayerAudioSource {
+    type Item = AudioSample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_crossfade_due() {
+            self.start_crossfade();
+        }
+        let is_finished = self
+            .current
+            .as_ref()
+            .map_or(false, CurrentPlaybackItem::is_finished);
+        if is_finished && self.next.is_none() {
+            // Nothing follows, play out the samples still delayed by the limiter
+            // before reporting the end.
+            if let Some(sample) = self.limiter.drain() {
+                return Some(sample);
+            }
+        }
+        if is_finished {
+            // We're at the end of track.  Drop the item and report.
+            if let Some(mut finished) = self.current.take() {
+                let error = finished.error.take().or_else(|| {
+                    finished
+                        .decoder
+                        .lock()
+                        .expect("Failed to acquire decoder lock")
+                        .source_mut()
+                        .take_error()
+                });
+                self.report_audio_end(finished.file.path(), error);
+                // In case the following item is queued, continue with it right away, so
+                // there is no gap.  Otherwise, player will pause the audio output and we
+                // will stop getting polled eventually.
+                if let Some(next) = self.next.take() {
+                    self.switch_to(next.loaded_item);
+                }
+            }
+        }
+        if self.is_waiting_for_decoder() {
+            // Decoding thread is going to read more samples first.
+            return None;
+        }
+        let sample = self.next_sample()?;
+        // Report audio progress.
+        if self.samples % duration_to_samples(PROGRESS_PRECISION, self.output) == 0 {
+            self.report_audio_position();
+        }
+        // Equalize first, so the limiter is the last stage and catches the peaks
+        // boosted by the equalizer as well.
+        let equalized = self.equalizer.process(sample);
+        let norm_factor = self.normalization_factor().unwrap_or(1.0);
+        let mixed = self.mix_in_fading(equalized * norm_factor);
+        Some(self.limiter.process(mixed))
+    }
+}
+
+/// Keep filling the audio buffer of `source`, until the player gets dropped.
+fn decode_into_buffer(source: Weak<Mutex<PlayerAudioSource>>) {
+    while let Some(source) = source.upgrade() {
+        let requests = source
+            .lock()
+            .expect("Failed to acquire audio source lock")
+            .read_requests(DECODE_CHUNK_SIZE);
+        let is_idle = requests.is_empty();
+        // Reads can block while the data is downloading, keep the audio source
+        // unlocked meanwhile, so seeking and queue changes do not wait for them.
+        let reads = requests.into_iter().map(DecoderRead::run).collect();
+        let decoded = source
+            .lock()
+            .expect("Failed to acquire audio source lock")
+            .fill_buffer(reads, DECODE_CHUNK_SIZE);
+        drop(source);
+        if decoded == 0 && is_idle {
+            thread::sleep(DECODE_INTERVAL);
+        }
+    }
+}
+
+/// Audio source read by the audio output.  Only copies the samples decoded by
+/// `PlayerAudioSource` out of the audio buffer, and reports the events that
+/// have been waiting for the playback to reach their position.
+struct BufferedAudioSource {
+    buffer: AudioBufferConsumer,
+    markers: Receiver<Marker>,
+    pending_marker: Option<Marker>,
+    source: Arc<Mutex<PlayerAudioSource>>,
+    event_sender: Sender<PlayerEvent>,
+    is_underrun: bool,
+}
+
+impl BufferedAudioSource {
+    fn report_reached_markers(&mut self) {
+        loop {
+            let (position, event) = match self.pending_marker.take() {
+                Some(marker) => marker,
+                None => match self.markers.try_recv() {
+                    Ok(marker) => marker,
+                    Err(_) => break,
+                },
+            };
+            if position > self.buffer.position() {
+                // Not there yet.
+                self.pending_marker.replace((position, event));
+                break;
+            }
+            if let PlayerEvent::Progress { .. } = event {
+                if position <= self.buffer.discarded_to() {
+                    // Progress of samples that are never going to be played.
+                    continue;
+                }
+            }
+            self.event_sender
+                .send(event)
+                .expect("Failed to send buffered PlayerEvent");
+        }
+    }
+}
+
+impl AudioSource for BufferedAudioSource {
     fn channels(&self) -> u8 {
-        OUTPUT_CHANNELS
+        self.source
+            .lock()
+            .expect("Failed to acquire audio source lock")
+            .output
+            .channels
     }
 
     fn sample_rate(&self) -> u32 {
-        OUTPUT_SAMPLE_RATE
+        self.source
+            .lock()
+            .expect("Failed to acquire audio source lock")
+            .output
+            .sample_rate
+    }
+
+    fn set_output_format(&mut self, channels: u8, sample_rate: u32) {
+        let output = AudioFormat {
+            channels,
+            sample_rate,
+        };
+        self.source
+            .lock()
+            .expect("Failed to acquire audio source lock")
+            .set_output_format(output);
     }
 
     fn normalization_factor(&self) -> Option<f32> {
-        self.current.as_ref().map(|current| current.norm_factor)
+        // Samples in the buffer are already normalized.
+        None
     }
 }
 
-impl Iterator for PlayerAudioSource {
+impl Iterator for BufferedAudioSource {
     type Item = AudioSample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let sample = self.next_sample();
-        if sample.is_some() {
-            // Report audio progress.
-            if self.samples % PROGRESS_PRECISION_SAMPLES == 0 {
-                self.report_audio_position();
+        let sample = self.buffer.pop();
+        self.report_reached_markers();
+        match sample {
+            Some(_) if self.is_underrun => {
+                self.is_underrun = false;
+                self.event_sender
+                    .send(PlayerEvent::Underrun { is_underrun: false })
+                    .expect("Failed to send PlayerEvent::Underrun");
             }
-        } else {
-            // We're at the end of track.  If we still have the source, drop it and report.
-            // Player will pause the audio output and we will stop getting polled
-            // eventually.
-            if self.current.take().is_some() {
-                self.report_audio_end();
+            None if self.buffer.is_producing() && !self.is_underrun => {
+                // Buffer has run dry while there's more audio to play, decoder is most
+                // probably waiting for the data to arrive.
+                self.is_underrun = true;
+                self.event_sender
+                    .send(PlayerEvent::Underrun { is_underrun: true })
+                    .expect("Failed to send PlayerEvent::Underrun");
             }
+            _ => {}
         }
         sample
     }
 }
+
+fn duration_to_samples(duration: Duration, format: AudioFormat) -> u64 {
+    let frames = duration.as_secs_f64() * f64::from(format.sample_rate);
+    frames as u64 * u64::from(format.channels)
+}
+
+fn samples_to_duration(samples: u64, format: AudioFormat) -> Duration {
+    Duration::from_secs_f64(
+        samples as f64 / f64::from(format.sample_rate) / f64::from(format.channels),
+    )
+}
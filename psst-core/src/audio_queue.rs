@@ -1,3 +1,5 @@
+use std::collections::VecDeque;
+
 use rand::prelude::SliceRandom;
 
 use crate::audio_player::PlaybackItem;
@@ -16,7 +18,31 @@ impl Default for QueueBehavior {
     }
 }
 
+/// Part of the queue an item is played from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum QueueTier {
+    /// Items queued explicitly by the user.
+    Manual,
+    /// Items of the album, playlist, etc. the playback has been started from.
+    Context,
+}
+
+/// Queue of items to play, in two tiers.  Items of the manual queue are always
+/// played first, in the order they were added, and each of them is removed from
+/// the queue once it starts playing.  After the manual queue is exhausted, the
+/// playback continues in the context queue, right after the context item that
+/// was playing before.
+///
+/// `QueueBehavior` applies to the context queue only.  This means the manual
+/// queue is never shuffled, `LoopAll` starts the context over, but does not
+/// repeat the manual items, and `LoopTrack` repeats the current context item,
+/// but plays a manual item only once, returning to the repeated context item
+/// afterwards.  Skipping to the previous item from a manual item returns to the
+/// last played context item.
 pub struct Queue {
+    manual: VecDeque<PlaybackItem>,
+    // Manual item that is currently playing, already removed from `manual`.
+    current_manual: Option<PlaybackItem>,
     items: Vec<PlaybackItem>,
     position: usize,
     positions: Vec<usize>,
@@ -26,6 +52,8 @@ pub struct Queue {
 impl Queue {
     pub fn new() -> Self {
         Self {
+            manual: VecDeque::new(),
+            current_manual: None,
             items: Vec::new(),
             position: 0,
             positions: Vec::new(),
@@ -34,22 +62,170 @@ impl Queue {
     }
 
     pub fn clear(&mut self) {
+        self.manual.clear();
+        self.current_manual = None;
         self.items.clear();
         self.positions.clear();
         self.position = 0;
     }
 
+    /// Replace the context queue and start playing it from `position`.  The
+    /// manual queue is kept intact.
     pub fn fill(&mut self, items: Vec<PlaybackItem>, position: usize) {
+        self.current_manual = None;
         self.items = items;
         self.position = position;
         self.compute_positions();
     }
 
+    /// Replace both tiers with a previously saved state, see `positions` and
+    /// `position`.  If `tier` is `Manual`, the first item of `manual` becomes the
+    /// current one.  Saved `positions` are only used if they still match the
+    /// items, otherwise they are computed again.
+    pub fn restore(
+        &mut self,
+        items: Vec<PlaybackItem>,
+        positions: Vec<usize>,
+        position: usize,
+        manual: Vec<PlaybackItem>,
+        tier: QueueTier,
+    ) {
+        self.manual = manual.into();
+        self.current_manual = match tier {
+            QueueTier::Manual => self.manual.pop_front(),
+            QueueTier::Context => None,
+        };
+        self.items = items;
+        let mut sorted = positions.clone();
+        sorted.sort_unstable();
+        if sorted.into_iter().eq(0..self.items.len()) {
+            self.positions = positions;
+            self.position = position;
+        } else {
+            // Saved order is not a permutation of the items, start over at the item
+            // that was current.
+            let current = positions.get(position).copied().unwrap_or(position);
+            self.position = current.min(self.items.len().saturating_sub(1));
+            self.compute_positions();
+        }
+    }
+
+    /// Order the items of the context queue are played in, as their indices.
+    /// This is a random permutation in case of `QueueBehavior::Random`.
+    pub fn positions(&self) -> &[usize] {
+        &self.positions
+    }
+
+    /// Index into `positions` of the current context item, or of the last played
+    /// one, in case a manual item is playing.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Insert `item` to the front of the manual queue, so it plays next.
+    pub fn insert_next(&mut self, item: PlaybackItem) {
+        self.manual.push_front(item);
+    }
+
+    /// Append `item` to the end of the manual queue.
+    pub fn append(&mut self, item: PlaybackItem) {
+        self.manual.push_back(item);
+    }
+
+    /// Remove the item at `index` of a queue tier.  Indices of the context
+    /// queue refer to the order the items were filled in, regardless of the
+    /// behavior.  Returns true if the current item has been removed, the
+    /// following item becomes the current one then.
+    pub fn remove(&mut self, tier: QueueTier, index: usize) -> bool {
+        match tier {
+            QueueTier::Manual => {
+                self.manual.remove(index);
+                false
+            }
+            QueueTier::Context => self.remove_from_context(index),
+        }
+    }
+
+    /// Move the item at index `from` of a queue tier to index `to`.  The
+    /// current item stays current.
+    pub fn move_item(&mut self, tier: QueueTier, from: usize, to: usize) {
+        match tier {
+            QueueTier::Manual => {
+                if from < self.manual.len() && to < self.manual.len() {
+                    if let Some(item) = self.manual.remove(from) {
+                        self.manual.insert(to, item);
+                    }
+                }
+            }
+            QueueTier::Context => self.move_in_context(from, to),
+        }
+    }
+
     pub fn set_behaviour(&mut self, behavior: QueueBehavior) {
         self.behavior = behavior;
         self.compute_positions();
     }
 
+    fn remove_from_context(&mut self, index: usize) -> bool {
+        if index >= self.items.len() {
+            return false;
+        }
+        let is_current =
+            self.current_manual.is_none() && self.current_context_index() == Some(index);
+        self.items.remove(index);
+        if let Some(position) = self.positions.iter().position(|&i| i == index) {
+            self.positions.remove(position);
+            if position < self.position {
+                self.position -= 1;
+            }
+        }
+        for i in &mut self.positions {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        if let QueueBehavior::LoopAll = self.behavior {
+            if self.position >= self.positions.len() {
+                self.position = 0;
+            }
+        }
+        is_current
+    }
+
+    fn move_in_context(&mut self, from: usize, to: usize) {
+        if from >= self.items.len() || to >= self.items.len() {
+            return;
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        let moved_index = |i: usize| {
+            if i == from {
+                to
+            } else if from < to && i > from && i <= to {
+                i - 1
+            } else if to < from && i >= to && i < from {
+                i + 1
+            } else {
+                i
+            }
+        };
+        if let QueueBehavior::Random = self.behavior {
+            // Keep the shuffled order, only follow the moved items.
+            for i in &mut self.positions {
+                *i = moved_index(*i);
+            }
+        } else {
+            // Ordered mapping stays 1:1, but the current item might have moved.
+            if let Some(current) = self.current_context_index() {
+                self.position = moved_index(current);
+            }
+        }
+    }
+
+    fn current_context_index(&self) -> Option<usize> {
+        self.positions.get(self.position).copied()
+    }
+
     fn compute_positions(&mut self) {
         // Start with an ordered 1:1 mapping.
         self.positions = (0..self.items.len()).collect();
@@ -67,24 +243,65 @@ impl Queue {
     }
 
     pub fn skip_to_previous(&mut self) {
-        self.position = self.previous_position();
+        if self.current_manual.take().is_none() {
+            self.position = self.previous_position();
+        }
     }
 
     pub fn skip_to_next(&mut self) {
-        self.position = self.next_position();
+        if let Some(item) = self.manual.pop_front() {
+            self.current_manual.replace(item);
+        } else {
+            self.current_manual = None;
+            self.position = self.next_position();
+        }
     }
 
     pub fn skip_to_following(&mut self) {
-        self.position = self.following_position();
+        if let QueueBehavior::LoopTrack = self.behavior {
+            if self.current_manual.take().is_some() {
+                // Return to the repeated context item after the manual queue.
+                if let Some(item) = self.manual.pop_front() {
+                    self.current_manual.replace(item);
+                }
+            }
+            // Otherwise, keep playing the current context item.
+        } else {
+            self.skip_to_next();
+        }
     }
 
     pub fn get_current(&self) -> Option<&PlaybackItem> {
+        if let Some(item) = &self.current_manual {
+            return Some(item);
+        }
         let position = self.positions.get(self.position).copied()?;
         self.items.get(position)
     }
 
+    /// Return the tier of the current item.
+    pub fn current_tier(&self) -> QueueTier {
+        if self.current_manual.is_some() {
+            QueueTier::Manual
+        } else {
+            QueueTier::Context
+        }
+    }
+
     pub fn get_following(&self) -> Option<&PlaybackItem> {
-        let position = self.positions.get(self.following_position()).copied()?;
+        if let QueueBehavior::LoopTrack = self.behavior {
+            if self.current_manual.is_none() {
+                return self.get_current();
+            }
+        }
+        if let Some(item) = self.manual.front() {
+            return Some(item);
+        }
+        let position = match self.behavior {
+            QueueBehavior::LoopTrack => self.position,
+            _ => self.next_position(),
+        };
+        let position = self.positions.get(position).copied()?;
         self.items.get(position)
     }
 
@@ -102,15 +319,147 @@ impl Queue {
             QueueBehavior::Sequential | QueueBehavior::Random | QueueBehavior::LoopTrack => {
                 self.position + 1
             }
-            QueueBehavior::LoopAll => (self.position + 1) % self.items.len(),
+            // Context might be empty, in case only manual items have been played.
+            QueueBehavior::LoopAll => (self.position + 1) % self.items.len().max(1),
         }
     }
+}
 
-    fn following_position(&self) -> usize {
-        match self.behavior {
-            QueueBehavior::Sequential | QueueBehavior::Random => self.position + 1,
-            QueueBehavior::LoopTrack => self.position,
-            QueueBehavior::LoopAll => (self.position + 1) % self.items.len(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        audio_normalize::NormalizationLevel,
+        item_id::{ItemId, ItemIdType},
+    };
+
+    fn item(id: u128) -> PlaybackItem {
+        PlaybackItem {
+            item_id: ItemId::new(id, ItemIdType::Track),
+            norm_level: NormalizationLevel::None,
+        }
+    }
+
+    fn queue(behavior: QueueBehavior, context: &[u128], position: usize) -> Queue {
+        let mut queue = Queue::new();
+        queue.set_behaviour(behavior);
+        queue.fill(context.iter().copied().map(item).collect(), position);
+        queue
+    }
+
+    fn current(queue: &Queue) -> Option<u128> {
+        queue.get_current().map(|item| item.item_id.id)
+    }
+
+    fn following(queue: &Queue) -> Option<u128> {
+        queue.get_following().map(|item| item.item_id.id)
+    }
+
+    #[test]
+    fn manual_queue_drains_before_context() {
+        let mut queue = queue(QueueBehavior::Sequential, &[1, 2, 3], 0);
+        queue.append(item(11));
+        queue.append(item(12));
+        queue.insert_next(item(10));
+        assert_eq!(following(&queue), Some(10));
+
+        for &expected in &[10, 11, 12] {
+            queue.skip_to_following();
+            assert_eq!(current(&queue), Some(expected));
+            assert_eq!(queue.current_tier(), QueueTier::Manual);
+        }
+        // Context continues right after the item that was playing before.
+        queue.skip_to_following();
+        assert_eq!(current(&queue), Some(2));
+        assert_eq!(queue.current_tier(), QueueTier::Context);
+        queue.skip_to_next();
+        assert_eq!(current(&queue), Some(3));
+        queue.skip_to_next();
+        assert_eq!(current(&queue), None);
+    }
+
+    #[test]
+    fn previous_skips_consumed_manual_items() {
+        let mut queue = queue(QueueBehavior::Sequential, &[1, 2, 3], 0);
+        queue.append(item(10));
+        queue.append(item(11));
+
+        queue.skip_to_next();
+        queue.skip_to_next();
+        assert_eq!(current(&queue), Some(11));
+        // From a manual item, previous returns to the last played context item.
+        queue.skip_to_previous();
+        assert_eq!(current(&queue), Some(1));
+        assert_eq!(queue.current_tier(), QueueTier::Context);
+
+        queue.skip_to_next();
+        assert_eq!(current(&queue), Some(2));
+        // Consumed manual items are gone for good.
+        queue.skip_to_previous();
+        assert_eq!(current(&queue), Some(1));
+        queue.skip_to_previous();
+        assert_eq!(current(&queue), Some(1));
+    }
+
+    #[test]
+    fn loop_all_repeats_context_only() {
+        let mut queue = queue(QueueBehavior::LoopAll, &[1, 2], 1);
+        queue.append(item(10));
+
+        let mut played = Vec::new();
+        for _ in 0..5 {
+            queue.skip_to_following();
+            played.extend(current(&queue));
+        }
+        assert_eq!(played, vec![10, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn loop_track_repeats_context_item_only() {
+        let mut queue = queue(QueueBehavior::LoopTrack, &[1, 2], 0);
+        queue.append(item(10));
+        queue.append(item(11));
+
+        // Repeated context item keeps playing until skipped explicitly.
+        assert_eq!(following(&queue), Some(1));
+        queue.skip_to_following();
+        assert_eq!(current(&queue), Some(1));
+
+        // Manual items play once each, then the repeated item continues.
+        queue.skip_to_next();
+        assert_eq!(current(&queue), Some(10));
+        assert_eq!(following(&queue), Some(11));
+        queue.skip_to_following();
+        assert_eq!(current(&queue), Some(11));
+        assert_eq!(following(&queue), Some(1));
+        queue.skip_to_following();
+        assert_eq!(current(&queue), Some(1));
+        assert_eq!(queue.current_tier(), QueueTier::Context);
+        queue.skip_to_following();
+        assert_eq!(current(&queue), Some(1));
+    }
+
+    #[test]
+    fn shuffle_keeps_manual_order() {
+        let context: Vec<u128> = (1..=20).collect();
+        let mut queue = queue(QueueBehavior::Sequential, &context, 0);
+        for id in 100..110 {
+            queue.append(item(id));
+        }
+        queue.set_behaviour(QueueBehavior::Random);
+
+        let mut played = Vec::new();
+        loop {
+            queue.skip_to_next();
+            match current(&queue) {
+                Some(id) => played.push(id),
+                None => break,
+            }
         }
+        // Manual items come first and in order, the context is shuffled behind them.
+        assert_eq!(played[..10], (100..110).collect::<Vec<_>>()[..]);
+        let mut rest = played[10..].to_vec();
+        rest.sort_unstable();
+        assert_eq!(rest, (2..=20).collect::<Vec<_>>());
     }
 }
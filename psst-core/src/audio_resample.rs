@@ -0,0 +1,236 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+use crate::audio_output::AudioSample;
+
+/// Number of input frames on each side of the interpolated position that are
+/// taken into account by the windowed-sinc kernel.
+const KERNEL_HALF_WIDTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct AudioFormat {
+    pub channels: u8,
+    pub sample_rate: u32,
+}
+
+/// Converts an interleaved stream of samples from the input format into the
+/// output format.  Channels are mapped first, then the sample rate is converted
+/// with a band-limited windowed-sinc interpolation.  In case the sample rates
+/// match, samples are only passed through the channel mapping.
+pub struct AudioResampler<S> {
+    source: S,
+    input: AudioFormat,
+    output: AudioFormat,
+    // Ratio of input to output sample rate, how many input frames we advance for
+    // every output frame.
+    step: f64,
+    // Relative cut-off frequency of the low-pass filter, to avoid aliasing when
+    // down-sampling.
+    cutoff: f64,
+    // Interleaved, channel-mapped input frames, around the interpolated position.
+    window: VecDeque<AudioSample>,
+    // Position of the next output frame, in input frames, relative to the first frame
+    // in `window`.
+    position: f64,
+    // Set after the source has run out of samples.
+    is_source_finished: bool,
+    // Output frame that is being currently emitted, and the offset into it.
+    frame: Vec<AudioSample>,
+    frame_pos: usize,
+}
+
+impl<S> AudioResampler<S>
+where
+    S: Iterator<Item = AudioSample>,
+{
+    pub fn new(source: S, input: AudioFormat, output: AudioFormat) -> Self {
+        let mut resampler = Self {
+            source,
+            input,
+            output,
+            step: 1.0,
+            cutoff: 1.0,
+            window: VecDeque::new(),
+            position: 0.0,
+            is_source_finished: false,
+            frame: Vec::new(),
+            frame_pos: 0,
+        };
+        resampler.set_output_format(output);
+        resampler
+    }
+
+    pub fn input_format(&self) -> AudioFormat {
+        self.input
+    }
+
+    pub fn output_format(&self) -> AudioFormat {
+        self.output
+    }
+
+    pub fn set_output_format(&mut self, output: AudioFormat) {
+        self.output = output;
+        self.step = f64::from(self.input.sample_rate) / f64::from(self.output.sample_rate);
+        self.cutoff = self.step.recip().min(1.0);
+        self.reset();
+    }
+
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Drop all buffered state.  Needs to be called after the position of the
+    /// source has changed, i.e. after seeking.
+    pub fn reset(&mut self) {
+        self.window.clear();
+        self.position = 0.0;
+        self.is_source_finished = false;
+        self.frame.clear();
+        self.frame_pos = 0;
+    }
+
+    fn is_passthrough(&self) -> bool {
+        self.input.sample_rate == self.output.sample_rate
+    }
+
+    fn window_len(&self) -> usize {
+        self.window.len() / usize::from(self.output.channels)
+    }
+
+    /// Read a frame from the source, map it to the output channels and push it
+    /// to the back of the window.  Returns false if the source has finished.
+    fn read_frame(&mut self) -> bool {
+        let input_channels = usize::from(self.input.channels);
+        let output_channels = usize::from(self.output.channels);
+        let mut input_frame = [0.0; 8];
+        let input_frame = if input_channels <= input_frame.len() {
+            &mut input_frame[..input_channels]
+        } else {
+            // Unusual channel layout, we only use the first eight channels.
+            &mut input_frame[..]
+        };
+        for (i, sample) in input_frame.iter_mut().enumerate() {
+            match self.source.next() {
+                Some(s) => *sample = s,
+                None if i == 0 => {
+                    self.is_source_finished = true;
+                    return false;
+                }
+                None => {
+                    // Incomplete frame, use silence for the missing channels.
+                    self.is_source_finished = true;
+                    break;
+                }
+            }
+        }
+        // Skip the channels we do not use.
+        for _ in input_frame.len()..input_channels {
+            self.source.next();
+        }
+        for channel in 0..output_channels {
+            self.window
+                .push_back(map_channel(input_frame, channel, output_channels));
+        }
+        true
+    }
+
+    fn next_frame_passthrough(&mut self) -> bool {
+        self.window.clear();
+        if self.read_frame() {
+            self.frame.clear();
+            self.frame.extend(self.window.drain(..));
+            true
+        } else {
+            false
+        }
+    }
+
+    fn next_frame_interpolated(&mut self) -> bool {
+        let output_channels = usize::from(self.output.channels);
+        let center = self.position.floor() as usize;
+        let frac = self.position - center as f64;
+
+        // Make sure we have enough frames in front of the interpolated position.
+        while !self.is_source_finished && self.window_len() < center + KERNEL_HALF_WIDTH + 1 {
+            self.read_frame();
+        }
+        if center >= self.window_len() {
+            return false; // End of stream.
+        }
+
+        self.frame.clear();
+        self.frame.resize(output_channels, 0.0);
+        let first = center.saturating_sub(KERNEL_HALF_WIDTH - 1);
+        let last = (center + KERNEL_HALF_WIDTH).min(self.window_len() - 1);
+        for i in first..=last {
+            let distance = i as f64 - center as f64 - frac;
+            let weight = self.kernel(distance) as AudioSample;
+            for (channel, sample) in self.frame.iter_mut().enumerate() {
+                *sample += self.window[i * output_channels + channel] * weight;
+            }
+        }
+
+        // Advance the position and drop the frames that are not needed anymore.
+        self.position += self.step;
+        let unused = (self.position.floor() as usize)
+            .saturating_sub(KERNEL_HALF_WIDTH - 1)
+            .min(self.window_len());
+        self.window.drain(..unused * output_channels);
+        self.position -= unused as f64;
+
+        true
+    }
+
+    /// Blackman-windowed sinc, scaled to the cut-off frequency.
+    fn kernel(&self, distance: f64) -> f64 {
+        let width = KERNEL_HALF_WIDTH as f64;
+        if distance.abs() >= width {
+            return 0.0;
+        }
+        let x = distance * self.cutoff;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * x).sin() / (PI * x)
+        };
+        let n = (distance + width) / (2.0 * width);
+        let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+        self.cutoff * sinc * window
+    }
+}
+
+/// Compute a sample of the output `channel` from an input frame.
+fn map_channel(input: &[AudioSample], channel: usize, output_channels: usize) -> AudioSample {
+    match (input.len(), output_channels) {
+        (0, _) => 0.0,
+        // Mono source is spread into all output channels.
+        (1, _) => input[0],
+        // Everything is down-mixed into a mono output.
+        (_, 1) => input.iter().sum::<AudioSample>() / input.len() as AudioSample,
+        // Otherwise, channels are mapped 1:1, and extra output channels are silent.
+        _ => input.get(channel).copied().unwrap_or(0.0),
+    }
+}
+
+impl<S> Iterator for AudioResampler<S>
+where
+    S: Iterator<Item = AudioSample>,
+{
+    type Item = AudioSample;
+
+    fn next(&mut self) -> Option<AudioSample> {
+        if self.frame_pos >= self.frame.len() {
+            let has_frame = if self.is_passthrough() {
+                self.next_frame_passthrough()
+            } else {
+                self.next_frame_interpolated()
+            };
+            if !has_frame {
+                return None;
+            }
+            self.frame_pos = 0;
+        }
+        let sample = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some(sample)
+    }
+}
@@ -0,0 +1,317 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use byteorder::{WriteBytesExt, LE};
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
+
+use crate::{
+    audio_output::{AudioOutputRemote, AudioSample, AudioSource, InternalEvent},
+    error::Error,
+};
+
+pub type AudioSourceHandle = Arc<Mutex<dyn AudioSource + Send>>;
+
+/// Destination of the played audio.  `Player` controls the sink through the
+/// `AudioOutputRemote`, while `start_playback` pulls the samples from the audio
+/// source.
+pub trait AudioSink: Send {
+    fn remote(&self) -> AudioOutputRemote;
+
+    /// Start consuming samples from `source`.  Blocks until the sink is closed
+    /// through the remote.
+    fn start_playback(&self, source: AudioSourceHandle) -> Result<(), Error>;
+}
+
+// Format of the samples the software sinks are producing.
+const SINK_CHANNELS: u8 = 2;
+const SINK_SAMPLE_RATE: u32 = 44100;
+const SINK_BITS_PER_SAMPLE: u16 = 16;
+
+// Number of frames we pull out of the source at once.
+const SINK_BUFFER_FRAMES: usize = 1024;
+
+// How long to wait for control events if the source has nothing to play.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Writes raw, interleaved, signed 16-bit little-endian stereo PCM at 44.1kHz
+/// either to stdout, or to a file or a named pipe.
+pub struct PcmSink {
+    path: Option<PathBuf>,
+    events: SinkEvents,
+}
+
+impl PcmSink {
+    pub fn stdout() -> Self {
+        Self {
+            path: None,
+            events: SinkEvents::new(),
+        }
+    }
+
+    pub fn pipe(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            events: SinkEvents::new(),
+        }
+    }
+}
+
+impl AudioSink for PcmSink {
+    fn remote(&self) -> AudioOutputRemote {
+        self.events.remote()
+    }
+
+    fn start_playback(&self, source: AudioSourceHandle) -> Result<(), Error> {
+        let writer: Box<dyn Write> = match &self.path {
+            // Regular files are created or truncated, named pipes are not affected.
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+        let mut writer = BufWriter::new(writer);
+        run_software_sink(&self.events, &source, Pacing::Unlimited, |samples| {
+            write_pcm_samples(&mut writer, samples)?;
+            writer.flush()
+        })?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes the audio into a WAV file, as signed 16-bit stereo PCM at 44.1kHz.
+pub struct WavSink {
+    path: PathBuf,
+    events: SinkEvents,
+}
+
+impl WavSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            events: SinkEvents::new(),
+        }
+    }
+}
+
+impl AudioSink for WavSink {
+    fn remote(&self) -> AudioOutputRemote {
+        self.events.remote()
+    }
+
+    fn start_playback(&self, source: AudioSourceHandle) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        // Sizes in the header are not known yet, we fill them in after the playback ends.
+        write_wav_header(&mut writer, 0)?;
+        let mut data_len = 0;
+        run_software_sink(&self.events, &source, Pacing::Unlimited, |samples| {
+            data_len += samples.len() as u32 * u32::from(SINK_BITS_PER_SAMPLE / 8);
+            write_pcm_samples(&mut writer, samples)
+        })?;
+        writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut writer, data_len)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Throws the audio away, either in real time, or as fast as the source can
+/// produce it.  Useful for running the player without any audio hardware.
+pub struct NullSink {
+    pacing: Pacing,
+    events: SinkEvents,
+}
+
+impl NullSink {
+    pub fn real_time() -> Self {
+        Self {
+            pacing: Pacing::RealTime,
+            events: SinkEvents::new(),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self {
+            pacing: Pacing::Unlimited,
+            events: SinkEvents::new(),
+        }
+    }
+}
+
+impl AudioSink for NullSink {
+    fn remote(&self) -> AudioOutputRemote {
+        self.events.remote()
+    }
+
+    fn start_playback(&self, source: AudioSourceHandle) -> Result<(), Error> {
+        run_software_sink(&self.events, &source, self.pacing, |_samples| Ok(()))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Pacing {
+    RealTime,
+    Unlimited,
+}
+
+struct SinkEvents {
+    event_sender: Sender<InternalEvent>,
+    event_receiver: Receiver<InternalEvent>,
+}
+
+impl SinkEvents {
+    fn new() -> Self {
+        let (event_sender, event_receiver) = unbounded();
+        Self {
+            event_sender,
+            event_receiver,
+        }
+    }
+
+    fn remote(&self) -> AudioOutputRemote {
+        AudioOutputRemote::new(self.event_sender.clone())
+    }
+}
+
+/// Pull samples from `source` while the sink is playing, and pass them to
+/// `write`.  Returns after the sink gets closed.
+fn run_software_sink(
+    events: &SinkEvents,
+    source: &AudioSourceHandle,
+    pacing: Pacing,
+    mut write: impl FnMut(&[AudioSample]) -> io::Result<()>,
+) -> Result<(), Error> {
+    source
+        .lock()
+        .expect("Failed to acquire audio source lock")
+        .set_output_format(SINK_CHANNELS, SINK_SAMPLE_RATE);
+
+    let mut is_playing = false;
+    let mut volume = 1.0;
+    let mut buffer = Vec::with_capacity(SINK_BUFFER_FRAMES * usize::from(SINK_CHANNELS));
+    // Time when we started playing, and the number of frames written since, for
+    // real-time pacing.
+    let mut clock = (Instant::now(), 0_u64);
+    // Event received while waiting for the source, handled in the next iteration.
+    let mut pending = None;
+
+    loop {
+        // While paused, block until something happens.  While playing, only look for
+        // pending events.
+        let event = if pending.is_some() {
+            pending.take()
+        } else if is_playing {
+            match events.event_receiver.try_recv() {
+                Ok(event) => Some(event),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match events.event_receiver.recv() {
+                Ok(event) => Some(event),
+                Err(_) => break,
+            }
+        };
+        match event {
+            Some(InternalEvent::Close) => {
+                log::debug!("closing audio sink");
+                break;
+            }
+            Some(InternalEvent::Pause) => {
+                log::debug!("pausing audio sink");
+                is_playing = false;
+            }
+            Some(InternalEvent::Resume) => {
+                log::debug!("resuming audio sink");
+                is_playing = true;
+                clock = (Instant::now(), 0);
+            }
+            Some(InternalEvent::SetVolume(new_volume)) => {
+                volume = new_volume as f32;
+            }
+            Some(InternalEvent::SwitchDevice(_)) | Some(InternalEvent::DeviceStopped) => {
+                // Software sinks do not have any devices.
+            }
+            None => {}
+        }
+        if !is_playing {
+            continue;
+        }
+
+        buffer.clear();
+        {
+            let mut source = source.lock().expect("Failed to acquire audio source lock");
+            let norm_factor = source.normalization_factor().unwrap_or(1.0);
+            // `by_ref` needs a sized iterator, borrow the trait object instead.
+            let source = &mut *source;
+            buffer.extend(
+                source
+                    .take(buffer.capacity())
+                    .map(|s| s * norm_factor * volume),
+            );
+        }
+        if buffer.is_empty() {
+            // Source has nothing to play right now, wait a bit for the player to catch up.
+            match events.event_receiver.recv_timeout(IDLE_TIMEOUT) {
+                Ok(event) => {
+                    pending.replace(event);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            clock = (Instant::now(), 0);
+            continue;
+        }
+        write(&buffer)?;
+
+        if let Pacing::RealTime = pacing {
+            let (started, frames) = &mut clock;
+            *frames += (buffer.len() / usize::from(SINK_CHANNELS)) as u64;
+            let deadline =
+                *started + Duration::from_secs_f64(*frames as f64 / f64::from(SINK_SAMPLE_RATE));
+            if let Some(delay) = deadline.checked_duration_since(Instant::now()) {
+                thread::sleep(delay);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_pcm_samples(writer: &mut impl Write, samples: &[AudioSample]) -> io::Result<()> {
+    for &sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        writer.write_i16::<LE>(sample)?;
+    }
+    Ok(())
+}
+
+fn write_wav_header(writer: &mut impl Write, data_len: u32) -> io::Result<()> {
+    let block_align = u16::from(SINK_CHANNELS) * SINK_BITS_PER_SAMPLE / 8;
+    let byte_rate = SINK_SAMPLE_RATE * u32::from(block_align);
+    writer.write_all(b"RIFF")?;
+    writer.write_u32::<LE>(36 + data_len)?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_u32::<LE>(16)?; // Size of the format chunk.
+    writer.write_u16::<LE>(1)?; // PCM.
+    writer.write_u16::<LE>(u16::from(SINK_CHANNELS))?;
+    writer.write_u32::<LE>(SINK_SAMPLE_RATE)?;
+    writer.write_u32::<LE>(byte_rate)?;
+    writer.write_u16::<LE>(block_align)?;
+    writer.write_u16::<LE>(SINK_BITS_PER_SAMPLE)?;
+    writer.write_all(b"data")?;
+    writer.write_u32::<LE>(data_len)?;
+    Ok(())
+}
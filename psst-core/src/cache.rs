@@ -1,10 +1,15 @@
 use std::{
-    fs, io,
+    fs::{self, File},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use psst_protocol::metadata::Track;
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use psst_protocol::metadata::{Album, Artist, Episode, Show, Track};
+use sha1::{Digest, Sha1};
+use tempfile::NamedTempFile;
 
 use crate::{
     audio_key::AudioKey,
@@ -25,25 +30,99 @@ impl Cache {
 
         // Create the cache structure.
         mkdir_if_not_exists(&base)?;
-        mkdir_if_not_exists(&base.join("track"))?;
-        mkdir_if_not_exists(&base.join("audio"))?;
-        mkdir_if_not_exists(&base.join("key"))?;
+        for dir in &CACHE_DIRS {
+            mkdir_if_not_exists(&base.join(dir))?;
+        }
+
+        // Files we have been writing when the app crashed, never renamed into place.
+        remove_partial_files(&base);
+        for dir in &CACHE_DIRS {
+            remove_partial_files(&base.join(dir));
+        }
 
         let cache = Self { base };
+        if let Err(err) = cache.migrate() {
+            log::error!("failed to migrate cache: {}", err);
+        }
         Ok(Arc::new(cache))
     }
 }
 
+const CACHE_DIRS: [&str; 8] = [
+    "track", "episode", "album", "artist", "show", "audio", "key", "resume",
+];
+
+// Version of the cache layout, kept in the `version` file.  Caches without it
+// have been written before the entries got their header.
+const CACHE_VERSION: u32 = 1;
+
+// Upgrade of caches written by previous versions.
+impl Cache {
+    fn migrate(&self) -> Result<(), Error> {
+        let version_path = self.base.join("version");
+        let version = fs::read_to_string(&version_path)
+            .ok()
+            .and_then(|version| version.trim().parse().ok())
+            .unwrap_or(0);
+        if version >= CACHE_VERSION {
+            return Ok(());
+        }
+        log::info!(
+            "migrating cache from version {} to {}",
+            version,
+            CACHE_VERSION
+        );
+        // Entries have been plain files with the content only.
+        for dir in &CACHE_DIRS {
+            if *dir != "audio" {
+                for path in list_files(&self.base.join(dir)) {
+                    migrate_entry(&path);
+                }
+            }
+        }
+        if self.country_code_path().exists() {
+            migrate_entry(&self.country_code_path());
+        }
+        // Audio files get their checksum lazily, see `Cache::get_audio_file`.
+        write_atomically(&version_path, |file| {
+            file.write_all(CACHE_VERSION.to_string().as_bytes())
+        })?;
+        Ok(())
+    }
+}
+
+fn migrate_entry(path: &Path) {
+    let result = fs::read(path).and_then(|content| write_entry(path, &content));
+    if let Err(err) = result {
+        log::warn!("failed to migrate cache entry {:?}: {}", path, err);
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn list_files(dir: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect(),
+        Err(err) => {
+            log::warn!("failed to list cache directory {:?}: {}", dir, err);
+            Vec::new()
+        }
+    }
+}
+
 // Cache of `Track` protobuf structures.
 impl Cache {
     pub fn get_track(&self, item_id: ItemId) -> Option<Track> {
-        let buf = fs::read(self.track_path(item_id)).ok()?;
+        let buf = read_entry(&self.track_path(item_id))?;
         deserialize_protobuf(&buf).ok()
     }
 
     pub fn save_track(&self, item_id: ItemId, track: &Track) -> Result<(), Error> {
         log::debug!("saving track to cache: {:?}", item_id);
-        fs::write(self.track_path(item_id), &serialize_protobuf(track)?)?;
+        write_entry(&self.track_path(item_id), &serialize_protobuf(track)?)?;
         Ok(())
     }
 
@@ -52,10 +131,82 @@ impl Cache {
     }
 }
 
+// Cache of `Episode` protobuf structures.
+impl Cache {
+    pub fn get_episode(&self, item_id: ItemId) -> Option<Episode> {
+        let buf = read_entry(&self.episode_path(item_id))?;
+        deserialize_protobuf(&buf).ok()
+    }
+
+    pub fn save_episode(&self, item_id: ItemId, episode: &Episode) -> Result<(), Error> {
+        log::debug!("saving episode to cache: {:?}", item_id);
+        write_entry(&self.episode_path(item_id), &serialize_protobuf(episode)?)?;
+        Ok(())
+    }
+
+    fn episode_path(&self, item_id: ItemId) -> PathBuf {
+        self.base.join("episode").join(item_id.to_base62())
+    }
+}
+
+// Cache of `Album` protobuf structures.
+impl Cache {
+    pub fn get_album(&self, item_id: ItemId) -> Option<Album> {
+        let buf = read_entry(&self.album_path(item_id))?;
+        deserialize_protobuf(&buf).ok()
+    }
+
+    pub fn save_album(&self, item_id: ItemId, album: &Album) -> Result<(), Error> {
+        log::debug!("saving album to cache: {:?}", item_id);
+        write_entry(&self.album_path(item_id), &serialize_protobuf(album)?)?;
+        Ok(())
+    }
+
+    fn album_path(&self, item_id: ItemId) -> PathBuf {
+        self.base.join("album").join(item_id.to_base62())
+    }
+}
+
+// Cache of `Artist` protobuf structures.
+impl Cache {
+    pub fn get_artist(&self, item_id: ItemId) -> Option<Artist> {
+        let buf = read_entry(&self.artist_path(item_id))?;
+        deserialize_protobuf(&buf).ok()
+    }
+
+    pub fn save_artist(&self, item_id: ItemId, artist: &Artist) -> Result<(), Error> {
+        log::debug!("saving artist to cache: {:?}", item_id);
+        write_entry(&self.artist_path(item_id), &serialize_protobuf(artist)?)?;
+        Ok(())
+    }
+
+    fn artist_path(&self, item_id: ItemId) -> PathBuf {
+        self.base.join("artist").join(item_id.to_base62())
+    }
+}
+
+// Cache of `Show` protobuf structures.
+impl Cache {
+    pub fn get_show(&self, item_id: ItemId) -> Option<Show> {
+        let buf = read_entry(&self.show_path(item_id))?;
+        deserialize_protobuf(&buf).ok()
+    }
+
+    pub fn save_show(&self, item_id: ItemId, show: &Show) -> Result<(), Error> {
+        log::debug!("saving show to cache: {:?}", item_id);
+        write_entry(&self.show_path(item_id), &serialize_protobuf(show)?)?;
+        Ok(())
+    }
+
+    fn show_path(&self, item_id: ItemId) -> PathBuf {
+        self.base.join("show").join(item_id.to_base62())
+    }
+}
+
 // Cache of `AudioKey`s.
 impl Cache {
     pub fn get_audio_key(&self, item_id: ItemId, file_id: FileId) -> Option<AudioKey> {
-        let buf = fs::read(self.audio_key_path(item_id, file_id)).ok()?;
+        let buf = read_entry(&self.audio_key_path(item_id, file_id))?;
         AudioKey::from_raw(&buf)
     }
 
@@ -66,7 +217,7 @@ impl Cache {
         key: &AudioKey,
     ) -> Result<(), Error> {
         log::debug!("saving audio key to cache: {:?}:{:?}", item_id, file_id);
-        fs::write(self.audio_key_path(item_id, file_id), &key.0)?;
+        write_entry(&self.audio_key_path(item_id, file_id), &key.0)?;
         Ok(())
     }
 
@@ -78,27 +229,206 @@ impl Cache {
     }
 }
 
-// Cache of encrypted audio file content.
+// Cache of encrypted audio file content.  Next to every file, we keep its
+// length and checksum, computed while the file is being saved, and written
+// before the file itself is renamed into place, so the files that have not been
+// saved whole are never played.  A file without a checksum has been saved by a
+// version that did not write them, it gets its checksum the first time it is
+// opened.
 impl Cache {
-    pub fn audio_file_path(&self, file_id: FileId) -> PathBuf {
-        self.base.join("audio").join(file_id.to_base16())
+    /// Path of the cached audio file, in case it is complete.  Only the length
+    /// is compared on open, files that do not match it are removed.
+    pub fn get_audio_file(&self, file_id: FileId) -> Option<PathBuf> {
+        let path = self.audio_file_path(file_id);
+        let length = fs::metadata(&path).ok()?.len();
+        match self.get_audio_file_checksum(file_id) {
+            Some(checksum) if checksum.length == length => Some(path),
+            Some(_) => {
+                log::warn!("discarding truncated audio file: {:?}", file_id);
+                self.remove_audio_file(file_id);
+                None
+            }
+            None => self.migrate_audio_file(file_id),
+        }
+    }
+
+    /// Quick check that the audio file is cached, comparing only its length.
+    pub fn has_audio_file(&self, file_id: FileId) -> bool {
+        let length = fs::metadata(self.audio_file_path(file_id)).map(|m| m.len());
+        match (self.get_audio_file_checksum(file_id), length) {
+            (Some(checksum), Ok(length)) => checksum.length == length,
+            _ => false,
+        }
     }
 
     pub fn save_audio_file(&self, file_id: FileId, from_path: PathBuf) -> Result<(), Error> {
         log::debug!("saving audio file to cache: {:?}", file_id);
-        fs::copy(from_path, self.audio_file_path(file_id))?;
+        let path = self.audio_file_path(file_id);
+        let (tmp_file, checksum) = write_partial(&path, |file| {
+            AudioFileChecksum::copy(&mut File::open(&from_path)?, file)
+        })?;
+        // Checksum goes first, so a reader never sees the new file without it.  A
+        // previous file would not match it, drop it right away.
+        remove_file_if_exists(&path)?;
+        write_entry(&self.audio_checksum_path(file_id), &checksum.to_bytes())?;
+        persist(tmp_file, &path)?;
         Ok(())
     }
+
+    /// Compute the missing checksum of a file saved by a previous version.  We
+    /// trust its contents the same way we did before.
+    fn migrate_audio_file(&self, file_id: FileId) -> Option<PathBuf> {
+        log::info!("computing checksum of cached audio file: {:?}", file_id);
+        let path = self.audio_file_path(file_id);
+        let result = AudioFileChecksum::compute(&path).and_then(|checksum| {
+            write_entry(&self.audio_checksum_path(file_id), &checksum.to_bytes())
+        });
+        match result {
+            Ok(_) => Some(path),
+            Err(err) => {
+                log::warn!("failed to migrate audio file {:?}: {}", file_id, err);
+                None
+            }
+        }
+    }
+
+    fn get_audio_file_checksum(&self, file_id: FileId) -> Option<AudioFileChecksum> {
+        read_entry(&self.audio_checksum_path(file_id))
+            .and_then(|buf| AudioFileChecksum::from_bytes(&buf))
+    }
+
+    fn remove_audio_file(&self, file_id: FileId) {
+        let _ = fs::remove_file(self.audio_file_path(file_id));
+        let _ = fs::remove_file(self.audio_checksum_path(file_id));
+    }
+
+    fn audio_file_path(&self, file_id: FileId) -> PathBuf {
+        self.base.join("audio").join(file_id.to_base16())
+    }
+
+    fn audio_checksum_path(&self, file_id: FileId) -> PathBuf {
+        self.base
+            .join("audio")
+            .join(format!("{}.checksum", file_id.to_base16()))
+    }
+}
+
+/// Length and SHA-1 digest of a cached audio file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AudioFileChecksum {
+    length: u64,
+    digest: [u8; 20],
+}
+
+impl AudioFileChecksum {
+    /// Copy all of `reader` into `writer`, and compute the checksum of the data.
+    fn copy(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<Self> {
+        let mut hasher = Sha1::new();
+        let mut length = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            hasher.update(&buf[..read]);
+            writer.write_all(&buf[..read])?;
+            length += read as u64;
+        }
+        let mut digest = [0; 20];
+        digest.copy_from_slice(&hasher.finalize());
+        Ok(Self { length, digest })
+    }
+
+    fn compute(path: &Path) -> io::Result<Self> {
+        Self::copy(&mut File::open(path)?, &mut io::sink())
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(8 + self.digest.len());
+        buf.write_u64::<LE>(self.length)
+            .expect("Failed to write to a vector");
+        buf.extend_from_slice(&self.digest);
+        buf
+    }
+
+    fn from_bytes(mut buf: &[u8]) -> Option<Self> {
+        let length = buf.read_u64::<LE>().ok()?;
+        let mut digest = [0; 20];
+        if buf.len() != digest.len() {
+            return None;
+        }
+        digest.copy_from_slice(buf);
+        Some(Self { length, digest })
+    }
+}
+
+/// Stored playback position of a long-form item, see `Cache::get_resume_position`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ResumePosition {
+    /// Playback has stopped at this position.
+    Position(Duration),
+    /// Item has been played through.
+    Finished,
+}
+
+// Cache of resume positions, stored as an entry per item, containing either the
+// position in milliseconds as text, or `finished`.
+impl Cache {
+    pub fn get_resume_position(&self, item_id: ItemId) -> Option<ResumePosition> {
+        let buf = read_entry(&self.resume_position_path(item_id))?;
+        match String::from_utf8_lossy(&buf).trim() {
+            "finished" => Some(ResumePosition::Finished),
+            millis => millis
+                .parse()
+                .ok()
+                .map(Duration::from_millis)
+                .map(ResumePosition::Position),
+        }
+    }
+
+    pub fn save_resume_position(
+        &self,
+        item_id: ItemId,
+        position: ResumePosition,
+    ) -> Result<(), Error> {
+        let content = match position {
+            ResumePosition::Position(position) => position.as_millis().to_string(),
+            ResumePosition::Finished => "finished".to_string(),
+        };
+        write_entry(&self.resume_position_path(item_id), content.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn remove_resume_position(&self, item_id: ItemId) -> Result<(), Error> {
+        remove_file_if_exists(&self.resume_position_path(item_id))?;
+        Ok(())
+    }
+
+    pub fn clear_resume_positions(&self) -> Result<(), Error> {
+        log::info!("clearing resume positions");
+        let dir = self.base.join("resume");
+        fs::remove_dir_all(&dir)?;
+        mkdir_if_not_exists(&dir)?;
+        Ok(())
+    }
+
+    fn resume_position_path(&self, item_id: ItemId) -> PathBuf {
+        self.base.join("resume").join(item_id.to_base62())
+    }
 }
 
 // Cache of user country code.
 impl Cache {
     pub fn get_country_code(&self) -> Option<String> {
-        fs::read_to_string(self.country_code_path()).ok()
+        let buf = read_entry(&self.country_code_path())?;
+        String::from_utf8(buf).ok()
     }
 
     pub fn save_country_code(&self, country_code: &str) -> Result<(), Error> {
-        fs::write(self.country_code_path(), country_code)?;
+        write_entry(&self.country_code_path(), country_code.as_bytes())?;
         Ok(())
     }
 
@@ -107,6 +437,105 @@ impl Cache {
     }
 }
 
+// Every cache entry starts with the length of its content and the SHA-1 digest
+// of it, so entries that have been truncated or damaged are detected, and
+// thrown away as if they were not cached.
+const ENTRY_HEADER_LENGTH: usize = 8 + 20;
+
+fn read_entry(path: &Path) -> Option<Vec<u8>> {
+    let mut buf = fs::read(path).ok()?;
+    if is_entry_valid(&buf) {
+        Some(buf.split_off(ENTRY_HEADER_LENGTH))
+    } else {
+        log::warn!("discarding corrupted cache entry: {:?}", path);
+        let _ = fs::remove_file(path);
+        None
+    }
+}
+
+fn is_entry_valid(buf: &[u8]) -> bool {
+    if buf.len() < ENTRY_HEADER_LENGTH {
+        return false;
+    }
+    let (mut header, content) = buf.split_at(ENTRY_HEADER_LENGTH);
+    let length = header.read_u64::<LE>().unwrap_or_default();
+    length == content.len() as u64 && header == Sha1::digest(content).as_slice()
+}
+
+fn write_entry(path: &Path, content: &[u8]) -> io::Result<()> {
+    write_atomically(path, |file| {
+        file.write_u64::<LE>(content.len() as u64)?;
+        file.write_all(&Sha1::digest(content))?;
+        file.write_all(content)
+    })
+}
+
+// Prefix of the temporary files we write the cache entries into.
+const PARTIAL_FILE_PREFIX: &str = ".partial";
+
+/// Write a file through a temporary file in the same directory, that is
+/// renamed into place once it is completely written and synced to the disk.
+/// The file at `path` is therefore either complete, or not there at all.
+fn write_atomically<T>(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> io::Result<T> {
+    let (tmp_file, result) = write_partial(path, write)?;
+    persist(tmp_file, path)?;
+    Ok(result)
+}
+
+/// Write and sync a temporary file next to `path`, see `persist`.
+fn write_partial<T>(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<T>,
+) -> io::Result<(NamedTempFile, T)> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut tmp_file = tempfile::Builder::new()
+        .prefix(PARTIAL_FILE_PREFIX)
+        .tempfile_in(dir)?;
+    let result = write(tmp_file.as_file_mut())?;
+    tmp_file.as_file().sync_all()?;
+    Ok((tmp_file, result))
+}
+
+/// Rename a temporary file of `write_partial` into place.
+fn persist(tmp_file: NamedTempFile, path: &Path) -> io::Result<()> {
+    tmp_file.persist(path).map_err(|err| err.error)?;
+    // Make sure the rename itself survives a crash.
+    #[cfg(unix)]
+    File::open(path.parent().unwrap_or_else(|| Path::new(".")))?.sync_all()?;
+    Ok(())
+}
+
+fn remove_file_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Remove the temporary files of `write_atomically` that have been left behind.
+fn remove_partial_files(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::warn!("failed to list cache directory {:?}: {}", dir, err);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(PARTIAL_FILE_PREFIX)
+        {
+            log::info!("removing partially written file: {:?}", entry.path());
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
 pub fn mkdir_if_not_exists(path: &Path) -> io::Result<()> {
     fs::create_dir(path).or_else(|err| {
         if err.kind() == io::ErrorKind::AlreadyExists {
@@ -1,5 +1,5 @@
 use std::{
-    io::Read,
+    io::{self, Read},
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -52,20 +52,13 @@ impl Cdn {
             fileid: String,
         }
 
-        // Deserialize the response and pick a file URL from the returned CDN list.
+        // Deserialize the response and keep the whole CDN list, so we can fail over
+        // to the other hosts.  The first URL is tried first.
         let locations: AudioFileLocations = response.into_json()?;
-        let file_uri = locations
-            .cdnurl
-            .into_iter()
-            // TODO:
-            //  Now, we always pick the first URL in the list, figure out a better strategy.
-            //  Choosing by random seems wrong.
-            .next()
-            // TODO: Avoid panicking here.
-            .expect("No file URI found");
-
-        let uri = CdnUrl::new(file_uri);
-        Ok(uri)
+        if locations.cdnurl.is_empty() {
+            return Err(Error::AudioFileNotFound);
+        }
+        Ok(CdnUrl::new(locations.cdnurl))
     }
 
     pub fn fetch_file_range(
@@ -79,7 +72,7 @@ impl Cdn {
             .get(uri)
             .set("Range", &range_header(offset, length))
             .call()?;
-        let total_length = parse_total_content_length(&response);
+        let total_length = parse_total_content_length(&response)?;
         let data_reader = response.into_reader();
         Ok((total_length, data_reader))
     }
@@ -87,8 +80,12 @@ impl Cdn {
 
 #[derive(Clone)]
 pub struct CdnUrl {
-    pub url: String,
-    pub expires: Instant,
+    // URLs of the file on all the hosts we know of, never empty.
+    urls: Vec<String>,
+    // Index of the URL currently in use.
+    current: usize,
+    /// `None` for URLs that never expire.
+    pub expires: Option<Instant>,
 }
 
 impl CdnUrl {
@@ -98,17 +95,100 @@ impl CdnUrl {
     // Consider URL expired even before the official expiration time.
     const EXPIRATION_TIME_THRESHOLD: Duration = Duration::from_secs(5);
 
-    fn new(url: String) -> Self {
-        let expires_in = parse_expiration(&url).unwrap_or_else(|| {
-            log::warn!("failed to parse expiration time from URL {:?}", &url);
-            Self::DEFAULT_EXPIRATION
-        });
-        let expires = Instant::now() + expires_in;
-        Self { url, expires }
+    fn new(urls: Vec<String>) -> Self {
+        let expires_in = urls
+            .iter()
+            .find_map(|url| parse_expiration(url))
+            .unwrap_or_else(|| {
+                log::warn!("failed to parse expiration time from URLs {:?}", &urls);
+                Self::DEFAULT_EXPIRATION
+            });
+        let expires = Some(Instant::now() + expires_in);
+        Self {
+            urls,
+            current: 0,
+            expires,
+        }
+    }
+
+    /// URL of a file that is not hosted on the CDN, and does not expire.
+    pub fn external(url: String) -> Self {
+        Self {
+            urls: vec![url],
+            current: 0,
+            expires: None,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.urls[self.current]
+    }
+
+    /// Number of hosts the file can be fetched from.
+    pub fn host_count(&self) -> usize {
+        self.urls.len()
+    }
+
+    /// Switch over to the next host in the list, after the current one failed.
+    pub fn rotate(&mut self) {
+        self.current = (self.current + 1) % self.urls.len();
+    }
+
+    /// Consider the URL expired right away, after the CDN has refused it.  URLs
+    /// that never expire are kept.
+    pub fn expire(&mut self) {
+        if self.expires.is_some() {
+            self.expires = Some(Instant::now());
+        }
     }
 
     pub fn is_expired(&self) -> bool {
-        self.expires.saturating_duration_since(Instant::now()) < Self::EXPIRATION_TIME_THRESHOLD
+        match self.expires {
+            Some(expires) => {
+                expires.saturating_duration_since(Instant::now()) < Self::EXPIRATION_TIME_THRESHOLD
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns true for errors that might go away if the request is repeated, like
+/// dropped connections or server errors.  Local I/O errors, like failed writes
+/// of the downloaded data, are not transient.
+pub fn is_transient_error(err: &Error) -> bool {
+    match err {
+        Error::AudioFetchingError(err) => match err.downcast_ref::<ureq::Error>() {
+            Some(ureq::Error::Status(code, _)) => *code == 403 || *code == 429 || *code >= 500,
+            Some(ureq::Error::Transport(_)) => true,
+            // Reading of the response body failed.
+            None => err.is::<io::Error>(),
+        },
+        _ => false,
+    }
+}
+
+/// Returns true for errors caused by the host, where another host of the CDN is
+/// likely to do better.
+pub fn is_host_error(err: &Error) -> bool {
+    match err {
+        Error::AudioFetchingError(err) => match err.downcast_ref::<ureq::Error>() {
+            Some(ureq::Error::Status(code, _)) => *code >= 500,
+            Some(ureq::Error::Transport(_)) => true,
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Returns true if the CDN has refused the URL, most probably because its
+/// signature has expired, and the URL needs to be resolved again.
+pub fn is_url_refused(err: &Error) -> bool {
+    match err {
+        Error::AudioFetchingError(err) => matches!(
+            err.downcast_ref::<ureq::Error>(),
+            Some(ureq::Error::Status(403, _))
+        ),
+        _ => false,
     }
 }
 
@@ -128,15 +208,19 @@ fn range_header(offfset: u64, length: u64) -> String {
 ///
 /// For example, returns 146515 for a response with header
 /// "Content-Range: bytes 0-1023/146515".
-fn parse_total_content_length(response: &ureq::Response) -> u64 {
-    response
-        .header("Content-Range")
-        .expect("Content-Range header not found")
+fn parse_total_content_length(response: &ureq::Response) -> Result<u64, Error> {
+    let content_range = response.header("Content-Range").ok_or_else(|| {
+        log::error!("missing Content-Range header");
+        Error::UnexpectedResponse
+    })?;
+    content_range
         .split('/')
         .last()
-        .expect("Failed to parse Content-Range Header")
-        .parse()
-        .expect("Failed to parse Content-Range Header")
+        .and_then(|total_length| total_length.parse().ok())
+        .ok_or_else(|| {
+            log::error!("failed to parse Content-Range header: {:?}", content_range);
+            Error::UnexpectedResponse
+        })
 }
 
 /// Parses an expiration of an audio file URL.
@@ -4,6 +4,11 @@ use std::{convert::TryInto, fmt, ops::Deref};
 pub enum ItemIdType {
     Track,
     Podcast,
+    Album,
+    Artist,
+    Show,
+    /// Audio file of the local music folder, see `LocalLibrary`.
+    LocalFile,
     Unknown,
 }
 
@@ -52,6 +57,12 @@ impl ItemId {
             Self::from_base62(gid, ItemIdType::Podcast)
         } else if uri.contains(":track:") {
             Self::from_base62(gid, ItemIdType::Track)
+        } else if uri.contains(":album:") {
+            Self::from_base62(gid, ItemIdType::Album)
+        } else if uri.contains(":artist:") {
+            Self::from_base62(gid, ItemIdType::Artist)
+        } else if uri.contains(":show:") {
+            Self::from_base62(gid, ItemIdType::Show)
         } else {
             Self::from_base62(gid, ItemIdType::Unknown)
         }
@@ -1,19 +1,25 @@
 #![allow(clippy::new_without_default)]
 
 pub mod access_token;
+pub mod audio_buffer;
 pub mod audio_decode;
 pub mod audio_decrypt;
+pub mod audio_equalizer;
 pub mod audio_file;
 pub mod audio_key;
+pub mod audio_limiter;
 pub mod audio_normalize;
 pub mod audio_output;
 pub mod audio_player;
 pub mod audio_queue;
+pub mod audio_resample;
+pub mod audio_sink;
 pub mod cache;
 pub mod cdn;
 pub mod connection;
 pub mod error;
 pub mod item_id;
+pub mod local_library;
 pub mod mercury;
 pub mod metadata;
 pub mod session;
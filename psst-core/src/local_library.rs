@@ -0,0 +1,311 @@
+use std::{
+    fs,
+    fs::File,
+    io,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use byteorder::{ReadBytesExt, LE};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    audio_decode::{AudioCodec, AudioInfo, SymphoniaDecoder},
+    error::Error,
+    item_id::{FileId, ItemId, ItemIdType},
+};
+
+/// Audio files of a local music folder, indexed by their tags, so they can be
+/// matched to the local entries of Spotify playlists.
+pub struct LocalLibrary {
+    dir: PathBuf,
+    tracks: Vec<LocalTrack>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalTrack {
+    pub item_id: ItemId,
+    pub file_id: FileId,
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub duration: Duration,
+}
+
+impl LocalLibrary {
+    /// Extensions of the files we are able to decode.
+    pub const EXTENSIONS: &'static [&'static str] = &["ogg", "oga", "mp3", "flac", "wav"];
+
+    /// Index all playable files in `dir` and its subdirectories.  Files that
+    /// fail to open are skipped.
+    pub fn scan(dir: PathBuf) -> Result<Self, Error> {
+        log::info!("scanning local files: {:?}", dir);
+        let mut paths = Vec::new();
+        collect_audio_files(&dir, &mut paths)?;
+        let tracks = paths
+            .into_iter()
+            .filter_map(|path| match LocalTrack::read(&dir, path) {
+                Ok(track) => Some(track),
+                Err(err) => {
+                    log::warn!("failed to read local file: {}", err);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        log::info!("found {} local files", tracks.len());
+        Ok(Self { dir, tracks })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn tracks(&self) -> &[LocalTrack] {
+        &self.tracks
+    }
+
+    pub fn get(&self, item_id: ItemId) -> Option<&LocalTrack> {
+        self.tracks.iter().find(|track| track.item_id == item_id)
+    }
+
+    /// Find the file best matching the metadata of a local track entry.  Title
+    /// has to match, artist has to be a part of the file's artist, and tracks
+    /// from the same album are preferred.  Case and whitespace are ignored.
+    pub fn find(&self, artist: &str, album: &str, title: &str) -> Option<&LocalTrack> {
+        let artist = normalize(artist);
+        let album = normalize(album);
+        let title = normalize(title);
+        let mut candidates = self.tracks.iter().filter(|track| {
+            normalize(&track.title) == title && normalize(&track.artist).contains(&artist)
+        });
+        let first = candidates.next()?;
+        if normalize(&first.album) == album {
+            return Some(first);
+        }
+        Some(
+            candidates
+                .find(|track| normalize(&track.album) == album)
+                .unwrap_or(first),
+        )
+    }
+
+    /// Resolve `item_id` to a file in `dir`, without reading any tags.
+    pub fn find_file(dir: &Path, item_id: ItemId) -> Option<PathBuf> {
+        let mut paths = Vec::new();
+        if let Err(err) = collect_audio_files(dir, &mut paths) {
+            log::warn!("failed to list local files: {}", err);
+        }
+        paths
+            .into_iter()
+            .find(|path| Self::item_id(dir, path) == item_id)
+    }
+
+    /// Derive a stable ID of a local file from its path relative to `dir`.
+    pub fn item_id(dir: &Path, path: &Path) -> ItemId {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        let digest = Sha1::digest(relative.to_string_lossy().as_bytes());
+        let mut id = [0; 16];
+        id.copy_from_slice(&digest[..16]);
+        ItemId::new(u128::from_be_bytes(id), ItemIdType::LocalFile)
+    }
+}
+
+impl LocalTrack {
+    /// Read the tags and the duration of the file at `path`.  Missing tags are
+    /// guessed from the `Artist/Album/Title.ogg` folder structure.
+    pub fn read(dir: &Path, path: PathBuf) -> Result<Self, Error> {
+        let mut file = BufReader::new(File::open(&path)?);
+        let info = match AudioCodec::detect(&mut file)? {
+            AudioCodec::Vorbis => read_vorbis_info(&mut file)?,
+            codec => SymphoniaDecoder::read_info(file, codec)?,
+        };
+        let tag = |key: &str| {
+            info.tags
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone())
+        };
+        let parent_name = |level: usize| {
+            path.ancestors()
+                .nth(level)
+                .filter(|ancestor| ancestor.starts_with(dir) && *ancestor != dir)
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_string())
+        };
+        let file_id = {
+            let mut id = [0; 20];
+            id.copy_from_slice(&Sha1::digest(path.to_string_lossy().as_bytes()));
+            FileId(id)
+        };
+        Ok(Self {
+            item_id: LocalLibrary::item_id(dir, &path),
+            file_id,
+            title: tag("TITLE")
+                .or_else(|| {
+                    path.file_stem()
+                        .map(|stem| strip_track_number(&stem.to_string_lossy()).to_string())
+                })
+                .unwrap_or_default(),
+            artist: tag("ARTIST").or_else(|| parent_name(2)).unwrap_or_default(),
+            album: tag("ALBUM").or_else(|| parent_name(1)).unwrap_or_default(),
+            duration: info.duration,
+            path,
+        })
+    }
+}
+
+fn collect_audio_files(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_audio_files(&path, paths)?;
+        } else if is_audio_file(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .map_or(false, |ext| {
+            LocalLibrary::EXTENSIONS.contains(&ext.as_str())
+        })
+}
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Strip a leading track number, as in `01 - Title` or `01. Title`.
+fn strip_track_number(name: &str) -> &str {
+    let rest = name.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == name.len() {
+        return name;
+    }
+    let rest = rest.trim_start_matches(|c: char| c == '.' || c == '-' || c.is_whitespace());
+    if rest.is_empty() {
+        name
+    } else {
+        rest
+    }
+}
+
+// Longest comment header we read, larger headers are usually caused by embedded
+// pictures and are truncated.
+const MAX_COMMENT_LENGTH: usize = 256 * 1024;
+
+/// Read the identification and comment headers of an Ogg Vorbis stream, and
+/// the total duration from the granule position of the last page.
+fn read_vorbis_info(file: &mut (impl Read + Seek)) -> Result<AudioInfo, Error> {
+    let ident = read_ogg_packet(file, usize::MAX)?;
+    if ident.len() < 16 || &ident[..7] != b"\x01vorbis" {
+        return Err(invalid_data("not an Ogg Vorbis file"));
+    }
+    let sample_rate = (&ident[12..16]).read_u32::<LE>()?;
+    let comment = read_ogg_packet(file, MAX_COMMENT_LENGTH)?;
+    let tags = parse_vorbis_comments(&comment);
+    let duration = match last_granule_position(file)? {
+        Some(granule) if sample_rate > 0 => {
+            Duration::from_secs_f64(granule as f64 / sample_rate as f64)
+        }
+        _ => Duration::default(),
+    };
+    Ok(AudioInfo { tags, duration })
+}
+
+/// Read the next packet of an Ogg stream, assuming it starts at a page
+/// boundary.  Only the first `max_length` bytes of the packet are returned.
+fn read_ogg_packet(file: &mut impl Read, max_length: usize) -> Result<Vec<u8>, Error> {
+    let mut packet = Vec::new();
+    loop {
+        let mut header = [0; 27];
+        file.read_exact(&mut header)?;
+        if &header[..4] != b"OggS" {
+            return Err(invalid_data("missing Ogg page header"));
+        }
+        let mut segments = vec![0; header[26] as usize];
+        file.read_exact(&mut segments)?;
+        for &length in &segments {
+            let mut segment = vec![0; length as usize];
+            file.read_exact(&mut segment)?;
+            if packet.len() < max_length {
+                packet.extend_from_slice(&segment);
+            }
+            if length < 255 {
+                // Packet ends in this segment, ignore the rest of the page.
+                packet.truncate(max_length);
+                return Ok(packet);
+            }
+        }
+        if packet.len() >= max_length {
+            packet.truncate(max_length);
+            return Ok(packet);
+        }
+    }
+}
+
+fn parse_vorbis_comments(packet: &[u8]) -> Vec<(String, String)> {
+    let mut comments = Vec::new();
+    if packet.len() < 7 || &packet[..7] != b"\x03vorbis" {
+        return comments;
+    }
+    let mut data = &packet[7..];
+    // Skip the vendor string.
+    if read_string(&mut data).is_none() {
+        return comments;
+    }
+    let count = data.read_u32::<LE>().unwrap_or(0);
+    for _ in 0..count {
+        match read_string(&mut data) {
+            Some(comment) => {
+                if let Some((key, value)) = comment.split_once('=') {
+                    comments.push((key.to_string(), value.to_string()));
+                }
+            }
+            // Comment header has been truncated.
+            None => break,
+        }
+    }
+    comments
+}
+
+fn read_string(data: &mut &[u8]) -> Option<String> {
+    let length = data.read_u32::<LE>().ok()? as usize;
+    if length > data.len() {
+        return None;
+    }
+    let (string, rest) = data.split_at(length);
+    *data = rest;
+    Some(String::from_utf8_lossy(string).to_string())
+}
+
+fn last_granule_position(file: &mut (impl Read + Seek)) -> Result<Option<u64>, Error> {
+    const TAIL_LENGTH: u64 = 64 * 1024;
+
+    let length = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(length.saturating_sub(TAIL_LENGTH)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let last_page = tail
+        .windows(4)
+        .rposition(|window| window == b"OggS")
+        .filter(|&pos| pos + 14 <= tail.len());
+    Ok(last_page.map(|pos| {
+        let mut granule = &tail[pos + 6..pos + 14];
+        granule.read_u64::<LE>().unwrap_or(0)
+    }))
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::AudioDecodingError(Box::new(io::Error::new(
+        io::ErrorKind::InvalidData,
+        message.to_string(),
+    )))
+}
@@ -4,9 +4,13 @@ use quick_protobuf::MessageRead;
 
 use crate::{
     audio_file::{AudioFile, AudioPath},
+    cache::Cache,
     error::Error,
     item_id::{FileId, ItemId, ItemIdType},
-    protocol::metadata::{Restriction, Track},
+    protocol::metadata::{
+        Album, Artist, AudioFile as FileMetadata, Episode, Image, ImageGroup, Restriction, Show,
+        Track,
+    },
     session::SessionService,
 };
 
@@ -23,10 +27,319 @@ impl Fetch for Track {
     }
 }
 
+impl Fetch for Episode {
+    fn uri(id: ItemId) -> String {
+        format!("hm://metadata/3/episode/{}", id.to_base16())
+    }
+}
+
+impl Fetch for Album {
+    fn uri(id: ItemId) -> String {
+        format!("hm://metadata/3/album/{}", id.to_base16())
+    }
+}
+
+impl Fetch for Artist {
+    fn uri(id: ItemId) -> String {
+        format!("hm://metadata/3/artist/{}", id.to_base16())
+    }
+}
+
+impl Fetch for Show {
+    fn uri(id: ItemId) -> String {
+        format!("hm://metadata/3/show/{}", id.to_base16())
+    }
+}
+
+/// Metadata that is kept in `Cache` after it has been fetched.
+pub trait FetchCached: Fetch {
+    fn get_cached(cache: &Cache, id: ItemId) -> Option<Self>;
+    fn save_cached(&self, cache: &Cache, id: ItemId) -> Result<(), Error>;
+
+    /// Load the metadata from `cache`, or fetch them and save them there.
+    fn fetch_cached(session: &SessionService, cache: &Cache, id: ItemId) -> Result<Self, Error> {
+        if let Some(cached) = Self::get_cached(cache, id) {
+            Ok(cached)
+        } else {
+            let fetched = Self::fetch(session, id)?;
+            if let Err(err) = fetched.save_cached(cache, id) {
+                log::warn!("failed to save metadata to cache: {:?}", err);
+            }
+            Ok(fetched)
+        }
+    }
+}
+
+impl FetchCached for Track {
+    fn get_cached(cache: &Cache, id: ItemId) -> Option<Self> {
+        cache.get_track(id)
+    }
+
+    fn save_cached(&self, cache: &Cache, id: ItemId) -> Result<(), Error> {
+        cache.save_track(id, self)
+    }
+}
+
+impl FetchCached for Episode {
+    fn get_cached(cache: &Cache, id: ItemId) -> Option<Self> {
+        cache.get_episode(id)
+    }
+
+    fn save_cached(&self, cache: &Cache, id: ItemId) -> Result<(), Error> {
+        cache.save_episode(id, self)
+    }
+}
+
+impl FetchCached for Album {
+    fn get_cached(cache: &Cache, id: ItemId) -> Option<Self> {
+        cache.get_album(id)
+    }
+
+    fn save_cached(&self, cache: &Cache, id: ItemId) -> Result<(), Error> {
+        cache.save_album(id, self)
+    }
+}
+
+impl FetchCached for Artist {
+    fn get_cached(cache: &Cache, id: ItemId) -> Option<Self> {
+        cache.get_artist(id)
+    }
+
+    fn save_cached(&self, cache: &Cache, id: ItemId) -> Result<(), Error> {
+        cache.save_artist(id, self)
+    }
+}
+
+impl FetchCached for Show {
+    fn get_cached(cache: &Cache, id: ItemId) -> Option<Self> {
+        cache.get_show(id)
+    }
+
+    fn save_cached(&self, cache: &Cache, id: ItemId) -> Result<(), Error> {
+        cache.save_show(id, self)
+    }
+}
+
+/// Tracks of a single disc of an album.
+#[derive(Debug, Clone)]
+pub struct DiscTracks {
+    pub number: i32,
+    pub name: Option<String>,
+    pub track_ids: Vec<ItemId>,
+}
+
+pub trait AlbumExt {
+    fn item_id(&self) -> Option<ItemId>;
+    fn artist_ids(&self) -> Vec<ItemId>;
+    fn discs(&self) -> Vec<DiscTracks>;
+    /// IDs of all tracks of the album, in the order of the discs.
+    fn track_ids(&self) -> Vec<ItemId>;
+}
+
+impl AlbumExt for Album {
+    fn item_id(&self) -> Option<ItemId> {
+        ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Album)
+    }
+
+    fn artist_ids(&self) -> Vec<ItemId> {
+        self.artist.iter().filter_map(ArtistExt::item_id).collect()
+    }
+
+    fn discs(&self) -> Vec<DiscTracks> {
+        self.disc
+            .iter()
+            .enumerate()
+            .map(|(index, disc)| DiscTracks {
+                number: disc.number.unwrap_or(index as i32 + 1),
+                name: disc.name.clone(),
+                track_ids: disc.track.iter().filter_map(TrackExt::item_id).collect(),
+            })
+            .collect()
+    }
+
+    fn track_ids(&self) -> Vec<ItemId> {
+        self.disc
+            .iter()
+            .flat_map(|disc| disc.track.iter().filter_map(TrackExt::item_id))
+            .collect()
+    }
+}
+
+pub trait ArtistExt {
+    fn item_id(&self) -> Option<ItemId>;
+    /// IDs of the most popular tracks of the artist in `country`, given as an
+    /// ISO 3166-1 alpha-2 code.
+    fn top_track_ids(&self, country: &str) -> Vec<ItemId>;
+    /// IDs of the albums of the artist, excluding singles, compilations and
+    /// albums the artist only appears on.
+    fn album_ids(&self) -> Vec<ItemId>;
+    fn single_ids(&self) -> Vec<ItemId>;
+    fn related_artist_ids(&self) -> Vec<ItemId>;
+}
+
+impl ArtistExt for Artist {
+    fn item_id(&self) -> Option<ItemId> {
+        ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Artist)
+    }
+
+    fn top_track_ids(&self, country: &str) -> Vec<ItemId> {
+        self.top_track
+            .iter()
+            .find(|top_tracks| top_tracks.country.as_deref() == Some(country))
+            .map(|top_tracks| {
+                top_tracks
+                    .track
+                    .iter()
+                    .filter_map(TrackExt::item_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn album_ids(&self) -> Vec<ItemId> {
+        // Each group contains the versions of a single album, the first one is the
+        // canonical one.
+        self.album_group
+            .iter()
+            .filter_map(|group| group.album.first()?.item_id())
+            .collect()
+    }
+
+    fn single_ids(&self) -> Vec<ItemId> {
+        self.single_group
+            .iter()
+            .filter_map(|group| group.album.first()?.item_id())
+            .collect()
+    }
+
+    fn related_artist_ids(&self) -> Vec<ItemId> {
+        self.related.iter().filter_map(ArtistExt::item_id).collect()
+    }
+}
+
+pub trait TrackExt {
+    fn item_id(&self) -> Option<ItemId>;
+    fn album_id(&self) -> Option<ItemId>;
+    fn artist_ids(&self) -> Vec<ItemId>;
+}
+
+impl TrackExt for Track {
+    fn item_id(&self) -> Option<ItemId> {
+        ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Track)
+    }
+
+    fn album_id(&self) -> Option<ItemId> {
+        self.album.as_ref()?.item_id()
+    }
+
+    fn artist_ids(&self) -> Vec<ItemId> {
+        self.artist.iter().filter_map(ArtistExt::item_id).collect()
+    }
+}
+
+pub trait ShowExt {
+    fn item_id(&self) -> Option<ItemId>;
+    fn episode_ids(&self) -> Vec<ItemId>;
+}
+
+impl ShowExt for Show {
+    fn item_id(&self) -> Option<ItemId> {
+        ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Show)
+    }
+
+    fn episode_ids(&self) -> Vec<ItemId> {
+        self.episode
+            .iter()
+            .filter_map(EpisodeExt::item_id)
+            .collect()
+    }
+}
+
+pub trait EpisodeExt {
+    fn item_id(&self) -> Option<ItemId>;
+    fn show_id(&self) -> Option<ItemId>;
+}
+
+impl EpisodeExt for Episode {
+    fn item_id(&self) -> Option<ItemId> {
+        ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Podcast)
+    }
+
+    fn show_id(&self) -> Option<ItemId> {
+        self.show.as_ref()?.item_id()
+    }
+}
+
+/// File IDs of the cover art or portraits of an item, in all available
+/// sizes.  Use `image_url` to download them.
+pub trait ToImageIds {
+    fn image_ids(&self) -> Vec<FileId>;
+}
+
+impl ToImageIds for Album {
+    fn image_ids(&self) -> Vec<FileId> {
+        image_ids(self.cover_group.as_ref(), &self.cover)
+    }
+}
+
+impl ToImageIds for Artist {
+    fn image_ids(&self) -> Vec<FileId> {
+        image_ids(self.portrait_group.as_ref(), &self.portrait)
+    }
+}
+
+impl ToImageIds for Track {
+    fn image_ids(&self) -> Vec<FileId> {
+        self.album
+            .as_ref()
+            .map(ToImageIds::image_ids)
+            .unwrap_or_default()
+    }
+}
+
+impl ToImageIds for Show {
+    fn image_ids(&self) -> Vec<FileId> {
+        image_ids(self.covers.as_ref(), &[])
+    }
+}
+
+impl ToImageIds for Episode {
+    fn image_ids(&self) -> Vec<FileId> {
+        let ids = image_ids(self.covers.as_ref(), &[]);
+        if ids.is_empty() {
+            // Episodes without their own cover use the cover of the show.
+            self.show
+                .as_ref()
+                .map(ToImageIds::image_ids)
+                .unwrap_or_default()
+        } else {
+            ids
+        }
+    }
+}
+
+/// Prefer the images of `group`, and fall back to the loose `images`.
+fn image_ids(group: Option<&ImageGroup>, images: &[Image]) -> Vec<FileId> {
+    let images = match group {
+        Some(group) if !group.image.is_empty() => &group.image[..],
+        _ => images,
+    };
+    images
+        .iter()
+        .filter_map(|image| FileId::from_raw(image.file_id.as_ref()?))
+        .collect()
+}
+
+pub fn image_url(file_id: FileId) -> String {
+    format!("https://i.scdn.co/image/{}", file_id.to_base16())
+}
+
 pub trait ToAudioPath {
     fn is_restricted_in_region(&self, country: &str) -> bool;
     fn find_allowed_alternative(&self, country: &str) -> Option<ItemId>;
-    fn to_audio_path(&self, preferred_bitrate: usize) -> Option<AudioPath>;
+    /// Pick the file to play, in the format closest to `preferred_bitrate`, or
+    /// in a better one in case it is fully cached.
+    fn to_audio_path(&self, preferred_bitrate: usize, cache: &Cache) -> Option<AudioPath>;
 }
 
 impl ToAudioPath for Track {
@@ -44,20 +357,43 @@ impl ToAudioPath for Track {
         ItemId::from_raw(alt_track.gid.as_ref()?, ItemIdType::Track)
     }
 
-    fn to_audio_path(&self, preferred_bitrate: usize) -> Option<AudioPath> {
-        let file = AudioFile::compatible_audio_formats(preferred_bitrate)
-            .iter()
-            .find_map(|&preferred_format| {
-                self.file
-                    .iter()
-                    .find(|file| file.format == Some(preferred_format))
-            })?;
+    fn to_audio_path(&self, preferred_bitrate: usize, cache: &Cache) -> Option<AudioPath> {
+        let file = select_audio_file(&self.file, preferred_bitrate, cache)?;
         let file_format = file.format?;
         let item_id = ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Track)?;
         let file_id = FileId::from_raw(file.file_id.as_ref()?)?;
         let duration = Duration::from_millis(self.duration? as u64);
         Some(AudioPath {
             item_id,
+            played_item_id: item_id,
+            file_id,
+            file_format,
+            duration,
+        })
+    }
+}
+
+impl ToAudioPath for Episode {
+    fn is_restricted_in_region(&self, country: &str) -> bool {
+        self.restriction
+            .iter()
+            .any(|rest| is_restricted_in_region(rest, country))
+    }
+
+    fn find_allowed_alternative(&self, _country: &str) -> Option<ItemId> {
+        // Episodes do not have any alternatives.
+        None
+    }
+
+    fn to_audio_path(&self, preferred_bitrate: usize, cache: &Cache) -> Option<AudioPath> {
+        let file = select_audio_file(&self.file, preferred_bitrate, cache)?;
+        let file_format = file.format?;
+        let item_id = ItemId::from_raw(self.gid.as_ref()?, ItemIdType::Podcast)?;
+        let file_id = FileId::from_raw(file.file_id.as_ref()?)?;
+        let duration = Duration::from_millis(self.duration? as u64);
+        Some(AudioPath {
+            item_id,
+            played_item_id: item_id,
             file_id,
             file_format,
             duration,
@@ -65,6 +401,29 @@ impl ToAudioPath for Track {
     }
 }
 
+fn select_audio_file<'a>(
+    files: &'a [FileMetadata],
+    preferred_bitrate: usize,
+    cache: &Cache,
+) -> Option<&'a FileMetadata> {
+    let find_format = |format| files.iter().find(|file| file.format == Some(format));
+    let preferred = AudioFile::compatible_audio_formats(preferred_bitrate)
+        .iter()
+        .find_map(|&format| find_format(format))?;
+    // Files of better quality than the preferred one do not cost any bandwidth if
+    // they are already cached, go with them.
+    let highest_bitrate = AudioFile::BITRATES[AudioFile::BITRATES.len() - 1];
+    let cached_better = AudioFile::compatible_audio_formats(highest_bitrate)
+        .iter()
+        .take_while(|&&format| Some(format) != preferred.format)
+        .filter_map(|&format| find_format(format))
+        .find(|file| {
+            let file_id = file.file_id.as_ref().and_then(|id| FileId::from_raw(id));
+            file_id.map_or(false, |id| cache.has_audio_file(id))
+        });
+    Some(cached_better.unwrap_or(preferred))
+}
+
 fn is_restricted_in_region(restriction: &Restriction, country: &str) -> bool {
     if let Some(allowed) = &restriction.countries_allowed {
         return !is_country_in_list(allowed.as_bytes(), country.as_bytes());
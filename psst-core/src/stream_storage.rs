@@ -1,4 +1,5 @@
 use std::{
+    cell::Cell,
     fs::File,
     io,
     io::{Read, Seek, SeekFrom, Write},
@@ -14,6 +15,7 @@ use tempfile::NamedTempFile;
 pub enum StreamRequest {
     Preload { offset: u64, length: u64 },
     Blocked { offset: u64 },
+    Unblocked { offset: u64 },
 }
 
 pub struct StreamStorage {
@@ -52,6 +54,7 @@ impl StreamStorage {
                 total_size,
                 downloaded: Mutex::new(RangeSet::new()),
                 requested: Mutex::new(RangeSet::new()),
+                failed: Mutex::new(RangeSet::new()),
                 condvar: Condvar::new(),
             }),
         })
@@ -80,6 +83,7 @@ impl StreamStorage {
                 total_size,
                 downloaded: Mutex::new(downloaded_set),
                 requested: Mutex::new(requested_set),
+                failed: Mutex::new(RangeSet::new()),
                 condvar: Condvar::new(),
             }),
         })
@@ -100,6 +104,21 @@ impl StreamStorage {
         })
     }
 
+    pub fn total_size(&self) -> u64 {
+        self.data_map.total_size
+    }
+
+    /// Ranges of the file that are downloaded, in ascending order.
+    pub fn downloaded_ranges(&self) -> Vec<Range<u64>> {
+        self.data_map
+            .downloaded
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
     pub fn receiver(&self) -> &Receiver<StreamRequest> {
         &self.req_receiver
     }
@@ -135,8 +154,8 @@ impl StreamWriter {
         self.data_map.is_complete()
     }
 
-    pub fn mark_as_not_requested(&self, offset: u64, length: u64) {
-        self.data_map.mark_as_not_requested(offset, length);
+    pub fn mark_as_failed(&self, offset: u64, length: u64) {
+        self.data_map.mark_as_failed(offset, length);
     }
 }
 
@@ -187,13 +206,21 @@ impl Read for StreamReader {
         }
 
         // Block and wait until at least a part of the range is available, and read it.
-        let ready_to_read_len = self.data_map.wait_for(position, |offset| {
+        let was_blocked = Cell::new(false);
+        let ready_to_read = self.data_map.wait_for(position, |offset| {
             // Notify the servicing thread we are blocked, so it can possibly prioritize the
             // blocked offset.
+            was_blocked.set(true);
             self.req_sender
                 .send(StreamRequest::Blocked { offset })
                 .expect("Data request channel was closed");
         });
+        if was_blocked.get() {
+            self.req_sender
+                .send(StreamRequest::Unblocked { offset: position })
+                .expect("Data request channel was closed");
+        }
+        let ready_to_read_len = ready_to_read?;
         assert!(ready_to_read_len > 0);
         self.reader
             .read(&mut buf[..ready_to_read_len.min(needed_len) as usize])
@@ -223,6 +250,9 @@ struct StreamDataMap {
     // Contains ranges of data sure to be present in the backing storage.  Always a subset of the
     // requested ranges.
     downloaded: Mutex<RangeSet<u64>>,
+    // Contains ranges that have failed to download, until they are requested again.  Locked only
+    // while holding the `downloaded` lock, or without holding any other lock.
+    failed: Mutex<RangeSet<u64>>,
     condvar: Condvar,
 }
 
@@ -252,14 +282,22 @@ impl StreamDataMap {
             .lock()
             .unwrap()
             .insert(offset..offset + length);
+        self.failed.lock().unwrap().remove(offset..offset + length);
     }
 
-    /// Remove range previously marked as requested.
-    fn mark_as_not_requested(&self, offset: u64, length: u64) {
+    /// Remove range previously marked as requested, and wake up the tasks
+    /// waiting for it in `self.wait_for`, so they fail instead of waiting
+    /// forever.
+    fn mark_as_failed(&self, offset: u64, length: u64) {
         self.requested
             .lock()
             .unwrap()
             .remove(offset..offset + length);
+        // Hold the `downloaded` lock, so the waiting tasks cannot miss the
+        // notification.
+        let _downloaded = self.downloaded.lock().unwrap();
+        self.failed.lock().unwrap().insert(offset..offset + length);
+        self.condvar.notify_all();
     }
 
     /// Mark the range as downloaded and notify the `self.condvar`, so tasks
@@ -273,10 +311,12 @@ impl StreamDataMap {
     }
 
     /// Block, waiting until at least some data at given offset is downloaded.
-    /// Returns length that is available.  See `self.mark_as_downloaded`.
-    fn wait_for(&self, offset: u64, blocking_callback: impl Fn(u64)) -> u64 {
+    /// Returns length that is available, or an error if the download of the
+    /// data has failed.  See `self.mark_as_downloaded`.
+    fn wait_for(&self, offset: u64, blocking_callback: impl Fn(u64)) -> io::Result<u64> {
         let mut called_callback = false;
         let mut available_len = 0; // Resulting length.
+        let mut has_failed = false;
 
         let downloaded = self.downloaded.lock().unwrap();
 
@@ -289,6 +329,10 @@ impl StreamDataMap {
                     available_len = over_len - offset_from_overlapping;
                     // There is `available_len` bytes of data downloaded, stop waiting.
                     false
+                } else if self.failed.lock().unwrap().contains(&offset) {
+                    // The data is not going to arrive, stop waiting.
+                    has_failed = true;
+                    false
                 } else {
                     // Call the blocking callback, but only the first time we are waiting.
                     if !called_callback {
@@ -300,7 +344,14 @@ impl StreamDataMap {
                 }
             })
             .unwrap();
-        available_len
+        if has_failed {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to download data at offset {}", offset),
+            ))
+        } else {
+            Ok(available_len)
+        }
     }
 
     // Returns true if data is completely downloaded.
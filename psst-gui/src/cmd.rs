@@ -1,8 +1,9 @@
-use std::time::Duration;
+use std::{ops::Range, time::Duration};
 
 use druid::{Selector, WidgetId};
+use psst_core::{audio_queue::QueueTier, item_id::ItemId};
 
-use crate::data::{Nav, PlaybackPayload, QueueBehavior, TrackId};
+use crate::data::{Nav, PlaybackPayload, QueueBehavior, QueuedItem};
 
 // Widget IDs
 
@@ -25,13 +26,24 @@ pub const NAVIGATE_BACK: Selector<usize> = Selector::new("app.navigate-back");
 
 // Playback state
 
-pub const PLAYBACK_LOADING: Selector<TrackId> = Selector::new("app.playback-loading");
-pub const PLAYBACK_PLAYING: Selector<(TrackId, Duration)> = Selector::new("app.playback-playing");
+pub const PLAYBACK_LOADING: Selector<(ItemId, QueueTier)> = Selector::new("app.playback-loading");
+/// Requested item, item that actually plays, queue tier and progress.
+pub const PLAYBACK_PLAYING: Selector<(ItemId, ItemId, QueueTier, Duration)> =
+    Selector::new("app.playback-playing");
 pub const PLAYBACK_PROGRESS: Selector<Duration> = Selector::new("app.playback-progress");
 pub const PLAYBACK_PAUSING: Selector = Selector::new("app.playback-pausing");
 pub const PLAYBACK_RESUMING: Selector = Selector::new("app.playback-resuming");
 pub const PLAYBACK_BLOCKED: Selector = Selector::new("app.playback-blocked");
+pub const PLAYBACK_UNBLOCKED: Selector = Selector::new("app.playback-unblocked");
 pub const PLAYBACK_STOPPED: Selector = Selector::new("app.playback-stopped");
+/// Item that failed to play and the error message.
+pub const PLAYBACK_FAILED: Selector<(ItemId, String)> = Selector::new("app.playback-failed");
+/// Item and its parts that are downloaded, as time ranges.
+pub const PLAYBACK_BUFFERED: Selector<(ItemId, Vec<Range<Duration>>)> =
+    Selector::new("app.playback-buffered");
+pub const PLAYBACK_QUEUE_CHANGED: Selector<(Vec<usize>, usize)> =
+    Selector::new("app.playback-queue-changed");
+pub const PLAYBACK_RESTORE: Selector = Selector::new("app.playback-restore");
 
 // Playback control
 
@@ -44,3 +56,9 @@ pub const PLAY_NEXT: Selector = Selector::new("app.play-next");
 pub const PLAY_STOP: Selector = Selector::new("app.play-stop");
 pub const PLAY_QUEUE_BEHAVIOR: Selector<QueueBehavior> = Selector::new("app.play-queue-behavior");
 pub const PLAY_SEEK: Selector<f64> = Selector::new("app.play-seek");
+pub const PLAY_CLEAR_RESUME_POSITIONS: Selector = Selector::new("app.play-clear-resume-positions");
+
+// Queue editing
+
+pub const QUEUE_INSERT_NEXT: Selector<QueuedItem> = Selector::new("app.queue-insert-next");
+pub const QUEUE_APPEND: Selector<QueuedItem> = Selector::new("app.queue-append");
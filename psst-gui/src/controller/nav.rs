@@ -3,7 +3,7 @@ use druid::widget::{prelude::*, Controller};
 use crate::{
     cmd,
     data::{AppState, Nav, SpotifyUrl},
-    ui::{album, artist, library, playlist, recommend, search},
+    ui::{album, artist, library, playlist, recommend, search, show},
 };
 
 pub struct NavController;
@@ -22,6 +22,11 @@ impl NavController {
                     ctx.submit_command(library::LOAD_ALBUMS);
                 }
             }
+            Nav::SavedShows => {
+                if !data.library.saved_shows.is_resolved() {
+                    ctx.submit_command(library::LOAD_SHOWS);
+                }
+            }
             Nav::SearchResults(query) => {
                 if let Some(link) = SpotifyUrl::parse(query) {
                     ctx.submit_command(search::OPEN_LINK.with(link));
@@ -44,6 +49,11 @@ impl NavController {
                     ctx.submit_command(playlist::LOAD_DETAIL.with(link.to_owned()));
                 }
             }
+            Nav::ShowDetail(link) => {
+                if !data.show_detail.show.contains(link) {
+                    ctx.submit_command(show::LOAD_DETAIL.with(link.to_owned()));
+                }
+            }
             Nav::Recommendations(request) => {
                 if !data.recommend.results.contains(request) {
                     ctx.submit_command(recommend::LOAD_RESULTS.with(request.clone()));
@@ -11,8 +11,10 @@ use druid::{
 };
 use psst_core::{
     audio_normalize::NormalizationLevel,
-    audio_output::AudioOutput,
+    audio_output::{AudioOutput, AudioOutputRemote},
     audio_player::{PlaybackConfig, PlaybackItem, Player, PlayerCommand, PlayerEvent},
+    audio_queue::QueueTier,
+    audio_sink::AudioSink,
     cache::Cache,
     cdn::Cdn,
     session::SessionService,
@@ -24,25 +26,41 @@ use souvlaki::{
 use crate::{
     cmd,
     data::{
-        AppState, Config, Playback, PlaybackOrigin, PlaybackState, QueueBehavior, QueuedTrack,
-        TrackId,
+        AppState, Config, Playable, Playback, PlaybackOrigin, PlaybackState, QueueBehavior,
+        QueuedItem, SavedQueue, TrackId,
     },
+    ui::playback,
 };
 
+// How often the progress of the playing track gets saved, see `SavedQueue`.
+const SAVE_PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct PlaybackController {
     sender: Option<Sender<PlayerEvent>>,
+    output_remote: Option<AudioOutputRemote>,
     thread: Option<JoinHandle<()>>,
     output_thread: Option<JoinHandle<()>>,
     media_controls: Option<MediaControls>,
+    // Order of the context queue and the position in it, as reported by the player.
+    positions: Vec<usize>,
+    position: usize,
+    // Tier of the playing track.
+    tier: QueueTier,
+    saved_progress: Duration,
 }
 
 impl PlaybackController {
     pub fn new() -> Self {
         Self {
             sender: None,
+            output_remote: None,
             thread: None,
             output_thread: None,
             media_controls: None,
+            positions: Vec::new(),
+            position: 0,
+            tier: QueueTier::Context,
+            saved_progress: Duration::default(),
         }
     }
 
@@ -50,19 +68,29 @@ impl PlaybackController {
         &mut self,
         session: SessionService,
         config: PlaybackConfig,
+        audio_device: Option<String>,
         event_sink: ExtEventSink,
         widget_id: WidgetId,
         #[allow(unused_variables)] window: &WindowHandle,
     ) {
-        let output = AudioOutput::open().unwrap();
+        let cache_dir = Config::cache_dir().unwrap();
+        let cache = match Cache::new(cache_dir) {
+            Ok(cache) => cache,
+            Err(err) => {
+                log::error!("failed to open cache, playback is not available: {}", err);
+                return;
+            }
+        };
+
+        let output = AudioOutput::open_with_device(audio_device).unwrap();
         let remote = output.remote();
+        let output_remote = output.remote();
 
-        let cache_dir = Config::cache_dir().unwrap();
         let proxy_url = Config::proxy();
         let player = Player::new(
             session.clone(),
             Cdn::new(session, proxy_url.as_deref()).unwrap(),
-            Cache::new(cache_dir).unwrap(),
+            cache,
             config,
             remote,
         );
@@ -106,6 +134,7 @@ impl PlaybackController {
             .unwrap();
 
         self.sender.replace(sender);
+        self.output_remote.replace(output_remote);
         self.thread.replace(thread);
         self.output_thread.replace(output_thread);
         self.media_controls.replace(media_controls);
@@ -115,17 +144,25 @@ impl PlaybackController {
         for event in player.event_receiver() {
             // Forward events that affect the UI state to the UI thread.
             match &event {
-                PlayerEvent::Loading { item } => {
-                    let item: TrackId = item.item_id.into();
+                PlayerEvent::Loading { item, tier } => {
                     event_sink
-                        .submit_command(cmd::PLAYBACK_LOADING, item, widget_id)
+                        .submit_command(cmd::PLAYBACK_LOADING, (item.item_id, *tier), widget_id)
                         .unwrap();
                 }
-                PlayerEvent::Playing { path, duration } => {
-                    let item: TrackId = path.item_id.into();
+                PlayerEvent::Playing {
+                    path,
+                    duration,
+                    tier,
+                } => {
+                    let item = path.item_id;
+                    let played = path.played_item_id;
                     let progress = duration.to_owned();
                     event_sink
-                        .submit_command(cmd::PLAYBACK_PLAYING, (item, progress), widget_id)
+                        .submit_command(
+                            cmd::PLAYBACK_PLAYING,
+                            (item, played, *tier, progress),
+                            widget_id,
+                        )
                         .unwrap();
                 }
                 PlayerEvent::Pausing { .. } => {
@@ -144,16 +181,51 @@ impl PlaybackController {
                         .submit_command(cmd::PLAYBACK_PROGRESS, progress, widget_id)
                         .unwrap();
                 }
-                PlayerEvent::Blocked => {
+                PlayerEvent::Blocked { .. } => {
                     event_sink
                         .submit_command(cmd::PLAYBACK_BLOCKED, (), widget_id)
                         .unwrap();
                 }
+                PlayerEvent::Unblocked { .. } => {
+                    event_sink
+                        .submit_command(cmd::PLAYBACK_UNBLOCKED, (), widget_id)
+                        .unwrap();
+                }
                 PlayerEvent::Stopped => {
                     event_sink
                         .submit_command(cmd::PLAYBACK_STOPPED, (), widget_id)
                         .unwrap();
                 }
+                PlayerEvent::Failed { path, error } => {
+                    event_sink
+                        .submit_command(
+                            cmd::PLAYBACK_FAILED,
+                            (path.item_id, error.to_string()),
+                            widget_id,
+                        )
+                        .unwrap();
+                }
+                PlayerEvent::Buffered { path, ranges, .. } => {
+                    event_sink
+                        .submit_command(
+                            cmd::PLAYBACK_BUFFERED,
+                            (path.item_id, ranges.to_owned()),
+                            widget_id,
+                        )
+                        .unwrap();
+                }
+                PlayerEvent::QueueChanged {
+                    positions,
+                    position,
+                } => {
+                    event_sink
+                        .submit_command(
+                            cmd::PLAYBACK_QUEUE_CHANGED,
+                            (positions.to_owned(), *position),
+                            widget_id,
+                        )
+                        .unwrap();
+                }
                 _ => {}
             }
 
@@ -186,7 +258,11 @@ impl PlaybackController {
                 .set_playback(match playback.state {
                     PlaybackState::Loading | PlaybackState::Stopped => MediaPlayback::Stopped,
                     PlaybackState::Playing => MediaPlayback::Playing { progress },
-                    PlaybackState::Paused => MediaPlayback::Paused { progress },
+                    // Media controls have no notion of buffering, show the playback as
+                    // paused, because the progress is not advancing.
+                    PlaybackState::Buffering | PlaybackState::Paused => {
+                        MediaPlayback::Paused { progress }
+                    }
                 })
                 .unwrap();
         }
@@ -194,10 +270,19 @@ impl PlaybackController {
 
     fn update_media_control_metadata(&mut self, playback: &Playback) {
         if let Some(media_controls) = self.media_controls.as_mut() {
-            let title = playback.now_playing.as_ref().map(|p| p.item.name.clone());
-            let album = playback.now_playing.as_ref().map(|p| p.item.album_name());
-            let artist = playback.now_playing.as_ref().map(|p| p.item.artist_name());
-            let duration = playback.now_playing.as_ref().map(|p| p.item.duration);
+            let title = playback.now_playing.as_ref().map(|p| p.shown_item().name());
+            let album = playback.now_playing.as_ref().map(|p| match p.shown_item() {
+                Playable::Track(track) => track.album_name(),
+                Playable::Episode(episode) => episode.show_name(),
+            });
+            let artist = playback
+                .now_playing
+                .as_ref()
+                .map(|p| p.shown_item().subtitle());
+            let duration = playback
+                .now_playing
+                .as_ref()
+                .map(|p| p.shown_item().duration());
             let cover_url = playback
                 .now_playing
                 .as_ref()
@@ -215,26 +300,100 @@ impl PlaybackController {
     }
 
     fn send(&mut self, event: PlayerEvent) {
-        self.sender.as_mut().unwrap().send(event).unwrap();
+        // Without the player, i.e. when the cache could not be opened, the events
+        // go nowhere.
+        if let Some(sender) = &self.sender {
+            sender.send(event).unwrap();
+        }
     }
 
-    fn play(&mut self, items: &Vector<QueuedTrack>, position: usize) {
-        let items = items
-            .iter()
-            .map(|queued| PlaybackItem {
-                item_id: *queued.track.id,
-                norm_level: match queued.origin {
-                    PlaybackOrigin::Album(_) => NormalizationLevel::Album,
-                    _ => NormalizationLevel::Track,
-                },
-            })
-            .collect();
+    fn playback_item(queued: &QueuedItem) -> PlaybackItem {
+        PlaybackItem {
+            item_id: queued.item.id(),
+            norm_level: match queued.origin {
+                PlaybackOrigin::Album(_) => NormalizationLevel::Album,
+                _ => NormalizationLevel::Track,
+            },
+        }
+    }
+
+    fn play(&mut self, items: &Vector<QueuedItem>, position: usize) {
+        let items = items.iter().map(Self::playback_item).collect();
         self.send(PlayerEvent::Command(PlayerCommand::LoadQueue {
             items,
             position,
         }));
     }
 
+    /// Load the queue saved in the last session, and let the player restore it
+    /// paused at the saved progress.
+    fn restore_queue(&mut self, data: &mut AppState) {
+        let saved = match SavedQueue::load() {
+            Some(saved) if !saved.is_empty() => saved,
+            _ => return,
+        };
+        log::info!("restoring saved queue");
+        data.playback.queue = saved.queue.clone();
+        data.playback.manual_queue = saved.manual_queue.clone();
+        self.positions = saved.positions.clone();
+        self.position = saved.position;
+        self.saved_progress = saved.progress;
+        self.send(PlayerEvent::Command(PlayerCommand::RestoreQueue {
+            items: saved.queue.iter().map(Self::playback_item).collect(),
+            positions: saved.positions,
+            position: saved.position,
+            manual: saved.manual_queue.iter().map(Self::playback_item).collect(),
+            tier: if saved.is_manual_current {
+                QueueTier::Manual
+            } else {
+                QueueTier::Context
+            },
+            progress: saved.progress,
+        }));
+    }
+
+    fn save_queue(&mut self, playback: &Playback) {
+        let saved = match (&playback.now_playing, playback.state) {
+            (_, PlaybackState::Stopped) => SavedQueue::default(),
+            // Nothing to save before the first track starts.
+            (None, _) => return,
+            (Some(now_playing), _) => {
+                let mut manual_queue = playback.manual_queue.clone();
+                // Manual items are taken out of the queue once they start playing, put
+                // the playing one back, so it gets restored as well.
+                let is_manual_current = self.tier == QueueTier::Manual;
+                if is_manual_current {
+                    manual_queue.push_front(QueuedItem {
+                        item: now_playing.item.clone(),
+                        origin: now_playing.origin.clone(),
+                    });
+                }
+                SavedQueue {
+                    queue: playback.queue.clone(),
+                    positions: self.positions.clone(),
+                    position: self.position,
+                    manual_queue,
+                    is_manual_current,
+                    progress: now_playing.progress,
+                }
+            }
+        };
+        self.saved_progress = saved.progress;
+        saved.save();
+    }
+
+    fn insert_next(&mut self, queued: &QueuedItem) {
+        self.send(PlayerEvent::Command(PlayerCommand::InsertNext {
+            item: Self::playback_item(queued),
+        }));
+    }
+
+    fn append(&mut self, queued: &QueuedItem) {
+        self.send(PlayerEvent::Command(PlayerCommand::Append {
+            item: Self::playback_item(queued),
+        }));
+    }
+
     fn pause(&mut self) {
         self.send(PlayerEvent::Command(PlayerCommand::Pause));
     }
@@ -263,10 +422,24 @@ impl PlaybackController {
         self.send(PlayerEvent::Command(PlayerCommand::Seek { position }));
     }
 
+    fn clear_resume_positions(&mut self) {
+        self.send(PlayerEvent::Command(PlayerCommand::ClearResumePositions));
+    }
+
     fn set_volume(&mut self, volume: f64) {
         self.send(PlayerEvent::Command(PlayerCommand::SetVolume { volume }));
     }
 
+    fn configure(&mut self, config: PlaybackConfig) {
+        self.send(PlayerEvent::Command(PlayerCommand::Configure { config }));
+    }
+
+    fn switch_audio_device(&mut self, audio_device: Option<String>) {
+        if let Some(output_remote) = &self.output_remote {
+            output_remote.switch_device(audio_device);
+        }
+    }
+
     fn set_queue_behavior(&mut self, behavior: QueueBehavior) {
         self.send(PlayerEvent::Command(PlayerCommand::SetQueueBehavior {
             behavior: match behavior {
@@ -296,10 +469,10 @@ where
                 ctx.request_focus();
             }
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_LOADING) => {
-                let item = cmd.get_unchecked(cmd::PLAYBACK_LOADING);
+                let (item, tier) = cmd.get_unchecked(cmd::PLAYBACK_LOADING);
 
-                if let Some(queued) = data.queued_track(item) {
-                    data.loading_playback(queued.track, queued.origin);
+                if let Some(queued) = data.take_queued_item(*item, *tier) {
+                    data.loading_playback(queued.item, queued.origin);
                     self.update_media_control_playback(&data.playback);
                     self.update_media_control_metadata(&data.playback);
                 } else {
@@ -308,13 +481,31 @@ where
                 ctx.set_handled();
             }
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_PLAYING) => {
-                let (item, progress) = cmd.get_unchecked(cmd::PLAYBACK_PLAYING);
+                let (item, played, tier, progress) = cmd.get_unchecked(cmd::PLAYBACK_PLAYING);
                 log::info!("playing");
 
-                if let Some(queued) = data.queued_track(item) {
-                    data.start_playback(queued.track, queued.origin, progress.to_owned());
+                let loaded = data.playback.now_playing.as_ref().filter(|now_playing| {
+                    data.playback.state == PlaybackState::Loading && now_playing.item.id() == *item
+                });
+                let queued = match loaded {
+                    // Item has been taken out of the queue while loading.
+                    Some(now_playing) => Some(QueuedItem {
+                        item: now_playing.item.clone(),
+                        origin: now_playing.origin.clone(),
+                    }),
+                    None => data.take_queued_item(*item, *tier),
+                };
+                if let Some(queued) = queued {
+                    data.start_playback(queued.item, queued.origin, progress.to_owned());
+                    if played != item {
+                        // Track is not available in our region, and an alternative
+                        // version plays instead.  Load its information.
+                        ctx.submit_command(playback::LOAD_SUBSTITUTE.with(TrackId::from(*played)));
+                    }
                     self.update_media_control_playback(&data.playback);
                     self.update_media_control_metadata(&data.playback);
+                    self.tier = *tier;
+                    self.save_queue(&data.playback);
                 } else {
                     log::warn!("played item not found in playback queue");
                 }
@@ -323,11 +514,20 @@ where
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_PROGRESS) => {
                 let progress = cmd.get_unchecked(cmd::PLAYBACK_PROGRESS);
                 data.progress_playback(progress.to_owned());
+                let since_saved = if *progress > self.saved_progress {
+                    *progress - self.saved_progress
+                } else {
+                    self.saved_progress - *progress
+                };
+                if since_saved >= SAVE_PROGRESS_INTERVAL {
+                    self.save_queue(&data.playback);
+                }
                 ctx.set_handled();
             }
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_PAUSING) => {
                 data.pause_playback();
                 self.update_media_control_playback(&data.playback);
+                self.save_queue(&data.playback);
                 ctx.set_handled();
             }
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_RESUMING) => {
@@ -337,27 +537,68 @@ where
             }
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_BLOCKED) => {
                 data.block_playback();
+                self.update_media_control_playback(&data.playback);
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(cmd::PLAYBACK_UNBLOCKED) => {
+                data.unblock_playback();
+                self.update_media_control_playback(&data.playback);
                 ctx.set_handled();
             }
             Event::Command(cmd) if cmd.is(cmd::PLAYBACK_STOPPED) => {
                 data.stop_playback();
                 self.update_media_control_playback(&data.playback);
+                self.save_queue(&data.playback);
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(cmd::PLAYBACK_FAILED) => {
+                let (item_id, error) = cmd.get_unchecked(cmd::PLAYBACK_FAILED);
+                data.fail_playback(*item_id, error);
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(cmd::PLAYBACK_BUFFERED) => {
+                let (item_id, ranges) = cmd.get_unchecked(cmd::PLAYBACK_BUFFERED);
+                data.buffer_playback(*item_id, ranges);
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(cmd::PLAYBACK_QUEUE_CHANGED) => {
+                let (positions, position) = cmd.get_unchecked(cmd::PLAYBACK_QUEUE_CHANGED);
+                self.positions = positions.to_owned();
+                self.position = *position;
+                self.save_queue(&data.playback);
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(cmd::PLAYBACK_RESTORE) => {
+                self.restore_queue(data);
                 ctx.set_handled();
             }
             //
             Event::Command(cmd) if cmd.is(cmd::PLAY_TRACKS) => {
                 let payload = cmd.get_unchecked(cmd::PLAY_TRACKS);
                 data.playback.queue = payload
-                    .tracks
+                    .items
                     .iter()
-                    .map(|track| QueuedTrack {
+                    .map(|item| QueuedItem {
                         origin: payload.origin.to_owned(),
-                        track: track.to_owned(),
+                        item: item.to_owned(),
                     })
                     .collect();
+                data.playback.failure.take();
                 self.play(&data.playback.queue, payload.position);
                 ctx.set_handled();
             }
+            Event::Command(cmd) if cmd.is(cmd::QUEUE_INSERT_NEXT) => {
+                let queued = cmd.get_unchecked(cmd::QUEUE_INSERT_NEXT);
+                data.playback.manual_queue.push_front(queued.to_owned());
+                self.insert_next(queued);
+                ctx.set_handled();
+            }
+            Event::Command(cmd) if cmd.is(cmd::QUEUE_APPEND) => {
+                let queued = cmd.get_unchecked(cmd::QUEUE_APPEND);
+                data.playback.manual_queue.push_back(queued.to_owned());
+                self.append(queued);
+                ctx.set_handled();
+            }
             Event::Command(cmd) if cmd.is(cmd::PLAY_PAUSE) => {
                 self.pause();
                 ctx.set_handled();
@@ -387,12 +628,17 @@ where
             Event::Command(cmd) if cmd.is(cmd::PLAY_SEEK) => {
                 if let Some(now_playing) = &data.playback.now_playing {
                     let fraction = cmd.get_unchecked(cmd::PLAY_SEEK);
-                    let position =
-                        Duration::from_secs_f64(now_playing.item.duration.as_secs_f64() * fraction);
+                    let position = Duration::from_secs_f64(
+                        now_playing.shown_item().duration().as_secs_f64() * fraction,
+                    );
                     self.seek(position);
                 }
                 ctx.set_handled();
             }
+            Event::Command(cmd) if cmd.is(cmd::PLAY_CLEAR_RESUME_POSITIONS) => {
+                self.clear_resume_positions();
+                ctx.set_handled();
+            }
             //
             Event::KeyDown(key) if key.code == Code::Space => {
                 self.pause_or_resume();
@@ -414,6 +660,10 @@ where
                 data.playback.volume = (data.playback.volume - 0.1).max(0.0);
                 ctx.set_handled();
             }
+            Event::WindowDisconnected => {
+                self.save_queue(&data.playback);
+                child.event(ctx, event, data, env);
+            }
             //
             _ => child.event(ctx, event, data, env),
         }
@@ -432,11 +682,13 @@ where
                 self.open_audio_output_and_start_threads(
                     data.session.clone(),
                     data.config.playback(),
+                    data.config.audio_device.clone(),
                     ctx.get_external_handle(),
                     ctx.widget_id(),
                     ctx.window(),
                 );
                 self.set_volume(data.playback.volume);
+                ctx.submit_command(cmd::PLAYBACK_RESTORE.to(ctx.widget_id()));
 
                 // Request focus so we can receive keyboard events.
                 ctx.submit_command(cmd::SET_FOCUS.to(ctx.widget_id()));
@@ -462,6 +714,18 @@ where
         if !old_data.playback.volume.same(&data.playback.volume) {
             self.set_volume(data.playback.volume);
         }
+        if !old_data.config.same(&data.config) {
+            self.configure(data.config.playback());
+        }
+        if old_data.config.audio_device != data.config.audio_device {
+            self.switch_audio_device(data.config.audio_device.clone());
+        }
+        if let (Some(old), Some(new)) = (&old_data.playback.now_playing, &data.playback.now_playing)
+        {
+            if !old.substitute.same(&new.substitute) {
+                self.update_media_control_metadata(&data.playback);
+            }
+        }
         child.update(ctx, old_data, data, env);
     }
 }
@@ -101,7 +101,7 @@ impl Default for AlbumType {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Data, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Data, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DatePrecision {
     Year,
@@ -1,8 +1,10 @@
-use std::{env, env::VarError, fs::File, path::PathBuf};
+use std::{env, env::VarError, fs::File, path::PathBuf, time::Duration};
 
-use druid::{Data, Lens};
+use druid::{im::Vector, Data, Lens};
 use platform_dirs::AppDirs;
 use psst_core::{
+    audio_equalizer::{EqualizerConfig, EqualizerPreset},
+    audio_output::AudioOutput,
     audio_player::PlaybackConfig,
     cache::mkdir_if_not_exists,
     connection::Credentials,
@@ -16,18 +18,44 @@ use super::{Nav, Promise, QueueBehavior};
 pub struct Preferences {
     pub active: PreferencesTab,
     pub cache_size: Promise<u64, (), ()>,
+    pub audio_devices: Promise<Vector<AudioDevice>, (), ()>,
     pub auth: Authentication,
 }
 
 impl Preferences {
     pub fn reset(&mut self) {
         self.cache_size.clear();
+        self.audio_devices.clear();
         self.auth.result.clear();
     }
 
     pub fn measure_cache_usage() -> Option<u64> {
         Config::cache_dir().and_then(|path| fs_extra::dir::get_size(&path).ok())
     }
+
+    pub fn list_audio_devices() -> Option<Vector<AudioDevice>> {
+        match AudioOutput::devices() {
+            Ok(devices) => Some(
+                devices
+                    .into_iter()
+                    .map(|device| AudioDevice {
+                        id: device.id,
+                        name: format!("{} ({})", device.name, device.backend),
+                    })
+                    .collect(),
+            ),
+            Err(err) => {
+                log::error!("failed to list audio devices: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Data, Lens)]
+pub struct AudioDevice {
+    pub id: String,
+    pub name: String,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Data)]
@@ -75,6 +103,13 @@ pub struct Config {
     pub volume: f64,
     pub last_route: Option<Nav>,
     pub queue_behavior: QueueBehavior,
+    /// Crossfade duration, in seconds.
+    pub crossfade: f64,
+    /// ID of the selected output device, `None` for the default device.
+    pub audio_device: Option<String>,
+    /// Folder with the local audio files, empty if not configured.
+    pub local_music_dir: String,
+    pub equalizer: EqualizerSettings,
 }
 
 impl Default for Config {
@@ -86,6 +121,10 @@ impl Default for Config {
             volume: 1.0,
             last_route: Default::default(),
             queue_behavior: Default::default(),
+            crossfade: 0.0,
+            audio_device: Default::default(),
+            local_music_dir: Default::default(),
+            equalizer: Default::default(),
         }
     }
 }
@@ -146,10 +185,27 @@ impl Config {
     pub fn playback(&self) -> PlaybackConfig {
         PlaybackConfig {
             bitrate: self.audio_quality.as_bitrate(),
+            adaptive_bitrate: self.audio_quality == AudioQuality::Automatic,
+            crossfade: Duration::from_secs_f64(
+                self.crossfade
+                    .round()
+                    .clamp(0.0, PlaybackConfig::MAX_CROSSFADE.as_secs_f64()),
+            ),
+            local_music_dir: self.local_music_path(),
+            equalizer: self.equalizer.to_config(),
             ..PlaybackConfig::default()
         }
     }
 
+    pub fn local_music_path(&self) -> Option<PathBuf> {
+        let dir = self.local_music_dir.trim();
+        if dir.is_empty() {
+            None
+        } else {
+            Some(PathBuf::from(dir))
+        }
+    }
+
     pub fn proxy() -> Option<String> {
         env::var(PROXY_ENV_VAR).map_or_else(
             |err| match err {
@@ -169,6 +225,8 @@ pub enum AudioQuality {
     Low,
     Normal,
     High,
+    /// Up to the high quality, depending on the connection.
+    Automatic,
 }
 
 impl AudioQuality {
@@ -176,7 +234,7 @@ impl AudioQuality {
         match self {
             AudioQuality::Low => 96,
             AudioQuality::Normal => 160,
-            AudioQuality::High => 320,
+            AudioQuality::High | AudioQuality::Automatic => 320,
         }
     }
 }
@@ -187,6 +245,40 @@ impl Default for AudioQuality {
     }
 }
 
+#[derive(Clone, Debug, Data, Lens, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EqualizerSettings {
+    pub enabled: bool,
+    /// Preamp, in dB.
+    pub preamp: f64,
+    /// Gains of the bands at `audio_equalizer::BAND_FREQUENCIES`, in dB.
+    pub gains: Vector<f64>,
+}
+
+impl EqualizerSettings {
+    pub fn apply_preset(&mut self, preset: EqualizerPreset) {
+        self.preamp = preset.preamp().into();
+        self.gains = preset.gains().iter().map(|&gain| gain.into()).collect();
+    }
+
+    fn to_config(&self) -> EqualizerConfig {
+        let gains: Vec<f32> = self.gains.iter().map(|&gain| gain as f32).collect();
+        EqualizerConfig::with_gains(self.enabled, self.preamp as f32, &gains)
+    }
+}
+
+impl Default for EqualizerSettings {
+    fn default() -> Self {
+        let mut settings = Self {
+            enabled: false,
+            preamp: 0.0,
+            gains: Vector::new(),
+        };
+        settings.apply_preset(EqualizerPreset::Flat);
+        settings
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Data, Serialize, Deserialize)]
 pub enum Theme {
     Light,
@@ -9,27 +9,31 @@ mod playlist;
 mod promise;
 mod recommend;
 mod search;
+mod show;
 mod track;
 mod user;
 mod utils;
 
-use std::{mem, sync::Arc, time::Duration};
+use std::{mem, ops::Range, sync::Arc, time::Duration};
 
 use druid::{
     im::{HashSet, Vector},
     Data, Lens,
 };
-use psst_core::session::SessionService;
+use psst_core::{audio_queue::QueueTier, item_id::ItemId, session::SessionService};
 
 pub use crate::data::{
-    album::{Album, AlbumDetail, AlbumLink, AlbumType, Copyright, CopyrightType},
+    album::{Album, AlbumDetail, AlbumLink, AlbumType, Copyright, CopyrightType, DatePrecision},
     artist::{Artist, ArtistAlbums, ArtistDetail, ArtistLink, ArtistTracks},
-    config::{AudioQuality, Authentication, Config, Preferences, PreferencesTab, Theme},
+    config::{
+        AudioDevice, AudioQuality, Authentication, Config, EqualizerSettings, Preferences,
+        PreferencesTab, Theme,
+    },
     ctx::Ctx,
     nav::{Nav, SpotifyUrl},
     playback::{
-        NowPlaying, Playback, PlaybackOrigin, PlaybackPayload, PlaybackState, QueueBehavior,
-        QueuedTrack,
+        NowPlaying, Playable, Playback, PlaybackOrigin, PlaybackPayload, PlaybackState,
+        QueueBehavior, QueuedItem, SavedQueue,
     },
     playlist::{Playlist, PlaylistDetail, PlaylistLink, PlaylistTracks},
     promise::{Promise, PromiseState},
@@ -38,6 +42,7 @@ pub use crate::data::{
         RecommendationsRequest, Toggled,
     },
     search::{Search, SearchResults},
+    show::{Episode, EpisodeId, ResumePoint, Show, ShowDetail, ShowEpisodes, ShowLink},
     track::{AudioAnalysis, AudioSegment, TimeInterval, Track, TrackId},
     user::UserProfile,
     utils::{Cached, Float64, Image, Page},
@@ -58,6 +63,7 @@ pub struct AppState {
     pub album_detail: AlbumDetail,
     pub artist_detail: ArtistDetail,
     pub playlist_detail: PlaylistDetail,
+    pub show_detail: ShowDetail,
     pub library: Arc<Library>,
     pub common_ctx: Arc<CommonCtx>,
     pub user_profile: Promise<UserProfile>,
@@ -69,6 +75,7 @@ impl AppState {
         let library = Arc::new(Library {
             saved_albums: Promise::Empty,
             saved_tracks: Promise::Empty,
+            saved_shows: Promise::Empty,
             playlists: Promise::Empty,
         });
         let common_ctx = Arc::new(CommonCtx {
@@ -80,7 +87,9 @@ impl AppState {
             now_playing: None,
             queue_behavior: config.queue_behavior,
             queue: Vector::new(),
+            manual_queue: Vector::new(),
             volume: config.volume,
+            failure: None,
         };
         Self {
             session: SessionService::empty(),
@@ -95,6 +104,7 @@ impl AppState {
                     result: Promise::Empty,
                 },
                 cache_size: Promise::Empty,
+                audio_devices: Promise::Empty,
             },
             playback,
             search: Search {
@@ -118,6 +128,10 @@ impl AppState {
                 playlist: Promise::Empty,
                 tracks: Promise::Empty,
             },
+            show_detail: ShowDetail {
+                show: Promise::Empty,
+                episodes: Promise::Empty,
+            },
             library,
             common_ctx,
             user_profile: Promise::Empty,
@@ -148,36 +162,65 @@ impl AppState {
 }
 
 impl AppState {
-    pub fn queued_track(&self, track_id: &TrackId) -> Option<QueuedTrack> {
+    pub fn queued_item(&self, item_id: ItemId) -> Option<QueuedItem> {
         self.playback
             .queue
             .iter()
-            .find(|queued| queued.track.id.same(track_id))
+            .find(|queued| queued.item.id() == item_id)
             .cloned()
     }
 
-    pub fn loading_playback(&mut self, item: Arc<Track>, origin: PlaybackOrigin) {
+    /// Find the queued item the player has started with.  Manual items are
+    /// consumed by the player once they start, so they are removed from the
+    /// manual queue here as well.
+    pub fn take_queued_item(&mut self, item_id: ItemId, tier: QueueTier) -> Option<QueuedItem> {
+        match tier {
+            QueueTier::Context => self.queued_item(item_id),
+            QueueTier::Manual => {
+                let index = self
+                    .playback
+                    .manual_queue
+                    .iter()
+                    .position(|queued| queued.item.id() == item_id)?;
+                Some(self.playback.manual_queue.remove(index))
+            }
+        }
+    }
+
+    pub fn loading_playback(&mut self, item: Playable, origin: PlaybackOrigin) {
         self.common_ctx_mut().playback_item.take();
         self.playback.state = PlaybackState::Loading;
         self.playback.now_playing.replace(NowPlaying {
             item,
             origin,
             progress: Duration::default(),
+            buffered: Arc::new([]),
+            substitute: Promise::Empty,
             library: Arc::clone(&self.library),
         });
     }
 
-    pub fn start_playback(&mut self, item: Arc<Track>, origin: PlaybackOrigin, progress: Duration) {
+    pub fn start_playback(&mut self, item: Playable, origin: PlaybackOrigin, progress: Duration) {
         self.common_ctx_mut().playback_item.replace(item.clone());
         self.playback.state = PlaybackState::Playing;
         self.playback.now_playing.replace(NowPlaying {
             item,
             origin,
             progress,
+            buffered: Arc::new([]),
+            substitute: Promise::Empty,
             library: Arc::clone(&self.library),
         });
     }
 
+    pub fn buffer_playback(&mut self, item_id: ItemId, ranges: &[Range<Duration>]) {
+        if let Some(now_playing) = &mut self.playback.now_playing {
+            if now_playing.item.id() == item_id {
+                now_playing.buffered = ranges.into();
+            }
+        }
+    }
+
     pub fn progress_playback(&mut self, progress: Duration) {
         if let Some(now_playing) = &mut self.playback.now_playing {
             now_playing.progress = progress;
@@ -193,11 +236,31 @@ impl AppState {
     }
 
     pub fn block_playback(&mut self) {
-        // TODO: Figure out how to signal blocked playback properly.
+        if self.playback.state == PlaybackState::Playing {
+            self.playback.state = PlaybackState::Buffering;
+        }
+    }
+
+    pub fn unblock_playback(&mut self) {
+        if self.playback.state == PlaybackState::Buffering {
+            self.playback.state = PlaybackState::Playing;
+        }
+    }
+
+    pub fn fail_playback(&mut self, item_id: ItemId, error: &str) {
+        let message = match self.common_ctx.playback_item.as_ref() {
+            Some(item) if item.id() == item_id => {
+                format!("Skipped “{}”: {}", item.name(), error)
+            }
+            _ => format!("Skipped an item: {}", error),
+        };
+        self.playback.failure.replace(message.into());
     }
 
     pub fn stop_playback(&mut self) {
         self.playback.state = PlaybackState::Stopped;
+        // Player clears its whole queue when stopping.
+        self.playback.manual_queue.clear();
         self.playback.now_playing.take();
         self.common_ctx_mut().playback_item.take();
     }
@@ -232,6 +295,7 @@ pub struct Library {
     pub playlists: Promise<Vector<Playlist>>,
     pub saved_albums: Promise<SavedAlbums>,
     pub saved_tracks: Promise<SavedTracks>,
+    pub saved_shows: Promise<SavedShows>,
 }
 
 impl Library {
@@ -278,6 +342,28 @@ impl Library {
             false
         }
     }
+
+    pub fn add_show(&mut self, show: Arc<Show>) {
+        if let Some(saved) = self.saved_shows.resolved_mut() {
+            saved.set.insert(show.id.clone());
+            saved.shows.push_front(show);
+        }
+    }
+
+    pub fn remove_show(&mut self, show_id: &Arc<str>) {
+        if let Some(saved) = self.saved_shows.resolved_mut() {
+            saved.set.remove(show_id);
+            saved.shows.retain(|s| &s.id != show_id);
+        }
+    }
+
+    pub fn contains_show(&self, show: &Show) -> bool {
+        if let Some(saved) = self.saved_shows.resolved() {
+            saved.set.contains(&show.id)
+        } else {
+            false
+        }
+    }
 }
 
 #[derive(Clone, Default, Data, Lens)]
@@ -306,17 +392,38 @@ impl SavedAlbums {
     }
 }
 
+#[derive(Clone, Default, Data, Lens)]
+pub struct SavedShows {
+    pub shows: Vector<Arc<Show>>,
+    pub set: HashSet<Arc<str>>,
+}
+
+impl SavedShows {
+    pub fn new(shows: Vector<Arc<Show>>) -> Self {
+        let set = shows.iter().map(|s| s.id.clone()).collect();
+        Self { shows, set }
+    }
+}
+
 #[derive(Clone, Data)]
 pub struct CommonCtx {
-    pub playback_item: Option<Arc<Track>>,
+    pub playback_item: Option<Playable>,
     pub library: Arc<Library>,
 }
 
 impl CommonCtx {
     pub fn is_track_playing(&self, track: &Track) -> bool {
+        self.is_playing(*track.id)
+    }
+
+    pub fn is_episode_playing(&self, episode: &Episode) -> bool {
+        self.is_playing(*episode.id)
+    }
+
+    fn is_playing(&self, item_id: ItemId) -> bool {
         self.playback_item
             .as_ref()
-            .map(|t| t.id.same(&track.id))
+            .map(|item| item.id() == item_id)
             .unwrap_or(false)
     }
 }
@@ -4,7 +4,7 @@ use druid::Data;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::data::{AlbumLink, ArtistLink, PlaylistLink};
+use crate::data::{AlbumLink, ArtistLink, PlaylistLink, ShowLink};
 
 use super::RecommendationsRequest;
 
@@ -13,10 +13,12 @@ pub enum Nav {
     Home,
     SavedTracks,
     SavedAlbums,
+    SavedShows,
     SearchResults(Arc<str>),
     ArtistDetail(ArtistLink),
     AlbumDetail(AlbumLink),
     PlaylistDetail(PlaylistLink),
+    ShowDetail(ShowLink),
     Recommendations(Arc<RecommendationsRequest>),
 }
 
@@ -26,10 +28,12 @@ impl Nav {
             Nav::Home => "Home".to_string(),
             Nav::SavedTracks => "Saved Tracks".to_string(),
             Nav::SavedAlbums => "Saved Albums".to_string(),
+            Nav::SavedShows => "Saved Podcasts".to_string(),
             Nav::SearchResults(query) => query.to_string(),
             Nav::AlbumDetail(link) => link.name.to_string(),
             Nav::ArtistDetail(link) => link.name.to_string(),
             Nav::PlaylistDetail(link) => link.name.to_string(),
+            Nav::ShowDetail(link) => link.name.to_string(),
             Nav::Recommendations(_) => "Recommended".to_string(),
         }
     }
@@ -39,10 +43,12 @@ impl Nav {
             Nav::Home => "Home".to_string(),
             Nav::SavedTracks => "Saved Tracks".to_string(),
             Nav::SavedAlbums => "Saved Albums".to_string(),
+            Nav::SavedShows => "Saved Podcasts".to_string(),
             Nav::SearchResults(query) => format!("Search “{}”", query),
             Nav::AlbumDetail(link) => format!("Album “{}”", link.name),
             Nav::ArtistDetail(link) => format!("Artist “{}”", link.name),
             Nav::PlaylistDetail(link) => format!("Playlist “{}”", link.name),
+            Nav::ShowDetail(link) => format!("Podcast “{}”", link.name),
             Nav::Recommendations(_) => "Recommended".to_string(),
         }
     }
@@ -54,6 +60,8 @@ pub enum SpotifyUrl {
     Artist(Arc<str>),
     Album(Arc<str>),
     Track(Arc<str>),
+    Show(Arc<str>),
+    Episode(Arc<str>),
 }
 
 impl SpotifyUrl {
@@ -67,6 +75,8 @@ impl SpotifyUrl {
             "artist" => Some(Self::Artist(id.into())),
             "album" => Some(Self::Album(id.into())),
             "track" => Some(Self::Track(id.into())),
+            "show" => Some(Self::Show(id.into())),
+            "episode" => Some(Self::Episode(id.into())),
             _ => None,
         }
     }
@@ -77,6 +87,8 @@ impl SpotifyUrl {
             SpotifyUrl::Artist(id) => id.clone(),
             SpotifyUrl::Album(id) => id.clone(),
             SpotifyUrl::Track(id) => id.clone(),
+            SpotifyUrl::Show(id) => id.clone(),
+            SpotifyUrl::Episode(id) => id.clone(),
         }
     }
 }
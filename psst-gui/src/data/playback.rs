@@ -1,25 +1,80 @@
-use std::{fmt, sync::Arc, time::Duration};
+use std::{
+    fmt,
+    fs::{self, File},
+    io,
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use druid::{im::Vector, Data, Lens};
+use psst_core::{cache::mkdir_if_not_exists, item_id::ItemId};
 use serde::{Deserialize, Serialize};
 
-use super::{AlbumLink, ArtistLink, Library, Nav, PlaylistLink, RecommendationsRequest, Track};
+use super::{
+    AlbumLink, ArtistLink, Config, Episode, Library, Nav, PlaylistLink, Promise,
+    RecommendationsRequest, ShowLink, Track, TrackId,
+};
 
 #[derive(Clone, Data, Lens)]
 pub struct Playback {
     pub state: PlaybackState,
     pub now_playing: Option<NowPlaying>,
     pub queue_behavior: QueueBehavior,
-    pub queue: Vector<QueuedTrack>,
+    pub queue: Vector<QueuedItem>,
+    /// Items queued explicitly, played before the rest of `queue`.
+    pub manual_queue: Vector<QueuedItem>,
     pub volume: f64,
+    /// Message about the last item that failed to play, kept until a new queue
+    /// starts playing.
+    pub failure: Option<Arc<str>>,
 }
 
-#[derive(Clone, Debug, Data, Lens)]
-pub struct QueuedTrack {
-    pub track: Arc<Track>,
+#[derive(Clone, Debug, Data, Lens, Serialize, Deserialize)]
+pub struct QueuedItem {
+    pub item: Playable,
     pub origin: PlaybackOrigin,
 }
 
+/// Anything the player can play, either a music track, or a podcast episode.
+#[derive(Clone, Debug, Data, Serialize, Deserialize)]
+pub enum Playable {
+    Track(Arc<Track>),
+    Episode(Arc<Episode>),
+}
+
+impl Playable {
+    pub fn id(&self) -> ItemId {
+        match self {
+            Playable::Track(track) => *track.id,
+            Playable::Episode(episode) => *episode.id,
+        }
+    }
+
+    pub fn name(&self) -> Arc<str> {
+        match self {
+            Playable::Track(track) => track.name.clone(),
+            Playable::Episode(episode) => episode.name.clone(),
+        }
+    }
+
+    /// Artist of a track, or the show of an episode.
+    pub fn subtitle(&self) -> Arc<str> {
+        match self {
+            Playable::Track(track) => track.artist_name(),
+            Playable::Episode(episode) => episode.show_name(),
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            Playable::Track(track) => track.duration,
+            Playable::Episode(episode) => episode.duration,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Data, Eq, PartialEq, Serialize, Deserialize)]
 pub enum QueueBehavior {
     Sequential,
@@ -38,15 +93,22 @@ impl Default for QueueBehavior {
 pub enum PlaybackState {
     Loading,
     Playing,
+    /// Playing, but waiting for the audio data to arrive.
+    Buffering,
     Paused,
     Stopped,
 }
 
 #[derive(Clone, Data, Lens)]
 pub struct NowPlaying {
-    pub item: Arc<Track>,
+    pub item: Playable,
     pub origin: PlaybackOrigin,
     pub progress: Duration,
+    /// Parts of the item that are downloaded, as time ranges.
+    pub buffered: Arc<[Range<Duration>]>,
+    /// Track playing in place of `item`, because `item` is not available in the
+    /// user's region.
+    pub substitute: Promise<Arc<Track>, TrackId>,
 
     // Although keeping a ref to the `Library` here is a bit of a hack, it dramatically
     // simplifies displaying the track context menu in the playback bar.
@@ -55,25 +117,45 @@ pub struct NowPlaying {
 
 impl NowPlaying {
     pub fn cover_image_url(&self, width: f64, height: f64) -> Option<&str> {
-        self.item_album()
-            .and_then(|album| album.image(width, height))
-            .map(|image| image.url.as_ref())
+        let image = match &self.item {
+            Playable::Track(_) => self.item_album()?.image(width, height),
+            Playable::Episode(episode) => episode.image(width, height),
+        };
+        image.map(|image| image.url.as_ref())
+    }
+
+    /// Item to display as playing, that is the substitute track, once its
+    /// information is loaded, or the requested item.
+    pub fn shown_item(&self) -> Playable {
+        match self.substitute.resolved() {
+            Some(track) => Playable::Track(track.clone()),
+            None => self.item.clone(),
+        }
+    }
+
+    pub fn is_substitute(&self) -> bool {
+        self.substitute.deferred().is_some()
     }
 
     pub fn item_album(&self) -> Option<&AlbumLink> {
-        self.item.album.as_ref().or_else(|| match &self.origin {
+        let track = match (self.substitute.resolved(), &self.item) {
+            (Some(track), _) | (None, Playable::Track(track)) => track,
+            (None, Playable::Episode(_)) => return None,
+        };
+        track.album.as_ref().or_else(|| match &self.origin {
             PlaybackOrigin::Album(album) => Some(album),
             _ => None,
         })
     }
 }
 
-#[derive(Clone, Debug, Data)]
+#[derive(Clone, Debug, Data, Serialize, Deserialize)]
 pub enum PlaybackOrigin {
     Library,
     Album(AlbumLink),
     Artist(ArtistLink),
     Playlist(PlaylistLink),
+    Show(ShowLink),
     Search(Arc<str>),
     Recommendations(Arc<RecommendationsRequest>),
 }
@@ -85,6 +167,7 @@ impl PlaybackOrigin {
             PlaybackOrigin::Album(link) => Nav::AlbumDetail(link.clone()),
             PlaybackOrigin::Artist(link) => Nav::ArtistDetail(link.clone()),
             PlaybackOrigin::Playlist(link) => Nav::PlaylistDetail(link.clone()),
+            PlaybackOrigin::Show(link) => Nav::ShowDetail(link.clone()),
             PlaybackOrigin::Search(query) => Nav::SearchResults(query.clone()),
             PlaybackOrigin::Recommendations(request) => Nav::Recommendations(request.clone()),
         }
@@ -98,15 +181,81 @@ impl fmt::Display for PlaybackOrigin {
             PlaybackOrigin::Album(link) => link.name.fmt(f),
             PlaybackOrigin::Artist(link) => link.name.fmt(f),
             PlaybackOrigin::Playlist(link) => link.name.fmt(f),
+            PlaybackOrigin::Show(link) => link.name.fmt(f),
             PlaybackOrigin::Search(query) => query.fmt(f),
             PlaybackOrigin::Recommendations(_) => f.write_str("Recommended"),
         }
     }
 }
 
+const SAVED_QUEUE_FILENAME: &str = "queue.json";
+
+/// Playback queue kept across restarts, restored in a paused state.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedQueue {
+    pub queue: Vector<QueuedItem>,
+    /// Order the items of `queue` are played in, as reported by the player.
+    pub positions: Vec<usize>,
+    /// Index into `positions` of the current item of `queue`.
+    pub position: usize,
+    /// If `is_manual_current` is set, the first item is the one that was playing.
+    pub manual_queue: Vector<QueuedItem>,
+    pub is_manual_current: bool,
+    pub progress: Duration,
+}
+
+impl SavedQueue {
+    fn path() -> Option<PathBuf> {
+        Config::config_dir().map(|dir| dir.join(SAVED_QUEUE_FILENAME))
+    }
+
+    pub fn load() -> Option<Self> {
+        let path = Self::path()?;
+        let file = File::open(&path).ok()?;
+        match serde_json::from_reader(file) {
+            Ok(saved) => Some(saved),
+            Err(err) => {
+                log::error!("failed to read saved queue: {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        if let Err(err) = self.try_save() {
+            log::error!("failed to save queue: {}", err);
+        }
+    }
+
+    /// Writes into a temporary file first and renames it over the saved queue,
+    /// so an interrupted write never leaves a truncated file behind.
+    fn try_save(&self) -> io::Result<()> {
+        let (dir, path) = match (Config::config_dir(), Self::path()) {
+            (Some(dir), Some(path)) => (dir, path),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "config dir not found",
+                ))
+            }
+        };
+        mkdir_if_not_exists(&dir)?;
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.manual_queue.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, Data)]
 pub struct PlaybackPayload {
     pub origin: PlaybackOrigin,
-    pub tracks: Vector<Arc<Track>>,
+    pub items: Vector<Playable>,
     pub position: usize,
 }
@@ -0,0 +1,179 @@
+use std::{convert::TryFrom, ops::Deref, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::NaiveDate;
+use druid::{im::Vector, Data, Lens};
+use psst_core::item_id::{ItemId, ItemIdType};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{Cached, DatePrecision, Image, Promise};
+
+#[derive(Clone, Data, Lens)]
+pub struct ShowDetail {
+    pub show: Promise<Cached<Arc<Show>>, ShowLink>,
+    pub episodes: Promise<ShowEpisodes, ShowLink>,
+}
+
+#[derive(Clone, Data, Lens, Deserialize)]
+pub struct Show {
+    pub id: Arc<str>,
+    pub name: Arc<str>,
+    #[serde(default)]
+    pub images: Vector<Image>,
+    #[serde(default = "super::utils::default_str")]
+    pub publisher: Arc<str>,
+    #[serde(default = "super::utils::default_str")]
+    pub description: Arc<str>,
+}
+
+impl Show {
+    pub fn image(&self, width: f64, height: f64) -> Option<&Image> {
+        Image::at_least_of_size(&self.images, width, height)
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://open.spotify.com/show/{id}", id = self.id)
+    }
+
+    pub fn link(&self) -> ShowLink {
+        ShowLink {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            images: self.images.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Data, Lens, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct ShowLink {
+    pub id: Arc<str>,
+    pub name: Arc<str>,
+    #[serde(default)]
+    pub images: Vector<Image>,
+}
+
+impl ShowLink {
+    pub fn image(&self, width: f64, height: f64) -> Option<&Image> {
+        Image::at_least_of_size(&self.images, width, height)
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://open.spotify.com/show/{id}", id = self.id)
+    }
+}
+
+#[derive(Clone, Data, Lens)]
+pub struct ShowEpisodes {
+    pub show: ShowLink,
+    pub episodes: Vector<Arc<Episode>>,
+}
+
+#[derive(Clone, Debug, Data, Lens, Deserialize, Serialize)]
+pub struct Episode {
+    pub id: EpisodeId,
+    pub name: Arc<str>,
+    #[serde(default = "super::utils::default_str")]
+    pub description: Arc<str>,
+    #[serde(default)]
+    pub images: Vector<Image>,
+    #[serde(rename = "duration_ms")]
+    #[serde(deserialize_with = "super::utils::deserialize_millis")]
+    #[serde(serialize_with = "super::utils::serialize_millis")]
+    pub duration: Duration,
+    #[serde(default)]
+    #[serde(deserialize_with = "super::utils::deserialize_date_option")]
+    #[data(same_fn = "PartialEq::eq")]
+    pub release_date: Option<NaiveDate>,
+    #[data(same_fn = "PartialEq::eq")]
+    pub release_date_precision: Option<DatePrecision>,
+    /// Position the user stopped listening at, only present if the episode has
+    /// been requested with a user token.
+    pub resume_point: Option<ResumePoint>,
+    /// Show the episode belongs to.  Missing in the episode listings of a show,
+    /// we fill it in ourselves there.
+    #[serde(default)]
+    pub show: Option<ShowLink>,
+}
+
+impl Episode {
+    pub fn release(&self) -> String {
+        let format = match self.release_date_precision {
+            Some(DatePrecision::Year) | None => "%Y",
+            Some(DatePrecision::Month) => "%B %Y",
+            Some(DatePrecision::Day) => "%B %d, %Y",
+        };
+        self.release_date
+            .as_ref()
+            .map(|date| date.format(format).to_string())
+            .unwrap_or_else(|| '-'.to_string())
+    }
+
+    pub fn show_name(&self) -> Arc<str> {
+        self.show
+            .as_ref()
+            .map(|show| show.name.clone())
+            .unwrap_or_else(|| "Unknown".into())
+    }
+
+    pub fn image(&self, width: f64, height: f64) -> Option<&Image> {
+        Image::at_least_of_size(&self.images, width, height)
+            .or_else(|| self.show.as_ref()?.image(width, height))
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://open.spotify.com/episode/{}", self.id.to_base62())
+    }
+}
+
+#[derive(Clone, Debug, Data, Lens, Deserialize, Serialize)]
+pub struct ResumePoint {
+    pub fully_played: bool,
+    #[serde(rename = "resume_position_ms")]
+    #[serde(deserialize_with = "super::utils::deserialize_millis")]
+    #[serde(serialize_with = "super::utils::serialize_millis")]
+    pub resume_position: Duration,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String")]
+#[serde(into = "String")]
+pub struct EpisodeId(ItemId);
+
+impl Data for EpisodeId {
+    fn same(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Deref for EpisodeId {
+    type Target = ItemId;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromStr for EpisodeId {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(id) = ItemId::from_base62(s, ItemIdType::Podcast) {
+            Ok(Self(id))
+        } else {
+            Err("Invalid episode ID")
+        }
+    }
+}
+
+impl TryFrom<String> for EpisodeId {
+    type Error = &'static str;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+impl From<EpisodeId> for String {
+    fn from(id: EpisodeId) -> Self {
+        id.0.to_base62()
+    }
+}
@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::data::{AlbumLink, ArtistLink};
 
-#[derive(Clone, Debug, Data, Lens, Deserialize)]
+#[derive(Clone, Debug, Data, Lens, Deserialize, Serialize)]
 pub struct Track {
     #[serde(default)]
     pub id: TrackId,
@@ -15,6 +15,7 @@ pub struct Track {
     pub artists: Vector<ArtistLink>,
     #[serde(rename = "duration_ms")]
     #[serde(deserialize_with = "super::utils::deserialize_millis")]
+    #[serde(serialize_with = "super::utils::serialize_millis")]
     pub duration: Duration,
     pub disc_number: usize,
     pub track_number: usize,
@@ -69,6 +70,9 @@ pub struct TrackId(ItemId);
 
 impl TrackId {
     pub const INVALID: Self = Self(ItemId::new(0u128, ItemIdType::Unknown));
+
+    /// Prefix of the serialized IDs of local files.
+    const LOCAL_PREFIX: &'static str = "local:";
 }
 
 impl Default for TrackId {
@@ -101,7 +105,11 @@ impl FromStr for TrackId {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Some(id) = ItemId::from_base62(s, ItemIdType::Track) {
+        let id = match s.strip_prefix(Self::LOCAL_PREFIX) {
+            Some(local) => ItemId::from_base62(local, ItemIdType::LocalFile),
+            None => ItemId::from_base62(s, ItemIdType::Track),
+        };
+        if let Some(id) = id {
             Ok(Self(id))
         } else {
             Err("Invalid track ID")
@@ -119,7 +127,10 @@ impl TryFrom<String> for TrackId {
 
 impl From<TrackId> for String {
     fn from(id: TrackId) -> Self {
-        id.0.to_base62()
+        match id.0.id_type {
+            ItemIdType::LocalFile => format!("{}{}", TrackId::LOCAL_PREFIX, id.0.to_base62()),
+            _ => id.0.to_base62(),
+        }
     }
 }
 
@@ -41,6 +41,7 @@ fn main() {
         Config::cache_dir(),
     )
     .install_as_global();
+    WebApi::global().set_local_music_dir(state.config.local_music_path());
 
     let delegate;
     let launcher;
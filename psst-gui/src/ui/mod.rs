@@ -24,6 +24,7 @@ pub mod playlist;
 pub mod preferences;
 pub mod recommend;
 pub mod search;
+pub mod show;
 pub mod theme;
 pub mod track;
 pub mod user;
@@ -163,6 +164,9 @@ fn route_widget() -> impl Widget<AppState> {
                     .vertical()
                     .boxed()
             }
+            Nav::SavedShows => Scroll::new(library::saved_shows_widget().padding(theme::grid(1.0)))
+                .vertical()
+                .boxed(),
             Nav::SearchResults(_) => {
                 Scroll::new(search::results_widget().padding(theme::grid(1.0)))
                     .vertical()
@@ -179,6 +183,9 @@ fn route_widget() -> impl Widget<AppState> {
                     .vertical()
                     .boxed()
             }
+            Nav::ShowDetail(_) => Scroll::new(show::detail_widget().padding(theme::grid(1.0)))
+                .vertical()
+                .boxed(),
             Nav::Recommendations(_) => {
                 Scroll::new(recommend::results_widget().padding(theme::grid(1.0)))
                     .vertical()
@@ -204,6 +211,7 @@ fn sidebar_menu_widget() -> impl Widget<AppState> {
         .with_child(sidebar_link_widget("Home", Nav::Home))
         .with_child(sidebar_link_widget("Tracks", Nav::SavedTracks))
         .with_child(sidebar_link_widget("Albums", Nav::SavedAlbums))
+        .with_child(sidebar_link_widget("Podcasts", Nav::SavedShows))
         .with_child(search::input_widget().padding((theme::grid(1.0), theme::grid(1.0))))
 }
 
@@ -324,10 +332,12 @@ fn route_icon_widget() -> impl Widget<Nav> {
                 Nav::Home => Empty.boxed(),
                 Nav::SavedTracks => Empty.boxed(),
                 Nav::SavedAlbums => Empty.boxed(),
+                Nav::SavedShows => Empty.boxed(),
                 Nav::SearchResults(_) => icon(&icons::SEARCH).boxed(),
                 Nav::AlbumDetail(_) => icon(&icons::ALBUM).boxed(),
                 Nav::ArtistDetail(_) => icon(&icons::ARTIST).boxed(),
                 Nav::PlaylistDetail(_) => icon(&icons::PLAYLIST).boxed(),
+                Nav::ShowDetail(_) => icon(&icons::PODCAST).boxed(),
                 Nav::Recommendations(_) => icon(&icons::SEARCH).boxed(),
             }
         },
@@ -342,11 +352,8 @@ fn route_title_widget() -> impl Widget<Nav> {
 
 fn compute_main_window_title(data: &AppState, _env: &Env) -> String {
     if let Some(now_playing) = &data.playback.now_playing {
-        format!(
-            "{} - {}",
-            now_playing.item.artist_name(),
-            now_playing.item.name
-        )
+        let item = now_playing.shown_item();
+        format!("{} - {}", item.subtitle(), item.name())
     } else {
         "Psst".to_owned()
     }
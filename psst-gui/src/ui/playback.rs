@@ -3,8 +3,8 @@ use std::time::Duration;
 use druid::{
     kurbo::{Affine, BezPath},
     widget::{CrossAxisAlignment, Either, Flex, Label, LineBreaking, Spinner, ViewSwitcher},
-    BoxConstraints, Cursor, Data, Env, Event, EventCtx, LayoutCtx, LensExt, LifeCycle,
-    LifeCycleCtx, MouseButton, PaintCtx, Point, Rect, RenderContext, Size, UpdateCtx, Widget,
+    BoxConstraints, Cursor, Data, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx,
+    MouseButton, PaintCtx, Point, Rect, RenderContext, Selector, Size, UpdateCtx, Widget,
     WidgetExt, WidgetPod,
 };
 use itertools::Itertools;
@@ -13,13 +13,14 @@ use crate::{
     cmd,
     controller::PlaybackController,
     data::{
-        AppState, AudioAnalysis, NowPlaying, Playback, PlaybackOrigin, PlaybackState,
-        QueueBehavior, Track,
+        AppState, AudioAnalysis, NowPlaying, Playable, Playback, PlaybackOrigin, PlaybackState,
+        QueueBehavior, TrackId,
     },
+    webapi::WebApi,
     widget::{icons, icons::SvgIcon, Empty, Maybe, MyWidgetExt, RemoteImage},
 };
 
-use super::{theme, track, utils};
+use super::{show, theme, track, utils};
 
 pub fn panel_widget() -> impl Widget<AppState> {
     let seek_bar = Maybe::or_empty(SeekBar::new).lens(Playback::now_playing);
@@ -29,25 +30,65 @@ pub fn panel_widget() -> impl Widget<AppState> {
         player_widget(),
         Empty,
     );
+    let failure = Maybe::or_empty(|| {
+        Label::raw()
+            .with_line_break_mode(LineBreaking::Clip)
+            .with_text_size(theme::TEXT_SIZE_SMALL)
+            .with_text_color(theme::PLACEHOLDER_COLOR)
+            .padding((theme::grid(2.0), theme::grid(1.0)))
+    })
+    .lens(Playback::failure);
     Flex::column()
         .with_child(seek_bar)
         .with_child(BarLayout::new(item_info, controls))
+        .with_child(failure)
         .lens(AppState::playback)
         .controller(PlaybackController::new())
+        .on_command_async(
+            LOAD_SUBSTITUTE,
+            |id| WebApi::global().get_track(&id.to_base62()),
+            |_, data, id| {
+                if let Some(now_playing) = &mut data.playback.now_playing {
+                    now_playing.substitute.defer(id);
+                }
+            },
+            |_, data, result| {
+                if let Some(now_playing) = &mut data.playback.now_playing {
+                    now_playing.substitute.update(result);
+                }
+            },
+        )
 }
 
+pub const LOAD_SUBSTITUTE: Selector<TrackId> = Selector::new("app.playback.load-substitute");
+
 fn playback_item_widget() -> impl Widget<NowPlaying> {
     let cover_art = cover_widget(theme::grid(10.0));
 
-    let track_name = Label::raw()
-        .with_line_break_mode(LineBreaking::Clip)
-        .with_font(theme::UI_FONT_MEDIUM)
-        .lens(NowPlaying::item.then(Track::name.in_arc()));
+    let track_name =
+        Label::dynamic(|now_playing: &NowPlaying, _| now_playing.shown_item().name().to_string())
+            .with_line_break_mode(LineBreaking::Clip)
+            .with_font(theme::UI_FONT_MEDIUM);
 
-    let track_artist = Label::raw()
+    let track_artist = Label::dynamic(|now_playing: &NowPlaying, _| {
+        now_playing.shown_item().subtitle().to_string()
+    })
+    .with_line_break_mode(LineBreaking::Clip)
+    .with_text_size(theme::TEXT_SIZE_SMALL);
+
+    let substitute_hint = Either::new(
+        |now_playing: &NowPlaying, _| now_playing.is_substitute(),
+        Label::dynamic(|now_playing: &NowPlaying, _| {
+            format!(
+                "Substitute for “{}”, unavailable in your region",
+                now_playing.item.name()
+            )
+        })
         .with_line_break_mode(LineBreaking::Clip)
         .with_text_size(theme::TEXT_SIZE_SMALL)
-        .lens(NowPlaying::item.then(Track::lens_artist_name().in_arc()));
+        .with_text_color(theme::PLACEHOLDER_COLOR),
+        Empty,
+    );
 
     let track_origin = ViewSwitcher::new(
         |origin: &PlaybackOrigin, _| origin.clone(),
@@ -76,6 +117,7 @@ fn playback_item_widget() -> impl Widget<NowPlaying> {
                 .with_spacer(2.0)
                 .with_child(track_artist)
                 .with_spacer(2.0)
+                .with_child(substitute_hint)
                 .with_child(track_origin)
                 .padding(theme::grid(2.0)),
             1.0,
@@ -84,7 +126,12 @@ fn playback_item_widget() -> impl Widget<NowPlaying> {
         .on_click(|ctx, now_playing, _| {
             ctx.submit_command(cmd::NAVIGATE.with(now_playing.origin.to_nav()));
         })
-        .context_menu(|now_playing| track::track_menu(&now_playing.item, &now_playing.library))
+        .context_menu(|now_playing| match &now_playing.item {
+            Playable::Track(track) => {
+                track::track_menu(track, &now_playing.library, &now_playing.origin)
+            }
+            Playable::Episode(episode) => show::episode_menu(episode, &now_playing.origin),
+        })
 }
 
 pub fn cover_widget(size: f64) -> impl Widget<NowPlaying> {
@@ -100,6 +147,7 @@ fn playback_origin_icon(origin: &PlaybackOrigin) -> &'static SvgIcon {
         PlaybackOrigin::Album { .. } => &icons::ALBUM,
         PlaybackOrigin::Artist { .. } => &icons::ARTIST,
         PlaybackOrigin::Playlist { .. } => &icons::PLAYLIST,
+        PlaybackOrigin::Show { .. } => &icons::PODCAST,
         PlaybackOrigin::Search { .. } => &icons::SEARCH,
         PlaybackOrigin::Recommendations { .. } => &icons::SEARCH,
     }
@@ -138,6 +186,15 @@ fn player_play_pause_widget() -> impl Widget<Playback> {
                 .border(theme::GREY_600, 1.0)
                 .on_click(|ctx, _, _| ctx.submit_command(cmd::PLAY_STOP))
                 .boxed(),
+            PlaybackState::Buffering => Spinner::new()
+                .with_color(theme::GREY_400)
+                .fix_size(theme::grid(3.0), theme::grid(3.0))
+                .padding(theme::grid(1.0))
+                .link()
+                .circle()
+                .border(theme::GREY_500, 1.0)
+                .on_click(|ctx, _, _| ctx.submit_command(cmd::PLAY_PAUSE))
+                .boxed(),
             PlaybackState::Playing => icons::PAUSE
                 .scale((theme::grid(3.0), theme::grid(3.0)))
                 .padding(theme::grid(1.0))
@@ -213,7 +270,7 @@ fn durations_widget() -> impl Widget<NowPlaying> {
         format!(
             "{} / {}",
             utils::as_minutes_and_seconds(&now_playing.progress),
-            utils::as_minutes_and_seconds(&now_playing.item.duration)
+            utils::as_minutes_and_seconds(&now_playing.shown_item().duration())
         )
     })
     .with_text_size(theme::TEXT_SIZE_SMALL)
@@ -458,7 +515,7 @@ fn paint_audio_analysis(ctx: &mut PaintCtx, data: &NowPlaying, path: &BezPath, e
     let bounds = ctx.size();
 
     let elapsed_time = data.progress.as_secs_f64();
-    let total_time = data.item.duration.as_secs_f64();
+    let total_time = data.shown_item().duration().as_secs_f64();
     let elapsed_frac = elapsed_time / total_time;
     let elapsed_width = bounds.width * elapsed_frac;
     let elapsed = Size::new(elapsed_width, bounds.height).to_rect();
@@ -478,12 +535,20 @@ fn paint_audio_analysis(ctx: &mut PaintCtx, data: &NowPlaying, path: &BezPath, e
 
 fn paint_progress_bar(ctx: &mut PaintCtx, data: &NowPlaying, env: &Env) {
     let elapsed_time = data.progress.as_secs_f64();
-    let total_time = data.item.duration.as_secs_f64();
+    let total_time = data.shown_item().duration().as_secs_f64();
 
-    let (elapsed_color, remaining_color) = if ctx.is_hot() {
-        (env.get(theme::GREY_200), env.get(theme::GREY_500))
+    let (elapsed_color, buffered_color, remaining_color) = if ctx.is_hot() {
+        (
+            env.get(theme::GREY_200),
+            env.get(theme::GREY_400),
+            env.get(theme::GREY_500),
+        )
     } else {
-        (env.get(theme::GREY_300), env.get(theme::GREY_600))
+        (
+            env.get(theme::GREY_300),
+            env.get(theme::GREY_500),
+            env.get(theme::GREY_600),
+        )
     };
     let bounds = ctx.size();
 
@@ -501,4 +566,14 @@ fn paint_progress_bar(ctx: &mut PaintCtx, data: &NowPlaying, env: &Env) {
         &Rect::from_origin_size(Point::new(elapsed.width, 0.0), remaining),
         &remaining_color,
     );
+
+    // Mark the downloaded parts of the remaining time.
+    for range in data.buffered.iter() {
+        let start = bounds.width * range.start.as_secs_f64() / total_time;
+        let end = bounds.width * range.end.as_secs_f64() / total_time;
+        let buffered = Rect::new(start.max(elapsed.width), 0.0, end, bounds.height).round();
+        if buffered.width() > 0.0 {
+            ctx.fill(&buffered, &buffered_color);
+        }
+    }
 }
@@ -2,21 +2,30 @@ use std::thread::{self, JoinHandle};
 
 use druid::{
     commands,
+    im::Vector,
+    lens::Map,
     widget::{
         Button, Controller, CrossAxisAlignment, Flex, Label, LineBreaking, MainAxisAlignment,
-        RadioGroup, TextBox, ViewSwitcher,
+        RadioGroup, Slider, TextBox, ViewSwitcher,
     },
-    Data, Env, Event, EventCtx, LensExt, LifeCycle, LifeCycleCtx, Selector, Widget, WidgetExt,
+    Data, Env, Event, EventCtx, LensExt, LifeCycle, LifeCycleCtx, Selector, Target, Widget,
+    WidgetExt,
+};
+use psst_core::{
+    audio_equalizer::{EqualizerConfig, EqualizerPreset, BAND_FREQUENCIES},
+    audio_player::PlaybackConfig,
+    connection::Credentials,
 };
-use psst_core::connection::Credentials;
 
 use crate::{
     cmd,
     controller::InputController,
     data::{
-        AppState, AudioQuality, Authentication, Config, Preferences, PreferencesTab, Promise, Theme,
+        AppState, AudioDevice, AudioQuality, Authentication, Config, EqualizerSettings,
+        Preferences, PreferencesTab, Promise, Theme,
     },
-    widget::{icons, Async, Border, MyWidgetExt},
+    webapi::WebApi,
+    widget::{icons, Async, Border, Checkbox, MyWidgetExt},
 };
 
 use super::{icons::SvgIcon, theme};
@@ -72,6 +81,9 @@ pub fn preferences_widget() -> impl Widget<AppState> {
             if !old_data.config.same(&data.config) {
                 data.config.save();
             }
+            if old_data.config.local_music_dir != data.config.local_music_dir {
+                WebApi::global().set_local_music_dir(data.config.local_music_path());
+            }
         })
 }
 
@@ -142,13 +154,232 @@ fn general_tab_widget() -> impl Widget<AppState> {
                 ("Low (96kbit)", AudioQuality::Low),
                 ("Normal (160kbit)", AudioQuality::Normal),
                 ("High (320kbit)", AudioQuality::High),
+                ("Automatic", AudioQuality::Automatic),
             ])
             .lens(AppState::config.then(Config::audio_quality)),
         );
 
+    col = col.with_spacer(theme::grid(3.0));
+
+    // Crossfade
+    col = col
+        .with_child(Label::new("Crossfade").with_font(theme::UI_FONT_MEDIUM))
+        .with_spacer(theme::grid(2.0))
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Slider::new().with_range(0.0, PlaybackConfig::MAX_CROSSFADE.as_secs_f64()),
+                )
+                .with_default_spacer()
+                .with_child(Label::dynamic(|&crossfade: &f64, _| {
+                    if crossfade.round() > 0.0 {
+                        format!("{} s", crossfade.round())
+                    } else {
+                        "Off".to_string()
+                    }
+                }))
+                .lens(AppState::config.then(Config::crossfade)),
+        );
+
+    col = col.with_spacer(theme::grid(3.0));
+
+    // Output device
+    col = col
+        .with_child(Label::new("Output device").with_font(theme::UI_FONT_MEDIUM))
+        .with_spacer(theme::grid(2.0))
+        .with_child(audio_device_widget());
+
+    col = col.with_spacer(theme::grid(3.0));
+
+    // Local files
+    col = col
+        .with_child(Label::new("Local files").with_font(theme::UI_FONT_MEDIUM))
+        .with_spacer(theme::grid(2.0))
+        .with_child(
+            TextBox::new()
+                .with_placeholder("Folder with your music files")
+                .controller(InputController::new())
+                .env_scope(|env, _| env.set(theme::WIDE_WIDGET_WIDTH, theme::grid(32.0)))
+                .lens(AppState::config.then(Config::local_music_dir)),
+        )
+        .with_spacer(theme::grid(1.0))
+        .with_child(
+            Label::new("Local tracks of your playlists are played from this folder.")
+                .with_text_size(theme::TEXT_SIZE_SMALL)
+                .with_text_color(theme::PLACEHOLDER_COLOR)
+                .with_line_break_mode(LineBreaking::WordWrap),
+        );
+
+    col = col.with_spacer(theme::grid(3.0));
+
+    // Equalizer
+    col = col
+        .with_child(Label::new("Equalizer").with_font(theme::UI_FONT_MEDIUM))
+        .with_spacer(theme::grid(2.0))
+        .with_child(equalizer_widget().lens(AppState::config.then(Config::equalizer)));
+
     col
 }
 
+fn equalizer_widget() -> impl Widget<EqualizerSettings> {
+    let gain_row = |label: String, slider: Box<dyn Widget<f64>>| {
+        Flex::row()
+            .with_child(
+                Label::new(label)
+                    .with_text_size(theme::TEXT_SIZE_SMALL)
+                    .fix_width(theme::grid(6.0)),
+            )
+            .with_child(slider)
+            .with_default_spacer()
+            .with_child(Label::dynamic(|&gain: &f64, _| format!("{:+.1} dB", gain)))
+    };
+    let gain_slider = || {
+        Slider::new()
+            .with_range(
+                -f64::from(EqualizerConfig::MAX_GAIN),
+                f64::from(EqualizerConfig::MAX_GAIN),
+            )
+            .boxed()
+    };
+
+    let mut presets = Flex::row();
+    for &preset in EqualizerPreset::ALL.iter() {
+        presets = presets
+            .with_child(
+                Button::new(preset.name())
+                    .on_click(move |_, eq: &mut EqualizerSettings, _| eq.apply_preset(preset)),
+            )
+            .with_spacer(theme::grid(1.0));
+    }
+
+    let mut bands = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+    for (i, &frequency) in BAND_FREQUENCIES.iter().enumerate() {
+        let label = if frequency >= 1000.0 {
+            format!("{} kHz", frequency / 1000.0)
+        } else {
+            format!("{} Hz", frequency)
+        };
+        // Tolerate settings with a different count of bands.
+        let gain = Map::new(
+            move |gains: &Vector<f64>| gains.get(i).copied().unwrap_or_default(),
+            move |gains: &mut Vector<f64>, gain| {
+                if let Some(g) = gains.get_mut(i) {
+                    *g = gain;
+                }
+            },
+        );
+        bands = bands.with_child(
+            gain_row(label, gain_slider())
+                .lens(EqualizerSettings::gains.then(gain))
+                .disabled_if(|eq: &EqualizerSettings, _| !eq.enabled),
+        );
+    }
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Checkbox::new("Enable equalizer").lens(EqualizerSettings::enabled))
+        .with_spacer(theme::grid(2.0))
+        .with_child(presets.disabled_if(|eq: &EqualizerSettings, _| !eq.enabled))
+        .with_spacer(theme::grid(2.0))
+        .with_child(
+            gain_row("Preamp".to_string(), gain_slider())
+                .lens(EqualizerSettings::preamp)
+                .disabled_if(|eq: &EqualizerSettings, _| !eq.enabled),
+        )
+        .with_spacer(theme::grid(1.0))
+        .with_child(bands)
+}
+
+fn audio_device_widget() -> impl Widget<AppState> {
+    ViewSwitcher::new(
+        |state: &AppState, _| state.preferences.audio_devices.clone(),
+        |devices, _, _| match devices {
+            Promise::Resolved { val: devices, .. } => {
+                let mut options = vec![("Default".to_string(), None)];
+                options.extend(
+                    devices
+                        .iter()
+                        .map(|device: &AudioDevice| (device.name.clone(), Some(device.id.clone()))),
+                );
+                RadioGroup::new(options)
+                    .lens(AppState::config.then(Config::audio_device))
+                    .boxed()
+            }
+            Promise::Rejected { .. } => Label::new("Failed to list the audio devices.")
+                .with_text_color(theme::PLACEHOLDER_COLOR)
+                .boxed(),
+            Promise::Empty | Promise::Deferred { .. } => Label::new("Loading...")
+                .with_text_color(theme::PLACEHOLDER_COLOR)
+                .boxed(),
+        },
+    )
+    .controller(ListAudioDevices::new())
+}
+
+struct ListAudioDevices {
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ListAudioDevices {
+    fn new() -> Self {
+        Self { thread: None }
+    }
+}
+
+impl ListAudioDevices {
+    const RESULT: Selector<Option<Vector<AudioDevice>>> =
+        Selector::new("app.preferences.list-audio-devices");
+}
+
+impl<W: Widget<AppState>> Controller<AppState, W> for ListAudioDevices {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppState,
+        env: &Env,
+    ) {
+        match &event {
+            Event::Command(cmd) if cmd.is(Self::RESULT) => {
+                let result = cmd.get_unchecked(Self::RESULT).to_owned();
+                data.preferences
+                    .audio_devices
+                    .resolve_or_reject((), result.ok_or(()));
+                self.thread.take();
+                ctx.set_handled();
+            }
+            _ => {
+                child.event(ctx, event, data, env);
+            }
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        child: &mut W,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
+        data: &AppState,
+        env: &Env,
+    ) {
+        if let LifeCycle::WidgetAdded = &event {
+            let handle = thread::spawn({
+                let widget_id = ctx.widget_id();
+                let event_sink = ctx.get_external_handle();
+                move || {
+                    let devices = Preferences::list_audio_devices();
+                    event_sink
+                        .submit_command(Self::RESULT, devices, widget_id)
+                        .unwrap();
+                }
+            });
+            self.thread.replace(handle);
+        }
+        child.lifecycle(ctx, event, data, env);
+    }
+}
+
 #[derive(Copy, Clone)]
 enum AccountTab {
     FirstSetup,
@@ -339,6 +570,25 @@ fn cache_tab_widget() -> impl Widget<AppState> {
             },
         ));
 
+    col = col.with_spacer(theme::grid(3.0));
+
+    col = col
+        .with_child(Label::new("Resume positions").with_font(theme::UI_FONT_MEDIUM))
+        .with_spacer(theme::grid(2.0))
+        .with_child(
+            Label::new(
+                "Psst remembers where you stopped listening to podcast episodes and tracks \
+                 longer than 20 minutes.",
+            )
+            .with_text_color(theme::PLACEHOLDER_COLOR)
+            .with_line_break_mode(LineBreaking::WordWrap),
+        )
+        .with_spacer(theme::grid(1.0))
+        .with_child(Button::new("Clear Resume Positions").on_click(|ctx, _, _| {
+            // Player lives in the main window.
+            ctx.submit_command(cmd::PLAY_CLEAR_RESUME_POSITIONS.to(Target::Global));
+        }));
+
     col.controller(MeasureCacheSize::new())
         .lens(AppState::preferences)
 }
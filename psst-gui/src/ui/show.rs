@@ -0,0 +1,399 @@
+use std::sync::Arc;
+
+use druid::{
+    widget::{
+        Controller, ControllerHost, CrossAxisAlignment, Flex, Label, LineBreaking, List, ListIter,
+    },
+    Data, Env, Event, EventCtx, Lens, LensExt, LocalizedString, Menu, MenuItem, Selector, Size,
+    Widget, WidgetExt,
+};
+
+use crate::{
+    cmd,
+    data::{
+        AppState, Cached, CommonCtx, Ctx, Episode, Library, Nav, Playable, PlaybackOrigin,
+        PlaybackPayload, QueuedItem, Show, ShowDetail, ShowEpisodes, ShowLink, WithCtx,
+    },
+    webapi::WebApi,
+    widget::{Async, MyWidgetExt, RemoteImage},
+};
+
+use super::{
+    library, theme,
+    utils::{self, error_widget, placeholder_widget, spinner_widget},
+};
+
+pub const LOAD_DETAIL: Selector<ShowLink> = Selector::new("app.show.load-detail");
+
+// Episode descriptions can be very long, we only show the beginning of them.
+const DESCRIPTION_MAX_CHARS: usize = 300;
+
+pub fn detail_widget() -> impl Widget<AppState> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(async_info_widget())
+        .with_spacer(theme::grid(1.0))
+        .with_child(async_episodes_widget())
+}
+
+fn async_info_widget() -> impl Widget<AppState> {
+    Async::new(spinner_widget, info_widget, error_widget)
+        .lens(
+            Ctx::make(
+                AppState::common_ctx,
+                AppState::show_detail.then(ShowDetail::show),
+            )
+            .then(Ctx::in_promise()),
+        )
+        .on_command_async(
+            LOAD_DETAIL,
+            |d| WebApi::global().get_show(&d.id),
+            |_, data, d| data.show_detail.show.defer(d),
+            |_, data, r| data.show_detail.show.update(r),
+        )
+}
+
+fn async_episodes_widget() -> impl Widget<AppState> {
+    Async::new(spinner_widget, episodelist_widget, error_widget)
+        .lens(
+            Ctx::make(
+                AppState::common_ctx,
+                AppState::show_detail.then(ShowDetail::episodes),
+            )
+            .then(Ctx::in_promise()),
+        )
+        .on_command_async(
+            LOAD_DETAIL,
+            |d| WebApi::global().get_show_episodes(&d.id),
+            |_, data, d| data.show_detail.episodes.defer(d),
+            |_, data, (d, r)| {
+                let r = r.map(|episodes| ShowEpisodes {
+                    // Listed episodes do not reference their show, but we need it for
+                    // displaying them in the playback bar.
+                    episodes: episodes
+                        .into_iter()
+                        .map(|episode| {
+                            Arc::new(Episode {
+                                show: Some(d.clone()),
+                                ..episode.as_ref().clone()
+                            })
+                        })
+                        .collect(),
+                    show: d.clone(),
+                });
+                data.show_detail.episodes.update((d, r))
+            },
+        )
+}
+
+fn info_widget() -> impl Widget<WithCtx<Cached<Arc<Show>>>> {
+    let show_cover = rounded_cover_widget(theme::grid(10.0));
+
+    let show_publisher = Label::raw()
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .with_font(theme::UI_FONT_MEDIUM)
+        .lens(Show::publisher.in_arc());
+
+    let show_description = Label::raw()
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .with_text_size(theme::TEXT_SIZE_SMALL)
+        .with_text_color(theme::PLACEHOLDER_COLOR)
+        .lens(Show::description.in_arc());
+
+    let show_info = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(show_publisher)
+        .with_default_spacer()
+        .with_child(show_description)
+        .padding(theme::grid(1.0));
+
+    Flex::row()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_spacer(theme::grid(1.0))
+        .with_child(show_cover)
+        .with_default_spacer()
+        .with_flex_child(show_info, 1.0)
+        .padding((0.0, theme::grid(1.0), 0.0, 0.0))
+        .lens(Ctx::data())
+        .context_menu(|show| show_menu(&show.data, &show.ctx.library))
+        .lens(Ctx::map(Cached::data))
+}
+
+fn cover_widget(size: f64) -> impl Widget<Arc<Show>> {
+    RemoteImage::new(placeholder_widget(), move |show: &Arc<Show>, _| {
+        show.image(size, size).map(|image| image.url.clone())
+    })
+    .fix_size(size, size)
+}
+
+fn rounded_cover_widget(size: f64) -> impl Widget<Arc<Show>> {
+    cover_widget(size).clip(Size::new(size, size).to_rounded_rect(4.0))
+}
+
+pub fn show_widget() -> impl Widget<WithCtx<Arc<Show>>> {
+    let show_cover = cover_widget(theme::grid(7.0));
+
+    let show_name = Label::raw()
+        .with_font(theme::UI_FONT_MEDIUM)
+        .with_line_break_mode(LineBreaking::Clip)
+        .lens(Show::name.in_arc());
+
+    let show_publisher = Label::raw()
+        .with_text_size(theme::TEXT_SIZE_SMALL)
+        .with_text_color(theme::PLACEHOLDER_COLOR)
+        .with_line_break_mode(LineBreaking::Clip)
+        .lens(Show::publisher.in_arc());
+
+    let show_info = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(show_name)
+        .with_spacer(1.0)
+        .with_child(show_publisher);
+
+    let show = Flex::row()
+        .with_child(show_cover)
+        .with_default_spacer()
+        .with_flex_child(show_info, 1.0)
+        .lens(Ctx::data());
+
+    show.link()
+        .on_click(|ctx, show, _| {
+            ctx.submit_command(cmd::NAVIGATE.with(Nav::ShowDetail(show.data.link())));
+        })
+        .context_menu(|show| show_menu(&show.data, &show.ctx.library))
+}
+
+fn show_menu(show: &Arc<Show>, library: &Arc<Library>) -> Menu<AppState> {
+    let mut menu = Menu::empty();
+
+    menu = menu.entry(
+        MenuItem::new(
+            LocalizedString::new("menu-item-copy-link").with_placeholder("Copy Link to Podcast"),
+        )
+        .command(cmd::COPY.with(show.url())),
+    );
+
+    menu = menu.separator();
+
+    if library.contains_show(show) {
+        menu = menu.entry(
+            MenuItem::new(
+                LocalizedString::new("menu-item-remove-from-library")
+                    .with_placeholder("Remove Podcast from Library"),
+            )
+            .command(library::UNSAVE_SHOW.with(show.link())),
+        );
+    } else {
+        menu = menu.entry(
+            MenuItem::new(
+                LocalizedString::new("menu-item-save-to-library")
+                    .with_placeholder("Save Podcast to Library"),
+            )
+            .command(library::SAVE_SHOW.with(show.clone())),
+        );
+    }
+
+    menu
+}
+
+fn episodelist_widget() -> impl Widget<WithCtx<ShowEpisodes>> {
+    ControllerHost::new(List::new(episode_widget), PlayController)
+}
+
+impl ListIter<EpisodeRow> for WithCtx<ShowEpisodes> {
+    fn for_each(&self, mut cb: impl FnMut(&EpisodeRow, usize)) {
+        let origin = PlaybackOrigin::Show(self.data.show.clone());
+        ListIter::for_each(&self.data.episodes, |episode, index| {
+            let d = EpisodeRow {
+                ctx: self.ctx.to_owned(),
+                origin: origin.to_owned(),
+                episode: episode.to_owned(),
+                position: index,
+                is_playing: self.ctx.is_episode_playing(episode),
+            };
+            cb(&d, index);
+        });
+    }
+
+    fn for_each_mut(&mut self, mut cb: impl FnMut(&mut EpisodeRow, usize)) {
+        let origin = PlaybackOrigin::Show(self.data.show.clone());
+        ListIter::for_each(&self.data.episodes, |episode, index| {
+            let mut d = EpisodeRow {
+                ctx: self.ctx.to_owned(),
+                origin: origin.to_owned(),
+                episode: episode.to_owned(),
+                position: index,
+                is_playing: self.ctx.is_episode_playing(episode),
+            };
+            cb(&mut d, index);
+
+            // Mutation intentionally ignored.
+        });
+    }
+
+    fn data_len(&self) -> usize {
+        self.data.episodes.len()
+    }
+}
+
+#[derive(Clone, Data, Lens)]
+struct EpisodeRow {
+    ctx: Arc<CommonCtx>,
+    episode: Arc<Episode>,
+    origin: PlaybackOrigin,
+    position: usize,
+    is_playing: bool,
+}
+
+struct PlayController;
+
+impl<W> Controller<WithCtx<ShowEpisodes>, W> for PlayController
+where
+    W: Widget<WithCtx<ShowEpisodes>>,
+{
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut WithCtx<ShowEpisodes>,
+        env: &Env,
+    ) {
+        match event {
+            Event::Notification(note) => {
+                if let Some(position) = note.get(cmd::PLAY_TRACK_AT) {
+                    let payload = PlaybackPayload {
+                        origin: PlaybackOrigin::Show(data.data.show.clone()),
+                        items: data
+                            .data
+                            .episodes
+                            .iter()
+                            .cloned()
+                            .map(Playable::Episode)
+                            .collect(),
+                        position: position.to_owned(),
+                    };
+                    ctx.submit_command(cmd::PLAY_TRACKS.with(payload));
+                    ctx.set_handled();
+                }
+            }
+            _ => child.event(ctx, event, data, env),
+        }
+    }
+}
+
+fn episode_widget() -> impl Widget<EpisodeRow> {
+    let episode_name = Label::raw()
+        .with_font(theme::UI_FONT_MEDIUM)
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .lens(EpisodeRow::episode.then(Episode::name.in_arc()));
+
+    let episode_duration = Label::<Arc<Episode>>::dynamic(|episode, _| {
+        utils::as_minutes_and_seconds(&episode.duration)
+    })
+    .with_text_size(theme::TEXT_SIZE_SMALL)
+    .with_text_color(theme::PLACEHOLDER_COLOR)
+    .lens(EpisodeRow::episode);
+
+    let episode_release = Label::<Arc<Episode>>::dynamic(|episode, _| episode.release())
+        .with_text_size(theme::TEXT_SIZE_SMALL)
+        .lens(EpisodeRow::episode);
+
+    let episode_progress = Label::<Arc<Episode>>::dynamic(|episode, _| resume_point_text(episode))
+        .with_text_size(theme::TEXT_SIZE_SMALL)
+        .with_text_color(theme::PLACEHOLDER_COLOR)
+        .lens(EpisodeRow::episode);
+
+    let episode_description =
+        Label::<Arc<Episode>>::dynamic(|episode, _| description_excerpt(&episode.description))
+            .with_line_break_mode(LineBreaking::WordWrap)
+            .with_text_size(theme::TEXT_SIZE_SMALL)
+            .with_text_color(theme::PLACEHOLDER_COLOR)
+            .lens(EpisodeRow::episode);
+
+    let major = Flex::row()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_flex_child(episode_name, 1.0)
+        .with_default_spacer()
+        .with_child(episode_duration);
+
+    let minor = Flex::row()
+        .with_child(episode_release)
+        .with_default_spacer()
+        .with_child(episode_progress);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(major)
+        .with_spacer(2.0)
+        .with_child(minor)
+        .with_spacer(2.0)
+        .with_child(episode_description)
+        .padding(theme::grid(1.0))
+        .link()
+        .active(|row, _| row.is_playing)
+        .rounded(theme::BUTTON_BORDER_RADIUS)
+        .on_click(|ctx, row, _| {
+            ctx.submit_notification(cmd::PLAY_TRACK_AT.with(row.position));
+        })
+        .context_menu(|row| episode_menu(&row.episode, &row.origin))
+}
+
+fn resume_point_text(episode: &Episode) -> String {
+    match &episode.resume_point {
+        Some(point) if point.fully_played => "Played".to_string(),
+        Some(point) if !point.resume_position.is_zero() => {
+            let remaining = episode.duration.saturating_sub(point.resume_position);
+            format!("{} left", utils::as_minutes_and_seconds(&remaining))
+        }
+        _ => String::new(),
+    }
+}
+
+fn description_excerpt(description: &str) -> String {
+    let mut chars = description.chars();
+    let mut excerpt: String = chars.by_ref().take(DESCRIPTION_MAX_CHARS).collect();
+    if chars.next().is_some() {
+        excerpt.push('…');
+    }
+    excerpt
+}
+
+pub fn episode_menu(episode: &Arc<Episode>, origin: &PlaybackOrigin) -> Menu<AppState> {
+    let mut menu = Menu::empty();
+
+    let queued = QueuedItem {
+        item: Playable::Episode(episode.to_owned()),
+        origin: origin.to_owned(),
+    };
+    menu = menu.entry(
+        MenuItem::new(LocalizedString::new("menu-item-play-next").with_placeholder("Play Next"))
+            .command(cmd::QUEUE_INSERT_NEXT.with(queued.clone())),
+    );
+    menu = menu.entry(
+        MenuItem::new(
+            LocalizedString::new("menu-item-add-to-queue").with_placeholder("Add to Queue"),
+        )
+        .command(cmd::QUEUE_APPEND.with(queued)),
+    );
+
+    menu = menu.separator();
+
+    if let Some(show_link) = episode.show.as_ref() {
+        menu = menu.entry(
+            MenuItem::new(
+                LocalizedString::new("menu-item-show-podcast").with_placeholder("Go To Podcast"),
+            )
+            .command(cmd::NAVIGATE.with(Nav::ShowDetail(show_link.to_owned()))),
+        );
+    }
+
+    menu = menu.entry(
+        MenuItem::new(
+            LocalizedString::new("menu-item-copy-link").with_placeholder("Copy Link to Episode"),
+        )
+        .command(cmd::COPY.with(episode.url())),
+    );
+
+    menu
+}
@@ -14,9 +14,9 @@ use druid::{
 use crate::{
     cmd,
     data::{
-        Album, AppState, ArtistLink, ArtistTracks, CommonCtx, Library, Nav, PlaybackOrigin,
-        PlaybackPayload, PlaylistTracks, Recommendations, RecommendationsRequest, SavedTracks,
-        SearchResults, Track, WithCtx,
+        Album, AppState, ArtistLink, ArtistTracks, CommonCtx, Library, Nav, Playable,
+        PlaybackOrigin, PlaybackPayload, PlaylistTracks, QueuedItem, Recommendations,
+        RecommendationsRequest, SavedTracks, SearchResults, Track, WithCtx,
     },
     widget::MyWidgetExt,
 };
@@ -186,7 +186,13 @@ where
                 if let Some(position) = note.get(cmd::PLAY_TRACK_AT) {
                     let payload = PlaybackPayload {
                         origin: data.data.origin(),
-                        tracks: data.data.tracks().to_owned(),
+                        items: data
+                            .data
+                            .tracks()
+                            .iter()
+                            .cloned()
+                            .map(Playable::Track)
+                            .collect(),
                         position: position.to_owned(),
                     };
                     ctx.submit_command(cmd::PLAY_TRACKS.with(payload));
@@ -316,12 +322,38 @@ fn popularity_stars(popularity: u32) -> String {
 }
 
 fn track_row_menu(row: &TrackRow) -> Menu<AppState> {
-    track_menu(&row.track, &row.ctx.library)
+    track_menu(&row.track, &row.ctx.library, &row.origin)
 }
 
-pub fn track_menu(track: &Arc<Track>, library: &Arc<Library>) -> Menu<AppState> {
+pub fn track_menu(
+    track: &Arc<Track>,
+    library: &Arc<Library>,
+    origin: &PlaybackOrigin,
+) -> Menu<AppState> {
     let mut menu = Menu::empty();
 
+    let queued = QueuedItem {
+        item: Playable::Track(track.to_owned()),
+        origin: origin.to_owned(),
+    };
+    menu = menu.entry(
+        MenuItem::new(LocalizedString::new("menu-item-play-next").with_placeholder("Play Next"))
+            .command(cmd::QUEUE_INSERT_NEXT.with(queued.clone())),
+    );
+    menu = menu.entry(
+        MenuItem::new(
+            LocalizedString::new("menu-item-add-to-queue").with_placeholder("Add to Queue"),
+        )
+        .command(cmd::QUEUE_APPEND.with(queued)),
+    );
+
+    if track.is_local {
+        // Local files do not have any Spotify pages or links.
+        return menu;
+    }
+
+    menu = menu.separator();
+
     for artist_link in &track.artists {
         let more_than_one_artist = track.artists.len() > 1;
         let title = if more_than_one_artist {
@@ -1,7 +1,8 @@
 use crate::{
     data::{
-        Album, AlbumType, Artist, ArtistAlbums, AudioAnalysis, Cached, Nav, Page, Playlist, Range,
-        Recommendations, RecommendationsRequest, SearchResults, SpotifyUrl, Track, UserProfile,
+        Album, AlbumLink, AlbumType, Artist, ArtistAlbums, ArtistLink, AudioAnalysis, Cached,
+        Episode, Nav, Page, Playlist, Range, Recommendations, RecommendationsRequest,
+        SearchResults, Show, SpotifyUrl, Track, TrackId, UserProfile,
     },
     error::Error,
 };
@@ -13,14 +14,15 @@ use druid::{
 use itertools::Itertools;
 use once_cell::sync::OnceCell;
 use psst_core::{
-    access_token::TokenProvider, session::SessionService, util::default_ureq_agent_builder,
+    access_token::TokenProvider, local_library::LocalLibrary, session::SessionService,
+    util::default_ureq_agent_builder,
 };
 use serde::{de::DeserializeOwned, Deserialize};
 use std::{
     fmt::Display,
     io::{self, Read},
     path::PathBuf,
-    sync::Arc,
+    sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
@@ -33,6 +35,13 @@ pub struct WebApi {
     agent: Agent,
     cache: WebApiCache,
     token_provider: TokenProvider,
+    local_files: Mutex<LocalFiles>,
+}
+
+#[derive(Default)]
+struct LocalFiles {
+    dir: Option<PathBuf>,
+    library: Option<Arc<LocalLibrary>>,
 }
 
 impl WebApi {
@@ -47,7 +56,35 @@ impl WebApi {
             agent,
             cache: WebApiCache::new(cache_base),
             token_provider: TokenProvider::new(),
+            local_files: Mutex::default(),
+        }
+    }
+
+    /// Set the folder local tracks of playlists are matched against.  The
+    /// folder is scanned lazily, the first time a playlist is loaded.
+    pub fn set_local_music_dir(&self, dir: Option<PathBuf>) {
+        let mut local_files = self.local_files.lock().unwrap();
+        if local_files.dir != dir {
+            *local_files = LocalFiles { dir, library: None };
+        }
+    }
+
+    fn local_library(&self) -> Option<Arc<LocalLibrary>> {
+        let mut local_files = self.local_files.lock().unwrap();
+        if local_files.library.is_none() {
+            let dir = local_files.dir.clone()?;
+            match LocalLibrary::scan(dir) {
+                Ok(library) => {
+                    local_files.library.replace(Arc::new(library));
+                }
+                Err(err) => {
+                    log::error!("failed to scan local files: {}", err);
+                    // Do not try again until the folder changes.
+                    local_files.dir = None;
+                }
+            }
         }
+        local_files.library.clone()
     }
 
     fn access_token(&self) -> Result<String, Error> {
@@ -280,6 +317,37 @@ impl WebApi {
     }
 }
 
+/// Show endpoints.
+impl WebApi {
+    // https://developer.spotify.com/documentation/web-api/reference/#endpoint-get-a-show
+    pub fn get_show(&self, id: &str) -> Result<Cached<Arc<Show>>, Error> {
+        let request = self
+            .get(format!("v1/shows/{}", id))?
+            .query("market", "from_token");
+        let result = self.load_cached(request, "show", id)?;
+        Ok(result)
+    }
+
+    // https://developer.spotify.com/documentation/web-api/reference/#endpoint-get-a-shows-episodes
+    pub fn get_show_episodes(&self, id: &str) -> Result<Vector<Arc<Episode>>, Error> {
+        let request = self
+            .get(format!("v1/shows/{}/episodes", id))?
+            .query("market", "from_token");
+        // Episodes not available in the user's market come back as `null`.
+        let result: Vector<Option<Arc<Episode>>> = self.load_all_pages(request)?;
+        Ok(result.into_iter().flatten().collect())
+    }
+
+    // https://developer.spotify.com/documentation/web-api/reference/#endpoint-get-an-episode
+    pub fn get_episode(&self, id: &str) -> Result<Arc<Episode>, Error> {
+        let request = self
+            .get(format!("v1/episodes/{}", id))?
+            .query("market", "from_token");
+        let result = self.load(request)?;
+        Ok(result)
+    }
+}
+
 /// Library endpoints.
 impl WebApi {
     // https://developer.spotify.com/documentation/web-api/reference/library/get-users-saved-albums/
@@ -341,6 +409,36 @@ impl WebApi {
         self.send_empty_json(request)?;
         Ok(())
     }
+
+    // https://developer.spotify.com/documentation/web-api/reference/#endpoint-get-users-saved-shows
+    pub fn get_saved_shows(&self) -> Result<Vector<Arc<Show>>, Error> {
+        #[derive(Clone, Deserialize)]
+        struct SavedShow {
+            show: Arc<Show>,
+        }
+
+        let request = self.get("v1/me/shows")?;
+
+        Ok(self
+            .load_all_pages(request)?
+            .into_iter()
+            .map(|item: SavedShow| item.show)
+            .collect())
+    }
+
+    // https://developer.spotify.com/documentation/web-api/reference/#endpoint-save-shows-user
+    pub fn save_show(&self, id: &str) -> Result<(), Error> {
+        let request = self.put("v1/me/shows")?.query("ids", id);
+        self.send_empty_json(request)?;
+        Ok(())
+    }
+
+    // https://developer.spotify.com/documentation/web-api/reference/#endpoint-remove-shows-user
+    pub fn unsave_show(&self, id: &str) -> Result<(), Error> {
+        let request = self.delete("v1/me/shows")?.query("ids", id);
+        self.send_empty_json(request)?;
+        Ok(())
+    }
 }
 
 /// View endpoints.
@@ -392,25 +490,98 @@ impl WebApi {
         #[serde(untagged)]
         enum OptionalTrack {
             Track(Arc<Track>),
+            Local(LocalTrackEntry),
             Json(serde_json::Value),
         }
 
+        // Metadata of a local track, we try to find a matching file in the local
+        // library.
+        #[derive(Clone, Deserialize)]
+        struct LocalTrackEntry {
+            name: Arc<str>,
+            #[serde(default)]
+            artists: Vec<LocalName>,
+            album: Option<LocalName>,
+            #[serde(default)]
+            duration_ms: u64,
+        }
+
+        #[derive(Clone, Deserialize)]
+        struct LocalName {
+            name: Arc<str>,
+        }
+
         let request = self
             .get(format!("v1/playlists/{}/tracks", id))?
             .query("marker", "from_token")
             .query("additional_types", "track");
         let result: Vector<PlaylistItem> = self.load_all_pages(request)?;
 
-        Ok(result
+        let library = if result.iter().any(|item| item.is_local) {
+            self.local_library()
+        } else {
+            None
+        };
+        let find_local = |entry: &LocalTrackEntry| {
+            let library = library.as_ref()?;
+            let artist = entry.artists.first().map_or("", |artist| &artist.name);
+            let album = entry.album.as_ref().map_or("", |album| &album.name);
+            let local = library.find(artist, album, &entry.name)?;
+            Some(Arc::new(Track {
+                id: TrackId::from(local.item_id),
+                name: entry.name.clone(),
+                album: entry.album.as_ref().map(|album| AlbumLink {
+                    id: "".into(),
+                    name: album.name.clone(),
+                    images: Vector::new(),
+                }),
+                artists: entry
+                    .artists
+                    .iter()
+                    .map(|artist| ArtistLink {
+                        id: "".into(),
+                        name: artist.name.clone(),
+                    })
+                    .collect(),
+                duration: if entry.duration_ms > 0 {
+                    Duration::from_millis(entry.duration_ms)
+                } else {
+                    local.duration
+                },
+                disc_number: 0,
+                track_number: 0,
+                explicit: false,
+                is_local: true,
+                is_playable: Some(true),
+                popularity: None,
+            }))
+        };
+
+        let mut unmatched = 0;
+        let tracks = result
             .into_iter()
             .filter_map(|item| match item {
                 PlaylistItem {
                     is_local: false,
                     track: OptionalTrack::Track(track),
                 } => Some(track),
+                PlaylistItem {
+                    is_local: true,
+                    track: OptionalTrack::Local(entry),
+                } => {
+                    let track = find_local(&entry);
+                    if track.is_none() {
+                        unmatched += 1;
+                    }
+                    track
+                }
                 _ => None,
             })
-            .collect())
+            .collect();
+        if unmatched > 0 {
+            log::info!("{} local tracks not found in the local files", unmatched);
+        }
+        Ok(tracks)
     }
 }
 
@@ -457,6 +628,12 @@ impl WebApi {
                     Error::WebApiError("Track was found but has no album".to_string())
                 })?,
             ),
+            SpotifyUrl::Show(id) => Nav::ShowDetail(self.get_show(id)?.data.link()),
+            SpotifyUrl::Episode(id) => {
+                Nav::ShowDetail(self.get_episode(id)?.show.clone().ok_or_else(|| {
+                    Error::WebApiError("Episode was found but has no show".to_string())
+                })?)
+            }
         };
         Ok(nav)
     }
@@ -118,6 +118,13 @@ pub static PLAYLIST: SvgIcon = SvgIcon {
     op: PaintOp::Fill,
 };
 
+// Microphone in a circle, outline taken from `ALBUM`.
+pub static PODCAST: SvgIcon = SvgIcon {
+    svg_path: "M10.9912 19.7422C15.9746 19.7422 20.0879 15.6289 20.0879 10.6543C20.0879 5.67969 15.9658 1.56641 10.9824 1.56641C6.00781 1.56641 1.90332 5.67969 1.90332 10.6543C1.90332 15.6289 6.0166 19.7422 10.9912 19.7422ZM10.9912 17.9316C6.95703 17.9316 3.73145 14.6885 3.73145 10.6543C3.73145 6.62012 6.95703 3.38574 10.9824 3.38574C15.0166 3.38574 18.2598 6.62012 18.2686 10.6543C18.2773 14.6885 15.0254 17.9316 10.9912 17.9316ZM11 11.6C11.8837 11.6 12.6 10.8837 12.6 10V7.2C12.6 6.31634 11.8837 5.6 11 5.6C10.1163 5.6 9.4 6.31634 9.4 7.2V10C9.4 10.8837 10.1163 11.6 11 11.6ZM10.45 12.4H11.55V15H10.45ZM8.8 15H13.2V16H8.8Z",
+    svg_size: Size::new(22.0, 22.0),
+    op: PaintOp::Fill,
+};
+
 #[derive(Copy, Clone)]
 pub enum PaintOp {
     Fill,